@@ -1,4 +1,7 @@
-use core::mem::{transmute_copy, MaybeUninit};
+use core::{
+    fmt::Write,
+    mem::{transmute_copy, MaybeUninit},
+};
 
 use cortex_m::prelude::_embedded_hal_adc_OneShot;
 use embedded_hal_0_2::{adc::Channel, blocking::delay::DelayUs, digital::v2::OutputPin as _};
@@ -6,7 +9,11 @@ use rp_pico::hal::{
     gpio::{DynPinId, FunctionSioOutput, Pin, PullDown},
     Adc,
 };
-use rustkbd::{keyboard::KeySwitches, Vec};
+use rustkbd::{
+    console::{Command, ConsoleHandler},
+    keyboard::KeySwitches,
+    Vec,
+};
 
 use crate::{filter::Filter, switch_identifier::KeySwitchIdentifier};
 
@@ -26,6 +33,8 @@ pub struct KeyMatrix<
     adc_pin: P,
     delay: D,
     filters: [[Filter; COLS]; ROWS],
+    /// 最後に読んだADCの値
+    raw_values: [[u16; COLS]; ROWS],
     /// for debug
     counter: u16,
 }
@@ -71,6 +80,7 @@ impl<
             adc_pin,
             delay,
             filters: unsafe { transmute_copy::<_, [[Filter; COLS]; ROWS]>(&filters) },
+            raw_values: [[0; COLS]; ROWS],
             counter: 0,
         }
     }
@@ -118,6 +128,7 @@ impl<
                 self.delay.delay_us(10);
 
                 let val: u16 = self.adc.read(&mut self.adc_pin).unwrap_or(0);
+                self.raw_values[row][col] = val;
                 self.delay.delay_us(10);
                 // if col == 0 && row == 0 {
                 //     defmt::debug!("{}", val);
@@ -143,3 +154,29 @@ impl<
         keys
     }
 }
+
+impl<
+        D: DelayUs<u16>,
+        P: Channel<Adc, ID = u8>,
+        const ROWS: usize,
+        const CSELS: usize,
+        const COLS: usize,
+    > ConsoleHandler for KeyMatrix<D, P, ROWS, CSELS, COLS>
+{
+    fn handle(&mut self, command: &Command<'_>, out: &mut dyn Write) -> bool {
+        if command.matches("ec raw").is_none() {
+            return false;
+        }
+        for row in self.raw_values.iter() {
+            for val in row.iter() {
+                write!(out, "{:5}", val).ok();
+            }
+            writeln!(out).ok();
+        }
+        true
+    }
+
+    fn help(&self, out: &mut dyn Write) {
+        writeln!(out, "ec raw  ADC values of each key").ok();
+    }
+}
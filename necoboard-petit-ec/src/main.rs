@@ -24,7 +24,7 @@ use embedded_hal::spi::MODE_0;
 use fugit::RateExtU32;
 use heapless::String;
 use key_matrix::KeyMatrix;
-use layout::{Layer, Layout};
+use layout::{Layer, Layout, LAYOUT};
 use panic_probe as _;
 use rp_pico::{
     hal::{
@@ -38,19 +38,24 @@ use rp_pico::{
     pac::{self, interrupt},
 };
 use rustkbd::{
-    keyboard::{Controller, KeyboardState},
-    usb::{DeviceInfo, UsbCommunicator},
+    console::{self, ConsoleHandler},
+    keyboard::{Controller, KeyboardState, Layer as _},
+    rp2040::{self, Flash},
+    storage::Storage,
+    usb::{DeviceInfo, RawHidDispatcher, UsbCommunicator, Via},
 };
 use ssd1306::{
     mode::DisplayConfig, prelude::SPIInterface, rotation::DisplayRotation, size::DisplaySize128x64,
     Ssd1306,
 };
+use system_commands::SystemCommands;
 use usb_device::class_prelude::UsbBusAllocator;
 
 mod filter;
 mod key_matrix;
 mod layout;
 mod switch_identifier;
+mod system_commands;
 mod uart_connection;
 
 type KeyboardType = Controller<
@@ -62,6 +67,8 @@ type KeyboardType = Controller<
 >;
 static mut KEYBOARD: Mutex<RefCell<Option<KeyboardType>>> = Mutex::new(RefCell::new(None));
 
+const CONSOLE_OUTPUT_LEN: usize = 512;
+
 #[entry]
 fn main() -> ! {
     // These variables must be static due to lifetime constraints
@@ -154,11 +161,21 @@ fn main() -> ! {
         serial_number: "17",
     };
 
-    let keyboard = Controller::new(
-        UsbCommunicator::new(device_info, USB_BUS.as_ref().unwrap()),
+    let mut storage = Storage::new(Flash, rp2040::STORAGE_OFFSET, rp2040::STORAGE_SECTORS)
+        .inspect_err(|e| defmt::warn!("StorageError: {}", e))
+        .ok();
+    let mut keyboard = Controller::new(
+        UsbCommunicator::with_console(device_info, USB_BUS.as_ref().unwrap()),
         key_matrix,
-        Layout::default(),
+        LAYOUT,
     );
+    if let Some(Err(e)) = storage.as_mut().map(|storage| {
+        keyboard
+            .load_settings(storage)
+            .and_then(|()| keyboard.load_keymap(storage))
+    }) {
+        defmt::warn!("StorageError: {}", e);
+    }
     cortex_m::interrupt::free(|cs| unsafe {
         KEYBOARD.borrow(cs).replace(Some(keyboard));
     });
@@ -187,6 +204,13 @@ fn main() -> ! {
             }
             draw_state(&mut display, keyboard.get_state());
             display.flush().ok();
+            if let Some(Err(e)) = storage.as_mut().map(|storage| {
+                keyboard
+                    .save_settings(storage)
+                    .and_then(|()| keyboard.save_keymap(storage))
+            }) {
+                defmt::warn!("StorageError: {}", e);
+            }
         });
     }
 }
@@ -195,11 +219,34 @@ fn main() -> ! {
 #[interrupt]
 fn USBCTRL_IRQ() {
     cortex_m::interrupt::free(|cs| unsafe {
-        KEYBOARD
-            .borrow(cs)
-            .borrow_mut()
-            .as_mut()
-            .map(|keyboard| keyboard.communicator.poll())
+        if let Some(Err(e)) = KEYBOARD.borrow(cs).borrow_mut().as_mut().map(|keyboard| {
+            keyboard.communicator.poll();
+            let mut via = Via::new(&mut keyboard.layout);
+            let mut dispatcher = RawHidDispatcher::<1>::new();
+            dispatcher.register(&mut via).ok();
+            let result = keyboard.communicator.handle_raw_hid(&mut dispatcher);
+            drop(dispatcher);
+            if via.edited() {
+                keyboard.keymap_edited();
+            }
+            result?;
+            let Some(line) = keyboard.communicator.read_console_line() else {
+                return Ok(());
+            };
+            let mut out = String::<CONSOLE_OUTPUT_LEN>::new();
+            console::execute(
+                &line,
+                &mut [
+                    &mut *keyboard as &mut dyn ConsoleHandler,
+                    &mut SystemCommands,
+                ],
+                &mut out,
+            );
+            out.push_str("> ").ok();
+            keyboard.communicator.write_console(&out)
+        }) {
+            defmt::warn!("UsbError: {}", defmt::Debug2Format(&e));
+        }
     });
 }
 
@@ -216,7 +263,7 @@ fn draw_state<const RO: usize>(
         .keys
         .into_iter()
         .filter(|key| key.is_keyboard_key())
-        .map(From::from)
+        .map(|key| key.to_char(state.host_layout))
         .for_each(|c| {
             string.push(c).ok();
         });
@@ -225,12 +272,7 @@ fn draw_state<const RO: usize>(
         .ok();
 
     // display Layer
-    let layer = match state.layer {
-        Layer::Default => "Default",
-        Layer::Lower => "Lower",
-        Layer::Raise => "Raise",
-    };
-    Text::new(layer, Point::new(0, 34), char_style)
+    Text::new(state.layer.name(), Point::new(0, 34), char_style)
         .draw(display)
         .ok();
 }
@@ -0,0 +1,25 @@
+use core::fmt::Write;
+
+use rp_pico::hal::rom_data;
+use rustkbd::console::{Command, ConsoleHandler};
+
+/// 再起動とBOOTSELモードへの切り替え
+pub struct SystemCommands;
+
+impl ConsoleHandler for SystemCommands {
+    fn handle(&mut self, command: &Command<'_>, _out: &mut dyn Write) -> bool {
+        if command.matches("reset").is_some() {
+            cortex_m::peripheral::SCB::sys_reset();
+        } else if command.matches("bootloader").is_some() {
+            rom_data::reset_to_usb_boot(0, 0);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn help(&self, out: &mut dyn Write) {
+        writeln!(out, "reset       restart the keyboard").ok();
+        writeln!(out, "bootloader  restart into the USB bootloader").ok();
+    }
+}
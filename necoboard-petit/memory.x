@@ -1,6 +1,7 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* 末尾の16Kは設定の保存に使う */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 16K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
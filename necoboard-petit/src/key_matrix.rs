@@ -3,31 +3,20 @@ use embedded_hal_0_2::{
     digital::v2::{InputPin as _, OutputPin as _},
 };
 use rp_pico::hal::gpio::{DynPinId, FunctionSioInput, FunctionSioOutput, Pin, PullDown};
-use rustkbd::{keyboard, Vec};
+use rustkbd::{console::ConsoleHandler, keyboard, Vec};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, keyboard::KeySwitchIdentifier)]
 pub struct KeySwitchIdentifier {
     pub row: u8,
     pub col: u8,
 }
 
-impl From<[u8; 2]> for KeySwitchIdentifier {
-    fn from(value: [u8; 2]) -> Self {
-        KeySwitchIdentifier {
-            row: value[0],
-            col: value[1],
-        }
+impl keyboard::MatrixPosition for KeySwitchIdentifier {
+    fn position(&self, _rows: usize, _cols: usize) -> (usize, usize) {
+        (self.row as usize, self.col as usize)
     }
 }
 
-impl From<KeySwitchIdentifier> for [u8; 2] {
-    fn from(value: KeySwitchIdentifier) -> Self {
-        [value.row, value.col]
-    }
-}
-
-impl keyboard::KeySwitchIdentifier<2> for KeySwitchIdentifier {}
-
 pub struct KeyMatrix<D: DelayUs<u16>, const ROWS: usize, const COLS: usize> {
     inputs: [Pin<DynPinId, FunctionSioInput, PullDown>; ROWS],
     outputs: [Pin<DynPinId, FunctionSioOutput, PullDown>; COLS],
@@ -75,3 +64,8 @@ impl<D: DelayUs<u16>, const ROWS: usize, const COLS: usize> keyboard::KeySwitche
         keys
     }
 }
+
+impl<D: DelayUs<u16>, const ROWS: usize, const COLS: usize> ConsoleHandler
+    for KeyMatrix<D, ROWS, COLS>
+{
+}
@@ -3,6 +3,7 @@
 
 use core::{
     cell::RefCell,
+    fmt::Write,
     sync::atomic::{AtomicUsize, Ordering},
 };
 
@@ -19,7 +20,6 @@ use embedded_graphics::{
     text::Text,
     Drawable,
 };
-use embedded_hal::digital::InputPin;
 use fugit::{ExtU64, HertzU32, MicrosDurationU32, RateExtU32};
 use hal::{
     gpio::{PullDown, PullUp},
@@ -49,20 +49,25 @@ use rp_pico::{
     pac::{self, interrupt, UART0},
 };
 use rustkbd::{
-    keyboard::{Controller, KeyboardState},
-    split::{SplitKeySwitches, SplitState},
-    usb::{DeviceInfo, UsbCommunicator},
+    console::{self, ConsoleHandler},
+    keyboard::{Controller, HostLeds, KeyboardState, Layer as _},
+    rp2040::{self, Flash},
+    split::{Handedness, SplitKeySwitches, SplitState},
+    storage::Storage,
+    usb::{DeviceInfo, RawHidDispatcher, UsbCommunicator, Via},
 };
-use split_layout::{Layer, SplitLayout};
+use split_layout::{Layer, SplitLayout, SPLIT_LAYOUT};
 use ssd1306::{
     mode::DisplayConfig, rotation::DisplayRotation, size::DisplaySize128x32, I2CDisplayInterface,
     Ssd1306,
 };
+use system_commands::SystemCommands;
 use uart_connection::UartConnection;
 use usb_device::class_prelude::UsbBusAllocator;
 
 mod key_matrix;
 mod split_layout;
+mod system_commands;
 mod uart_connection;
 
 type KeyboardType = Controller<
@@ -91,6 +96,13 @@ static mut CORE1_STACK: Stack<4096> = Stack::new();
 
 const USB_SEND_INTERVAL: MicrosDurationU32 = MicrosDurationU32::micros(10_000);
 const SWITCH_SCAN_INTERVAL: MicrosDurationU32 = MicrosDurationU32::micros(1_000);
+const CONSOLE_OUTPUT_LEN: usize = 512;
+/// 左右が保存されていないときに使う。右手側は`--features right`で書き込むか、`side right`で保存する
+const DEFAULT_HANDEDNESS: Handedness = if cfg!(feature = "right") {
+    Handedness::Right
+} else {
+    Handedness::Left
+};
 
 #[entry]
 fn main() -> ! {
@@ -140,6 +152,11 @@ fn main() -> ! {
         ALARM1.borrow(cs).replace(Some(alarm1));
     });
 
+    // core1を動かす前に読んでおく。保存されたものがなければここで初期化される
+    let mut storage = Storage::new(Flash, rp2040::STORAGE_OFFSET, rp2040::STORAGE_SECTORS)
+        .inspect_err(|e| defmt::warn!("StorageError: {}", e))
+        .ok();
+
     let mut mc = Multicore::new(&mut pac.PSM, &mut pac.PPB, &mut sio.fifo);
     let cores = mc.cores();
     let core1 = &mut cores[1];
@@ -183,7 +200,7 @@ fn main() -> ! {
         .into_buffered_graphics_mode();
     display.init().ok();
 
-    let key_switches = SplitKeySwitches::new(
+    let mut key_switches = SplitKeySwitches::new(
         KeyMatrix::new(
             [
                 pins.gpio16.into_pull_down_input().into_dyn_pin(),
@@ -198,9 +215,9 @@ fn main() -> ! {
         connection,
         TIMER.as_ref().unwrap().count_down(),
         10u64.millis(),
-        pins.gpio22.into_pull_up_input().is_low().unwrap(),
+        load_handedness(storage.as_mut()),
     );
-    let layout = SplitLayout::default();
+    key_switches.set_clock(now_us);
     let device_info = DeviceInfo {
         manufacturer: "necocen",
         vendor_id: 0x0c0d,
@@ -208,8 +225,16 @@ fn main() -> ! {
         product_name: "necoboard petit",
         serial_number: "17",
     };
-    let usb_communicator = UsbCommunicator::new(device_info, USB_BUS.as_ref().unwrap());
-    let keyboard = Controller::new(usb_communicator, key_switches, layout);
+    let usb_communicator = UsbCommunicator::with_console(device_info, USB_BUS.as_ref().unwrap());
+    let mut keyboard = Controller::new(usb_communicator, key_switches, SPLIT_LAYOUT);
+    keyboard.set_clock(now_us);
+    if let Some(Err(e)) = storage.as_mut().map(|storage| {
+        keyboard
+            .load_settings(storage)
+            .and_then(|()| keyboard.load_keymap(storage))
+    }) {
+        defmt::warn!("StorageError: {}", e);
+    }
     cortex_m::interrupt::free(|cs| unsafe {
         KEYBOARD.borrow(cs).replace(Some(keyboard));
     });
@@ -232,6 +257,7 @@ fn main() -> ! {
 
     core1
         .spawn(unsafe { &mut CORE1_STACK.mem }, move || loop {
+            rp2040::park_core1();
             let (state, split_state) = {
                 let _lock = Spinlock0::claim();
                 cortex_m::interrupt::free(|cs| unsafe {
@@ -247,6 +273,54 @@ fn main() -> ! {
 
     loop {
         cortex_m::asm::wfi();
+        if let Some(storage) = storage.as_mut() {
+            save_settings(storage);
+        }
+    }
+}
+
+/// 保存された左右。読めなければ`DEFAULT_HANDEDNESS`
+fn load_handedness(storage: Option<&mut Storage<Flash>>) -> Handedness {
+    match storage.map(|storage| storage.load::<Handedness>()) {
+        Some(Ok(Some(handedness))) => handedness,
+        Some(Err(e)) => {
+            defmt::warn!("StorageError: {}", e);
+            DEFAULT_HANDEDNESS
+        }
+        _ => DEFAULT_HANDEDNESS,
+    }
+}
+
+/// 実行中に切り替えられた設定があれば保存する
+fn save_settings(storage: &mut Storage<Flash>) {
+    if let Some(side) = system_commands::take_side() {
+        if let Err(e) = rp2040::with_core1_paused(|| storage.save(&side)) {
+            defmt::warn!("StorageError: {}", e);
+        }
+    }
+    let unsaved = cortex_m::interrupt::free(|cs| unsafe {
+        let _lock = Spinlock0::claim();
+        KEYBOARD
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .is_some_and(|keyboard| keyboard.has_unsaved_settings())
+    });
+    if !unsaved {
+        return;
+    }
+    let result = rp2040::with_core1_paused(|| {
+        cortex_m::interrupt::free(|cs| unsafe {
+            let _lock = Spinlock0::claim();
+            KEYBOARD.borrow(cs).borrow_mut().as_mut().map(|keyboard| {
+                keyboard
+                    .save_settings(storage)
+                    .and_then(|()| keyboard.save_keymap(storage))
+            })
+        })
+    });
+    if let Some(Err(e)) = result {
+        defmt::warn!("StorageError: {}", e);
     }
 }
 
@@ -264,7 +338,7 @@ fn draw_state<const RO: usize>(
         .keys
         .into_iter()
         .filter(|key| key.is_keyboard_key())
-        .map(From::from)
+        .map(|key| key.to_char(state.host_layout))
         .for_each(|c| {
             string.push(c).ok();
         });
@@ -272,25 +346,57 @@ fn draw_state<const RO: usize>(
         .draw(display)
         .ok();
 
-    // display "Receiver" or "Controller"
-    let split = match split_state {
-        SplitState::Undetermined => "Undetermined",
-        SplitState::Controller => "Controller",
-        SplitState::Receiver => "Receiver",
+    // display "Receiver" or "Controller" with the round-trip latency
+    let mut split = String::<21>::new();
+    let link = match split_state {
+        SplitState::Undetermined => {
+            split.push_str("Undetermined").ok();
+            None
+        }
+        SplitState::NotAvailable => {
+            split.push_str("Not available").ok();
+            None
+        }
+        SplitState::Controller(link) => {
+            split.push_str("Controller").ok();
+            Some(link)
+        }
+        SplitState::Receiver(link) => {
+            split.push_str("Receiver").ok();
+            Some(link)
+        }
+        SplitState::Incompatible(_) => {
+            split.push_str("Incompatible").ok();
+            None
+        }
     };
-    Text::new(split, Point::new(0, 20), char_style)
+    if let Some(latency) = link.and_then(|link| link.latency_us) {
+        write!(split, " {}us", latency).ok();
+    }
+    Text::new(split.as_str(), Point::new(0, 20), char_style)
         .draw(display)
         .ok();
 
     // display Layer
-    let layer = match state.layer {
-        Layer::Default => "Default",
-        Layer::Lower => "Lower",
-        Layer::Raise => "Raise",
-    };
-    Text::new(layer, Point::new(0, 30), char_style)
+    Text::new(state.layer.name(), Point::new(0, 30), char_style)
         .draw(display)
         .ok();
+
+    // display Caps Lock and WPM on the right. Receiver shows the ones sent from Controller
+    let mut status = String::<12>::new();
+    if state.host_leds.contains(HostLeds::CAPS_LOCK) {
+        status.push_str("CAPS ").ok();
+    }
+    write!(status, "{}wpm", state.wpm).ok();
+    let x = 128 - 6 * status.len() as i32;
+    Text::new(status.as_str(), Point::new(x, 30), char_style)
+        .draw(display)
+        .ok();
+}
+
+/// 起動してからの時間(µs)の下位32ビット
+fn now_us() -> u32 {
+    unsafe { (*pac::TIMER::ptr()).timerawl().read().bits() }
 }
 
 #[allow(non_snake_case)]
@@ -298,11 +404,34 @@ fn draw_state<const RO: usize>(
 fn USBCTRL_IRQ() {
     cortex_m::interrupt::free(|cs| unsafe {
         let _lock = Spinlock0::claim();
-        KEYBOARD
-            .borrow(cs)
-            .borrow_mut()
-            .as_mut()
-            .map(|keyboard| keyboard.communicator.poll())
+        if let Some(Err(e)) = KEYBOARD.borrow(cs).borrow_mut().as_mut().map(|keyboard| {
+            keyboard.communicator.poll();
+            let mut via = Via::new(&mut keyboard.layout);
+            let mut dispatcher = RawHidDispatcher::<1>::new();
+            dispatcher.register(&mut via).ok();
+            let result = keyboard.communicator.handle_raw_hid(&mut dispatcher);
+            drop(dispatcher);
+            if via.edited() {
+                keyboard.keymap_edited();
+            }
+            result?;
+            let Some(line) = keyboard.communicator.read_console_line() else {
+                return Ok(());
+            };
+            let mut out = String::<CONSOLE_OUTPUT_LEN>::new();
+            console::execute(
+                &line,
+                &mut [
+                    &mut *keyboard as &mut dyn ConsoleHandler,
+                    &mut SystemCommands,
+                ],
+                &mut out,
+            );
+            out.push_str("> ").ok();
+            keyboard.communicator.write_console(&out)
+        }) {
+            defmt::warn!("UsbError: {}", defmt::Debug2Format(&e));
+        }
     });
 }
 
@@ -332,8 +461,8 @@ fn TIMER_IRQ_0() {
         alarm.enable_interrupt();
         if let Some(Err(e)) = KEYBOARD
             .borrow(cs)
-            .borrow()
-            .as_ref()
+            .borrow_mut()
+            .as_mut()
             .map(Controller::send_keys)
         {
             defmt::warn!("UsbError: {}", defmt::Debug2Format(&e));
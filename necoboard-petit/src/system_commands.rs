@@ -0,0 +1,50 @@
+use core::{cell::Cell, fmt::Write};
+
+use cortex_m::interrupt::Mutex;
+use rp_pico::hal::rom_data;
+use rustkbd::{
+    console::{Command, ConsoleHandler},
+    split::Handedness,
+};
+
+/// `side`で選ばれて、まだ保存していない左右
+static SIDE: Mutex<Cell<Option<Handedness>>> = Mutex::new(Cell::new(None));
+
+/// 再起動とBOOTSELモードへの切り替え、左右の設定
+pub struct SystemCommands;
+
+/// `side`で選ばれた左右があれば取り出す。保存してから再起動すると使われる
+pub fn take_side() -> Option<Handedness> {
+    cortex_m::interrupt::free(|cs| SIDE.borrow(cs).take())
+}
+
+impl ConsoleHandler for SystemCommands {
+    fn handle(&mut self, command: &Command<'_>, out: &mut dyn Write) -> bool {
+        if command.matches("reset").is_some() {
+            cortex_m::peripheral::SCB::sys_reset();
+        } else if command.matches("bootloader").is_some() {
+            rom_data::reset_to_usb_boot(0, 0);
+            true
+        } else if let Some(mut args) = command.matches("side") {
+            let side = match args.next() {
+                Some("left") => Handedness::Left,
+                Some("right") => Handedness::Right,
+                _ => {
+                    writeln!(out, "usage: side left|right").ok();
+                    return true;
+                }
+            };
+            cortex_m::interrupt::free(|cs| SIDE.borrow(cs).set(Some(side)));
+            writeln!(out, "side: {side:?} (reset to apply)").ok();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn help(&self, out: &mut dyn Write) {
+        writeln!(out, "reset       restart the keyboard").ok();
+        writeln!(out, "bootloader  restart into the USB bootloader").ok();
+        writeln!(out, "side        save which half this is (left|right)").ok();
+    }
+}
@@ -18,7 +18,7 @@ use rp2040_hal::{
     spi::Enabled,
     Spi,
 };
-use rustkbd::keyboard::KeyboardState;
+use rustkbd::keyboard::{KeyboardState, Layer as _};
 use ssd1306::{
     mode::BufferedGraphicsMode,
     prelude::{DisplayConfig, SPIInterface},
@@ -46,7 +46,7 @@ pub fn draw_state<const RO: usize>(
         .keys
         .into_iter()
         .filter(|key| key.is_keyboard_key())
-        .map(From::from)
+        .map(|key| key.to_char(state.host_layout))
         .for_each(|c| {
             string.push(c).ok();
         });
@@ -55,12 +55,7 @@ pub fn draw_state<const RO: usize>(
         .ok();
 
     // display Layer
-    let layer = match state.layer {
-        Layer::Default => "Default",
-        Layer::Lower => "Lower",
-        Layer::Raise => "Raise",
-    };
-    Text::new(layer, Point::new(0, 49), char_style)
+    Text::new(state.layer.name(), Point::new(0, 49), char_style)
         .draw(display)
         .ok();
 }
@@ -10,7 +10,7 @@ use defmt_rtt as _;
 use fugit::{ExtU32, MicrosDurationU32};
 use hal::{entry, Clock as _, Timer};
 use key_matrix::KeyMatrix;
-use layout::Layout;
+use layout::{Layout, LAYOUT};
 use panic_probe as _;
 use rp2040_hal::{
     self as hal,
@@ -25,7 +25,9 @@ use rp2040_hal::{
 };
 use rustkbd::{
     keyboard::Controller,
-    usb::{DeviceInfo, UsbCommunicator},
+    rp2040::{self, Flash},
+    storage::Storage,
+    usb::{DeviceInfo, RawHidDispatcher, UsbCommunicator, Via},
 };
 use usb_device::class_prelude::UsbBusAllocator;
 
@@ -112,6 +114,11 @@ fn main() -> ! {
     ));
     *USB_BUS = Some(usb_bus);
 
+    // core1を動かす前に読んでおく。保存されたものがなければここで初期化される
+    let mut storage = Storage::new(Flash, rp2040::STORAGE_OFFSET, rp2040::STORAGE_SECTORS)
+        .inspect_err(|e| defmt::warn!("StorageError: {}", e))
+        .ok();
+
     let mut mc = Multicore::new(&mut pac.PSM, &mut pac.PPB, &mut sio.fifo);
     let cores = mc.cores();
     let core1 = &mut cores[1];
@@ -155,11 +162,18 @@ fn main() -> ! {
         serial_number: "17",
     };
 
-    let keyboard = Controller::new(
+    let mut keyboard = Controller::new(
         UsbCommunicator::new(device_info, USB_BUS.as_ref().unwrap()),
         key_matrix,
-        Layout::default(),
+        LAYOUT,
     );
+    if let Some(Err(e)) = storage.as_mut().map(|storage| {
+        keyboard
+            .load_settings(storage)
+            .and_then(|()| keyboard.load_keymap(storage))
+    }) {
+        defmt::warn!("StorageError: {}", e);
+    }
     cortex_m::interrupt::free(|cs| unsafe {
         KEYBOARD.borrow(cs).replace(Some(keyboard));
     });
@@ -182,6 +196,7 @@ fn main() -> ! {
 
     core1
         .spawn(unsafe { &mut CORE1_STACK.mem }, move || loop {
+            rp2040::park_core1();
             let state = cortex_m::interrupt::free(|cs| unsafe {
                 let _lock = Spinlock0::claim();
                 KEYBOARD.borrow(cs).borrow().as_ref().unwrap().get_state()
@@ -195,28 +210,60 @@ fn main() -> ! {
     watchdog.start(1.secs());
 
     loop {
-        cortex_m::interrupt::free(|cs| unsafe {
+        let unsaved = cortex_m::interrupt::free(|cs| unsafe {
             let _lock = Spinlock0::claim();
             KEYBOARD
                 .borrow(cs)
                 .borrow_mut()
                 .as_mut()
-                .map(Controller::main_loop);
+                .is_some_and(|keyboard| {
+                    keyboard.main_loop();
+                    keyboard.has_unsaved_settings()
+                })
         });
+        if let Some(storage) = storage.as_mut().filter(|_| unsaved) {
+            save_settings(storage);
+        }
         watchdog.feed();
     }
 }
 
+/// 実行中に切り替えられた設定を保存する
+fn save_settings(storage: &mut Storage<Flash>) {
+    let result = rp2040::with_core1_paused(|| {
+        cortex_m::interrupt::free(|cs| unsafe {
+            let _lock = Spinlock0::claim();
+            KEYBOARD.borrow(cs).borrow_mut().as_mut().map(|keyboard| {
+                keyboard
+                    .save_settings(storage)
+                    .and_then(|()| keyboard.save_keymap(storage))
+            })
+        })
+    });
+    if let Some(Err(e)) = result {
+        defmt::warn!("StorageError: {}", e);
+    }
+}
+
 #[allow(non_snake_case)]
 #[interrupt]
 fn USBCTRL_IRQ() {
     cortex_m::interrupt::free(|cs| unsafe {
         let _lock = Spinlock0::claim();
-        KEYBOARD
-            .borrow(cs)
-            .borrow_mut()
-            .as_mut()
-            .map(|keyboard| keyboard.communicator.poll())
+        if let Some(Err(e)) = KEYBOARD.borrow(cs).borrow_mut().as_mut().map(|keyboard| {
+            keyboard.communicator.poll();
+            let mut via = Via::new(&mut keyboard.layout);
+            let mut dispatcher = RawHidDispatcher::<1>::new();
+            dispatcher.register(&mut via).ok();
+            let result = keyboard.communicator.handle_raw_hid(&mut dispatcher);
+            drop(dispatcher);
+            if via.edited() {
+                keyboard.keymap_edited();
+            }
+            result
+        }) {
+            defmt::warn!("UsbError: {}", defmt::Debug2Format(&e));
+        }
     });
 }
 
@@ -232,8 +279,8 @@ fn TIMER_IRQ_0() {
         alarm.enable_interrupt();
         if let Some(Err(e)) = KEYBOARD
             .borrow(cs)
-            .borrow()
-            .as_ref()
+            .borrow_mut()
+            .as_mut()
             .map(Controller::send_keys)
         {
             defmt::warn!("UsbError: {}", defmt::Debug2Format(&e));
@@ -0,0 +1,261 @@
+use serde::Deserialize;
+use toml::Spanned;
+
+/// `keymap.toml`の中身
+///
+/// ```toml
+/// [keymap]
+/// layout = "Layout"
+/// layer = "Layer"
+/// identifier = "crate::switch_identifier::KeySwitchIdentifier"
+/// size = 2
+///
+/// [[hold]]
+/// switch = [3, 7]
+/// layer = "Lower"
+///
+/// [[layer]]
+/// name = "Default"
+/// keys = '''
+/// | Esc |  Q  | ...
+/// '''
+/// ```
+///
+/// 分割キーボードでは`keys`の代わりに`left`と`right`を書き、`hold`には`side`を書ける。書かなければ左右両方になる。
+/// レイヤに`below = "Default"`と書くと、透過キーで落ちる先を変えられる。書かなければ1つ前のレイヤに落ちる。
+/// コンボにはまだ対応していないので、`[[combo]]`を書くとエラーになる。
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeymapFile {
+    pub keymap: Header,
+    #[serde(default)]
+    pub hold: Vec<HoldEntry>,
+    #[serde(default)]
+    pub layer: Vec<LayerEntry>,
+    #[serde(default, alias = "combos")]
+    combo: Option<Spanned<toml::Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Header {
+    pub layout: Spanned<String>,
+    pub layer: Spanned<String>,
+    pub identifier: Spanned<String>,
+    pub size: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HoldEntry {
+    pub switch: (usize, usize),
+    pub layer: Spanned<String>,
+    pub side: Option<Side>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerEntry {
+    pub name: Spanned<String>,
+    pub below: Option<Spanned<String>>,
+    pub keys: Option<Spanned<String>>,
+    pub left: Option<Spanned<String>>,
+    pub right: Option<Spanned<String>>,
+}
+
+/// `keymap.toml`の誤り。`offset`はファイルの中のバイト位置
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileError {
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+impl KeymapFile {
+    pub fn parse(content: &str) -> Result<KeymapFile, FileError> {
+        let file: KeymapFile = toml::from_str(content).map_err(|e| FileError {
+            offset: e.span().map(|span| span.start),
+            message: e.message().to_string(),
+        })?;
+        if let Some(combo) = &file.combo {
+            return Err(FileError {
+                offset: Some(combo.span().start),
+                message: "combos are not supported yet".to_string(),
+            });
+        }
+        Ok(file)
+    }
+}
+
+impl LayerEntry {
+    /// `keys`の表か、`left`と`right`の表
+    pub fn tables(&self) -> Result<Vec<&Spanned<String>>, FileError> {
+        match (&self.keys, &self.left, &self.right) {
+            (Some(keys), None, None) => Ok(vec![keys]),
+            (None, Some(left), Some(right)) => Ok(vec![left, right]),
+            _ => Err(FileError {
+                offset: Some(self.name.span().start),
+                message: format!(
+                    "layer `{}` needs either `keys` or both `left` and `right`",
+                    self.name.get_ref()
+                ),
+            }),
+        }
+    }
+}
+
+/// ファイルの中の位置（1始まりの行と列）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// `content`の`offset`バイト目の位置
+    pub fn new(content: &str, offset: usize) -> Position {
+        let before = &content[..offset];
+        Position {
+            line: before.matches('\n').count() + 1,
+            column: before[before.rfind('\n').map_or(0, |i| i + 1)..]
+                .chars()
+                .count()
+                + 1,
+        }
+    }
+}
+
+/// 表の文字列の中身がファイルのどこから始まるか
+#[derive(Debug, Clone, Copy)]
+pub struct Origin {
+    start: Position,
+    /// エスケープを含まず、中身がファイルにそのまま書かれているか
+    verbatim: bool,
+}
+
+impl Origin {
+    /// TOMLの文字列から作る。複数行の文字列は最初の改行を読み飛ばす
+    pub fn new(content: &str, string: &Spanned<String>) -> Origin {
+        let raw = &content[string.span()];
+        let quote = if raw.starts_with("'''") || raw.starts_with("\"\"\"") {
+            3
+        } else {
+            1
+        };
+        let mut start = string.span().start + quote;
+        if quote == 3 {
+            if let Some(newline) = ["\n", "\r\n"]
+                .into_iter()
+                .find(|newline| content[start..].starts_with(newline))
+            {
+                start += newline.len();
+            }
+        }
+        Origin {
+            start: Position::new(content, start),
+            verbatim: content[start..].starts_with(string.get_ref().as_str()),
+        }
+    }
+
+    /// 表の`offset`バイト目のファイルでの位置。エスケープを含むときは中身の始まりにする
+    pub fn position(&self, value: &str, offset: usize) -> Position {
+        let before = &value[..offset];
+        match before.rfind('\n') {
+            _ if !self.verbatim => self.start,
+            None => Position {
+                line: self.start.line,
+                column: self.start.column + before.chars().count(),
+            },
+            Some(i) => Position {
+                line: self.start.line + before.matches('\n').count(),
+                column: before[(i + 1)..].chars().count() + 1,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = r#"[keymap]
+layout = "Layout"
+layer = "Layer"
+identifier = "Id"
+size = 2
+"#;
+
+    #[test]
+    // 表の中の位置をファイルの行と列にできる
+    fn test_origin() {
+        let content = format!(
+            r#"{HEADER}
+[[layer]]
+name = "Default"
+keys = '''
+| A |
+| B |
+'''
+
+[[layer]]
+name = "Lower"
+left = "|\\|"
+right = '| C |'
+"#
+        );
+        let file = KeymapFile::parse(&content).unwrap();
+        let keys = file.layer[0].tables().unwrap()[0];
+        let origin = Origin::new(&content, keys);
+        assert_eq!(
+            Position {
+                line: 10,
+                column: 1
+            },
+            origin.position(keys.get_ref(), 0)
+        );
+        assert_eq!(
+            Position {
+                line: 11,
+                column: 3
+            },
+            origin.position(keys.get_ref(), 8)
+        );
+
+        // エスケープを含むときは中身の始まり
+        let tables = file.layer[1].tables().unwrap();
+        let left = Origin::new(&content, tables[0]);
+        assert_eq!(
+            Position {
+                line: 16,
+                column: 9
+            },
+            left.position(tables[0].get_ref(), 2)
+        );
+        let right = Origin::new(&content, tables[1]);
+        assert_eq!(
+            Position {
+                line: 17,
+                column: 12
+            },
+            right.position(tables[1].get_ref(), 2)
+        );
+    }
+
+    #[test]
+    // コンボはまだないので、書かれていたらエラーにする
+    fn test_combo() {
+        let content = format!("{HEADER}\n[[combo]]\nkeys = [\"J\", \"K\"]\n");
+        let error = KeymapFile::parse(&content).unwrap_err();
+        assert_eq!("combos are not supported yet", error.message);
+        assert_eq!(
+            Some(Position { line: 7, column: 1 }),
+            error.offset.map(|offset| Position::new(&content, offset))
+        );
+    }
+}
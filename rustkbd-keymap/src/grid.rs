@@ -0,0 +1,157 @@
+/// 表の1つのセル。`offset`は表の文字列の中でのバイト位置
+pub struct Cell<'a> {
+    pub text: &'a str,
+    pub offset: usize,
+}
+
+/// 表の誤り。`offset`から`len`バイトが誤っているところ
+#[derive(Debug, PartialEq, Eq)]
+pub struct GridError {
+    pub offset: usize,
+    pub len: usize,
+    pub message: String,
+}
+
+/// `|`で区切られた表をセルに分ける。空行は読み飛ばし、誤りはまとめて返す
+///
+/// 列の数が揃っていて、`|`の位置が1行目と揃っていなければならない。
+/// `shape`を渡すと、行数と列数もそれと比べる。
+pub fn split_grid(
+    value: &str,
+    shape: Option<(usize, usize)>,
+) -> (Vec<Vec<Cell<'_>>>, Vec<GridError>) {
+    let mut errors = Vec::new();
+    let mut grid = Vec::new();
+    // 行の位置と中身
+    let mut lines = Vec::new();
+    // 1行目の`|`の位置（文字数）
+    let mut first_pipes: Option<Vec<usize>> = None;
+
+    let mut line_offset = 0;
+    for line in value.split('\n') {
+        let start = line_offset + line.len() - line.trim_start().len();
+        line_offset += line.len() + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let row = grid.len() + 1;
+        lines.push((start, line));
+        if !line.starts_with('|') || !line.ends_with('|') || line.len() < 2 {
+            errors.push(GridError {
+                offset: start,
+                len: line.len(),
+                message: format!("row {}: rows must start and end with `|`: `{}`", row, line),
+            });
+            grid.push(Vec::new());
+            continue;
+        }
+
+        let pipes = line.match_indices('|').map(|(i, _)| i).collect::<Vec<_>>();
+        let columns = pipes
+            .iter()
+            .map(|i| line[..*i].chars().count())
+            .collect::<Vec<_>>();
+        match &first_pipes {
+            None => first_pipes = Some(columns),
+            Some(first) if first.len() == columns.len() => {
+                if let Some(i) = (0..columns.len()).find(|i| first[*i] != columns[*i]) {
+                    errors.push(GridError {
+                        offset: start + pipes[i],
+                        len: 1,
+                        message: format!(
+                            "row {}: `|` before column {} is not aligned with the first row: `{}`",
+                            row,
+                            i + 1,
+                            line
+                        ),
+                    });
+                }
+            }
+            Some(_) => {}
+        }
+
+        let cells = pipes
+            .windows(2)
+            .map(|window| {
+                let raw = &line[(window[0] + 1)..window[1]];
+                Cell {
+                    text: raw.trim(),
+                    offset: start + window[0] + 1 + raw.len() - raw.trim_start().len(),
+                }
+            })
+            .collect();
+        grid.push(cells);
+    }
+
+    let (rows, cols) = shape.unwrap_or((grid.len(), grid.first().map_or(0, Vec::len)));
+    if grid.len() != rows {
+        errors.push(GridError {
+            offset: 0,
+            len: value.len(),
+            message: format!("expected {} rows, found {}", rows, grid.len()),
+        });
+    }
+    for (i, (cells, (start, line))) in grid.iter().zip(lines).enumerate() {
+        if !cells.is_empty() && cells.len() != cols {
+            errors.push(GridError {
+                offset: start,
+                len: line.len(),
+                message: format!(
+                    "row {}: expected {} columns, found {}: `{}`",
+                    i + 1,
+                    cols,
+                    cells.len(),
+                    line
+                ),
+            });
+        }
+    }
+    (grid, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // セルの中身と位置がわかる
+    fn test_split_grid() {
+        let value = "\n  | A |Tab|\n  |   | B |\n";
+        let (grid, errors) = split_grid(value, None);
+        assert!(errors.is_empty());
+        let cells = grid
+            .iter()
+            .map(|cells| cells.iter().map(|c| (c.text, c.offset)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![vec![("A", 5), ("Tab", 8)], vec![("", 19), ("B", 21)]],
+            cells
+        );
+    }
+
+    #[test]
+    // 列の数と`|`の位置が揃っていなければエラー
+    fn test_shape_errors() {
+        let messages = |value, shape| {
+            let (_, errors) = split_grid(value, shape);
+            errors.into_iter().map(|e| e.message).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec!["row 2: expected 2 columns, found 1: `|C|`"],
+            messages("|A|B|\n|C|\n", None)
+        );
+        assert_eq!(
+            vec!["row 2: `|` before column 2 is not aligned with the first row: `|C| D |`"],
+            messages("| A |B|\n|C| D |\n", None)
+        );
+        assert_eq!(
+            vec!["row 2: rows must start and end with `|`: `C|D|`"],
+            messages("|A|B|\n C|D|\n", None)
+        );
+        assert_eq!(
+            vec!["expected 2 rows, found 1"],
+            messages("|A|\n", Some((2, 1)))
+        );
+    }
+}
@@ -0,0 +1,212 @@
+/// `rustkbd::keyboard::Key`のデータを持たないヴァリアント。`Key::FIELDLESS`と同じ順に並べる
+pub const KEY_NAMES: &[&str] = &[
+    "None",
+    "Transparent",
+    "A",
+    "B",
+    "C",
+    "D",
+    "E",
+    "F",
+    "G",
+    "H",
+    "I",
+    "J",
+    "K",
+    "L",
+    "M",
+    "N",
+    "O",
+    "P",
+    "Q",
+    "R",
+    "S",
+    "T",
+    "U",
+    "V",
+    "W",
+    "X",
+    "Y",
+    "Z",
+    "Digit1_Exclamation",
+    "Digit2_At",
+    "Digit3_Number",
+    "Digit4_Dollar",
+    "Digit5_Percent",
+    "Digit6_Circumflex",
+    "Digit7_Ampersand",
+    "Digit8_Asterisk",
+    "Digit9_LeftParenthesis",
+    "Digit0_RightParenthesis",
+    "Enter",
+    "Escape",
+    "Delete",
+    "Tab",
+    "Space",
+    "HyphenMinus_LowLine",
+    "Equal_Plus",
+    "LeftSquareBracket_LeftCurlyBracket",
+    "RightSquareBracket_RightCurlyBracket",
+    "Backslash_VerticalBar",
+    "NonUs_Number_Tilde",
+    "Semicolon_Colon",
+    "Apostrophe_Quotation",
+    "Grave_Tilde",
+    "Comma_LessThan",
+    "Period_GreaterThan",
+    "Slash_Question",
+    "CapsLock",
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "F7",
+    "F8",
+    "F9",
+    "F10",
+    "F11",
+    "F12",
+    "PrintScreen",
+    "ScrollLock",
+    "Pause",
+    "Insert",
+    "Home",
+    "PageUp",
+    "DeleteForward",
+    "End",
+    "PageDown",
+    "RightArrow",
+    "LeftArrow",
+    "DownArrow",
+    "UpArrow",
+    "Keypad_NumLock_Clear",
+    "Keypad_Slash",
+    "Keypad_Asterisk",
+    "Keypad_HyphenMinus",
+    "Keypad_Plus",
+    "Keypad_Enter",
+    "Keypad_Digit1_End",
+    "Keypad_Digit2_DownArrow",
+    "Keypad_Digit3_PageDown",
+    "Keypad_Digit4_LeftArrow",
+    "Keypad_Digit5",
+    "Keypad_Digit6_RightArrow",
+    "Keypad_Digit7_Home",
+    "Keypad_Digit8_UpArrow",
+    "Keypad_Digit9_PageUp",
+    "Keypad_Digit0_Insert",
+    "Keypad_Period_Delete",
+    "NonUs_BackSlash_VerticalBar",
+    "Application",
+    "Power",
+    "Keypad_Equal",
+    "F13",
+    "F14",
+    "F15",
+    "F16",
+    "F17",
+    "F18",
+    "F19",
+    "F20",
+    "F21",
+    "F22",
+    "F23",
+    "F24",
+    "Execute",
+    "Help",
+    "Menu",
+    "Select",
+    "Stop",
+    "Again",
+    "Undo",
+    "Cut",
+    "Copy",
+    "Paste",
+    "Find",
+    "Mute",
+    "VolumeUp",
+    "VolumeDown",
+    "LockingCapsLock",
+    "LockingNumLock",
+    "LockingScrollLock",
+    "Keypad_Comma",
+    "Keypad_EqualSign",
+    "International1",
+    "International2",
+    "International3",
+    "International4",
+    "International5",
+    "International6",
+    "International7",
+    "International8",
+    "International9",
+    "Lang1",
+    "Lang2",
+    "Lang3",
+    "Lang4",
+    "Lang5",
+    "Lang6",
+    "Lang7",
+    "Lang8",
+    "Lang9",
+    "AlternateErase",
+    "SysReq_Attention",
+    "Cancel",
+    "Clear",
+    "Prior",
+    "Return",
+    "Separator",
+    "Out",
+    "Oper",
+    "Clear_Again",
+    "CrSel_Props",
+    "ExSel",
+    "LeftControl",
+    "LeftShift",
+    "LeftAlt",
+    "LeftGui",
+    "RightControl",
+    "RightShift",
+    "RightAlt",
+    "RightGui",
+    "MediaZero",
+    "MediaPlay",
+    "MediaPause",
+    "MediaRecord",
+    "MediaNextTrack",
+    "MediaPrevTrack",
+    "MediaStop",
+    "MediaRandomPlay",
+    "MediaRepeat",
+    "MediaPlayPause",
+    "MediaMute",
+    "MediaVolumeIncrement",
+    "MediaVolumeDecrement",
+    "Tilde",
+    "Exclamation",
+    "At",
+    "Hash",
+    "Dollar",
+    "Percent",
+    "Circumflex",
+    "Ampersand",
+    "Asterisk",
+    "LeftParenthesis",
+    "RightParenthesis",
+    "LowLine",
+    "Plus",
+    "LeftCurlyBracket",
+    "RightCurlyBracket",
+    "VerticalBar",
+    "Colon",
+    "Quotation",
+    "LessThan",
+    "GreaterThan",
+    "Question",
+    "UnicodeModeLinux",
+    "UnicodeModeMacOs",
+    "UnicodeModeWinCompose",
+    "UnicodeModeWindowsAlt",
+];
@@ -0,0 +1,14 @@
+//! `layout!`・`include_keymap!`とホスト側のツールで共有するキーマップの書き方
+//!
+//! proc-macroのクレートは定数や関数を公開できないので、ここに置いて両方から使う。
+
+mod file;
+mod grid;
+mod key_names;
+mod symbols;
+
+pub use file::{FileError, Header, HoldEntry, KeymapFile, LayerEntry, Origin, Position, Side};
+pub use grid::{split_grid, Cell, GridError};
+pub use key_names::KEY_NAMES;
+pub use symbols::SYMBOLS;
+pub use toml::Spanned;
@@ -0,0 +1,122 @@
+/// `layout!`の記号と`rustkbd::keyboard::Key`のヴァリアント名の対応
+pub const SYMBOLS: &[(&str, &str)] = &[
+    ("", "None"),
+    ("Trn", "Transparent"),
+    ("A", "A"),
+    ("B", "B"),
+    ("C", "C"),
+    ("D", "D"),
+    ("E", "E"),
+    ("F", "F"),
+    ("G", "G"),
+    ("H", "H"),
+    ("I", "I"),
+    ("J", "J"),
+    ("K", "K"),
+    ("L", "L"),
+    ("M", "M"),
+    ("N", "N"),
+    ("O", "O"),
+    ("P", "P"),
+    ("Q", "Q"),
+    ("R", "R"),
+    ("S", "S"),
+    ("T", "T"),
+    ("U", "U"),
+    ("V", "V"),
+    ("W", "W"),
+    ("X", "X"),
+    ("Y", "Y"),
+    ("Z", "Z"),
+    ("1", "Digit1_Exclamation"),
+    ("2", "Digit2_At"),
+    ("3", "Digit3_Number"),
+    ("4", "Digit4_Dollar"),
+    ("5", "Digit5_Percent"),
+    ("6", "Digit6_Circumflex"),
+    ("7", "Digit7_Ampersand"),
+    ("8", "Digit8_Asterisk"),
+    ("9", "Digit9_LeftParenthesis"),
+    ("0", "Digit0_RightParenthesis"),
+    ("Enter", "Enter"),
+    ("Esc", "Escape"),
+    ("Del", "Delete"),
+    ("Tab", "Tab"),
+    ("Space", "Space"),
+    ("-", "HyphenMinus_LowLine"),
+    ("=", "Equal_Plus"),
+    ("[", "LeftSquareBracket_LeftCurlyBracket"),
+    ("]", "RightSquareBracket_RightCurlyBracket"),
+    ("\\", "Backslash_VerticalBar"),
+    // ("", "NonUs_Number_Tilde"),
+    (";", "Semicolon_Colon"),
+    ("'", "Apostrophe_Quotation"),
+    ("`", "Grave_Tilde"),
+    (",", "Comma_LessThan"),
+    (".", "Period_GreaterThan"),
+    ("/", "Slash_Question"),
+    ("Caps", "CapsLock"),
+    ("F1", "F1"),
+    ("F2", "F2"),
+    ("F3", "F3"),
+    ("F4", "F4"),
+    ("F5", "F5"),
+    ("F6", "F6"),
+    ("F7", "F7"),
+    ("F8", "F8"),
+    ("F9", "F9"),
+    ("F10", "F10"),
+    ("F11", "F11"),
+    ("F12", "F12"),
+    ("PrScr", "PrintScreen"),
+    ("ScLck", "ScrollLock"),
+    ("Pause", "Pause"),
+    ("Ins", "Insert"),
+    ("Home", "Home"),
+    ("PgUp", "PageUp"),
+    ("DelFw", "DeleteForward"),
+    ("End", "End"),
+    ("PgDn", "PageDown"),
+    ("Right", "RightArrow"),
+    ("Left", "LeftArrow"),
+    ("Down", "DownArrow"),
+    ("Up", "UpArrow"),
+    ("LCtl", "LeftControl"),
+    ("LSft", "LeftShift"),
+    ("LAlt", "LeftAlt"),
+    ("LGui", "LeftGui"),
+    ("RCtl", "RightControl"),
+    ("RSft", "RightShift"),
+    ("RAlt", "RightAlt"),
+    ("RGui", "RightGui"),
+    ("MPlay", "MediaPlay"),
+    ("MPau", "MediaPause"),
+    ("MNext", "MediaNextTrack"),
+    ("MPrev", "MediaPrevTrack"),
+    ("MStop", "MediaStop"),
+    ("MPlPs", "MediaPlayPause"),
+    ("MMute", "MediaMute"),
+    ("MVlUp", "MediaVolumeIncrement"),
+    ("MVlDn", "MediaVolumeDecrement"),
+    ("~", "Tilde"),
+    ("!", "Exclamation"),
+    ("@", "At"),
+    ("#", "Hash"),
+    ("$", "Dollar"),
+    ("%", "Percent"),
+    ("&", "Ampersand"),
+    ("^", "Circumflex"),
+    ("*", "Asterisk"),
+    ("(", "LeftParenthesis"),
+    (")", "RightParenthesis"),
+    ("_", "LowLine"),
+    ("+", "Plus"),
+    ("{", "LeftCurlyBracket"),
+    ("}", "RightCurlyBracket"),
+    ("Pipe", "VerticalBar"),
+    (":", "Colon"),
+    ("\"", "Quotation"),
+    ("<", "LessThan"),
+    (">", "GreaterThan"),
+    ("?", "Question"),
+];
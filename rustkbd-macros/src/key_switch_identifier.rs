@@ -0,0 +1,268 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Error, Fields, Ident, Index, Member, Type};
+
+/// 読み書きできるフィールドの型とバイト数
+fn field_size(ty: &Type) -> Option<usize> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    match path.path.get_ident()?.to_string().as_str() {
+        "u8" | "i8" | "bool" => Some(1),
+        "u16" | "i16" => Some(2),
+        "u32" | "i32" => Some(4),
+        _ => None,
+    }
+}
+
+/// フィールドの並びと、それぞれのバイト数
+struct FieldLayout<'a> {
+    fields: Vec<(Member, &'a Type, usize)>,
+}
+
+impl<'a> FieldLayout<'a> {
+    fn new(fields: &'a Fields) -> syn::Result<Self> {
+        let mut errors = Vec::<Error>::new();
+        let mut layout = Vec::new();
+        for (i, field) in fields.iter().enumerate() {
+            let member = match &field.ident {
+                Some(ident) => Member::Named(ident.clone()),
+                None => Member::Unnamed(Index::from(i)),
+            };
+            match field_size(&field.ty) {
+                Some(size) => layout.push((member, &field.ty, size)),
+                None => errors.push(Error::new_spanned(
+                    &field.ty,
+                    "fields of a switch identifier must be u8, i8, u16, i16, u32, i32 or bool",
+                )),
+            }
+        }
+        match errors.into_iter().reduce(|mut acc, error| {
+            acc.combine(error);
+            acc
+        }) {
+            Some(error) => Err(error),
+            None => Ok(FieldLayout { fields: layout }),
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.fields.iter().map(|(_, _, size)| size).sum()
+    }
+
+    /// フィールドを束縛する名前
+    fn bindings(&self) -> Vec<Ident> {
+        self.fields
+            .iter()
+            .map(|(member, _, _)| match member {
+                Member::Named(ident) => ident.clone(),
+                Member::Unnamed(index) => format_ident!("f{}", index.index),
+            })
+            .collect()
+    }
+
+    /// フィールドを`bindings()`の名前で束縛するパターンの中身
+    fn pattern(&self) -> Vec<TokenStream> {
+        self.fields
+            .iter()
+            .zip(self.bindings())
+            .map(|((member, _, _), binding)| match member {
+                Member::Named(ident) => quote!(#ident),
+                Member::Unnamed(index) => quote!(#index: #binding),
+            })
+            .collect()
+    }
+
+    /// `bytes`の`offset`から書き込む文
+    fn encode(&self, offset: usize) -> Vec<TokenStream> {
+        let mut offset = offset;
+        self.fields
+            .iter()
+            .zip(self.bindings())
+            .map(|((_, ty, size), binding)| {
+                let value = if is_bool(ty) {
+                    quote!(u8::from(#binding))
+                } else {
+                    quote!(#binding)
+                };
+                let range = quote!(#offset..(#offset + #size));
+                offset += size;
+                quote!(bytes[#range].copy_from_slice(&#value.to_le_bytes());)
+            })
+            .collect()
+    }
+
+    /// `bytes`の`offset`から読む`フィールド: 値`
+    fn decode(&self, offset: usize) -> Vec<TokenStream> {
+        let mut offset = offset;
+        self.fields
+            .iter()
+            .map(|(member, ty, size)| {
+                let value = if is_bool(ty) {
+                    quote! {
+                        match bytes[#offset] {
+                            0 => false,
+                            1 => true,
+                            _ => return Err(rustkbd::keyboard::InvalidSwitchIdentifier),
+                        }
+                    }
+                } else {
+                    let indices = (offset..(offset + size)).map(|i| quote!(bytes[#i]));
+                    quote!(<#ty>::from_le_bytes([#(#indices),*]))
+                };
+                offset += size;
+                quote!(#member: #value)
+            })
+            .collect()
+    }
+}
+
+fn is_bool(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.path.is_ident("bool"))
+}
+
+/// `#[derive(KeySwitchIdentifier)]`の本体
+///
+/// 構造体はフィールドを宣言順にリトルエンディアンで並べる。
+/// 列挙型は先頭の1バイトにヴァリアントの番号を書き、残りにフィールドを並べて0で埋める。
+/// バイト数（`SZ`）はフィールドの型から決まる。
+pub(crate) fn derive(ast: DeriveInput) -> TokenStream {
+    match expand(&ast) {
+        Ok(expanded) => expanded,
+        Err(error) => error.to_compile_error(),
+    }
+}
+
+fn expand(ast: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = &ast.ident;
+    if !ast.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &ast.generics,
+            "switch identifiers cannot be generic",
+        ));
+    }
+
+    let (size, encode, decode) = match &ast.data {
+        Data::Struct(data) => {
+            let layout = FieldLayout::new(&data.fields)?;
+            let pattern = layout.pattern();
+            let encode = layout.encode(0);
+            let decode = layout.decode(0);
+            (
+                layout.size(),
+                quote! {
+                    let #name { #(#pattern),* } = value;
+                    #(#encode)*
+                },
+                quote!(Ok(#name { #(#decode),* })),
+            )
+        }
+        Data::Enum(data) => {
+            if data.variants.len() > 256 {
+                return Err(Error::new(name.span(), "too many variants"));
+            }
+            let layouts = data
+                .variants
+                .iter()
+                .map(|v| FieldLayout::new(&v.fields))
+                .collect::<syn::Result<Vec<_>>>()?;
+            let size = 1 + layouts.iter().map(FieldLayout::size).max().unwrap_or(0);
+            let mut encode_arms = Vec::new();
+            let mut decode_arms = Vec::new();
+            for (i, (variant, layout)) in data.variants.iter().zip(layouts.iter()).enumerate() {
+                let (ident, tag) = (&variant.ident, i as u8);
+                let pattern = layout.pattern();
+                let encode = layout.encode(1);
+                let decode = layout.decode(1);
+                let used = 1 + layout.size();
+                encode_arms.push(quote! {
+                    #name::#ident { #(#pattern),* } => {
+                        bytes[0] = #tag;
+                        #(#encode)*
+                    }
+                });
+                decode_arms.push(quote! {
+                    #tag if bytes[#used..].iter().all(|b| *b == 0) => Ok(#name::#ident { #(#decode),* }),
+                });
+            }
+            (
+                size,
+                quote! {
+                    match value {
+                        #(#encode_arms)*
+                    }
+                },
+                quote! {
+                    match bytes[0] {
+                        #(#decode_arms)*
+                        _ => Err(rustkbd::keyboard::InvalidSwitchIdentifier),
+                    }
+                },
+            )
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                Span::call_site(),
+                "KeySwitchIdentifier cannot be derived for unions",
+            ))
+        }
+    };
+    if size == 0 {
+        return Err(Error::new(
+            name.span(),
+            "switch identifiers need at least one byte",
+        ));
+    }
+
+    Ok(quote! {
+        impl TryFrom<[u8; #size]> for #name {
+            type Error = rustkbd::keyboard::InvalidSwitchIdentifier;
+
+            fn try_from(bytes: [u8; #size]) -> Result<Self, Self::Error> {
+                #decode
+            }
+        }
+
+        impl From<#name> for [u8; #size] {
+            fn from(value: #name) -> Self {
+                let mut bytes = [0u8; #size];
+                #encode
+                bytes
+            }
+        }
+
+        impl rustkbd::keyboard::KeySwitchIdentifier<#size> for #name {}
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derive_str(input: &str) -> String {
+        derive(syn::parse_str::<DeriveInput>(input).unwrap()).to_string()
+    }
+
+    #[test]
+    // バイト数はフィールドの型から決まる
+    fn test_size() {
+        let expanded = derive_str("struct Id { row: u8, col: u8 }");
+        assert!(expanded.contains("KeySwitchIdentifier < 2usize > for Id"));
+
+        let expanded = derive_str("struct Id(u16, bool);");
+        assert!(expanded.contains("KeySwitchIdentifier < 3usize > for Id"));
+
+        let expanded = derive_str("enum Id { Key { row: u8, col: u8 }, Encoder(u8), Button }");
+        assert!(expanded.contains("KeySwitchIdentifier < 3usize > for Id"));
+    }
+
+    #[test]
+    // 読み書きできない型のフィールドはエラー
+    fn test_errors() {
+        let expanded = derive_str("struct Id { row: usize, col: u8 }");
+        assert!(expanded.contains("fields of a switch identifier must be"));
+
+        let expanded = derive_str("struct Id;");
+        assert!(expanded.contains("switch identifiers need at least one byte"));
+    }
+}
@@ -0,0 +1,369 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use rustkbd_keymap::{Origin, Position};
+use syn::{
+    parenthesized,
+    parse::{Parse, ParseStream},
+    Error, Ident, LitInt, LitStr, Token, Type, Visibility,
+};
+
+use crate::layout::{grid_error, parse_grid};
+
+mod kw {
+    syn::custom_keyword!(below);
+    syn::custom_keyword!(hold);
+    syn::custom_keyword!(layer);
+    syn::custom_keyword!(Left);
+    syn::custom_keyword!(Right);
+}
+
+/// `keymap!`の入力
+///
+/// ```text
+/// pub type Layout: 2, KeySwitchIdentifier;
+/// pub enum Layer;
+/// hold (3, 7) => Lower;
+/// layer Default r"...";
+/// layer Lower r"...";
+/// layer Raise below Default r"...";
+/// ```
+///
+/// `below`を書かないレイヤは、透過キーで1つ前のレイヤに落ちる。
+///
+/// 左右に分かれたキーボードでは、各レイヤに左手側と右手側の2つの表を書き、
+/// `hold Right(1, 0) => Lower;`のように左右を指定できる。指定しなければ左右両方のスイッチになる。
+pub(crate) struct KeymapInput {
+    pub(crate) layout_vis: Visibility,
+    pub(crate) layout: Ident,
+    /// スイッチの識別子の大きさ。分割キーボードでは片手側のもの
+    pub(crate) size: usize,
+    pub(crate) identifier: Type,
+    pub(crate) layer_vis: Visibility,
+    pub(crate) layer: Ident,
+    pub(crate) holds: Vec<Hold>,
+    pub(crate) layers: Vec<LayerGrid>,
+    /// ファイルから読んだときはそのパス。エラーのメッセージに含める
+    pub(crate) file: Option<String>,
+}
+
+pub(crate) enum Side {
+    Both,
+    Left,
+    Right,
+}
+
+/// 押している間レイヤを切り替えるスイッチ
+pub(crate) struct Hold {
+    pub(crate) side: Side,
+    pub(crate) row: LitInt,
+    pub(crate) col: LitInt,
+    pub(crate) layer: Ident,
+    /// ファイルから読んだときの位置
+    pub(crate) position: Option<Position>,
+}
+
+pub(crate) struct LayerGrid {
+    pub(crate) name: Ident,
+    /// 透過キーで落ちる先のレイヤ
+    pub(crate) below: Option<Ident>,
+    pub(crate) grids: Vec<Table>,
+    /// ファイルから読んだときの位置
+    pub(crate) position: Option<Position>,
+}
+
+/// レイヤの表
+pub(crate) struct Table {
+    pub(crate) literal: LitStr,
+    /// ファイルから読んだときの、文字列の中身の始まり
+    pub(crate) origin: Option<Origin>,
+}
+
+impl Parse for KeymapInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let layout_vis = input.parse()?;
+        input.parse::<Token![type]>()?;
+        let layout = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let size = input.parse::<LitInt>()?.base10_parse()?;
+        input.parse::<Token![,]>()?;
+        let identifier = input.parse()?;
+        input.parse::<Token![;]>()?;
+
+        let layer_vis = input.parse()?;
+        input.parse::<Token![enum]>()?;
+        let layer = input.parse()?;
+        input.parse::<Token![;]>()?;
+
+        let mut holds = Vec::new();
+        let mut layers = Vec::new();
+        while !input.is_empty() {
+            let lookahead = input.lookahead1();
+            if lookahead.peek(kw::hold) {
+                input.parse::<kw::hold>()?;
+                let side = if input.peek(kw::Left) {
+                    input.parse::<kw::Left>()?;
+                    Side::Left
+                } else if input.peek(kw::Right) {
+                    input.parse::<kw::Right>()?;
+                    Side::Right
+                } else {
+                    Side::Both
+                };
+                let content;
+                parenthesized!(content in input);
+                let row = content.parse()?;
+                content.parse::<Token![,]>()?;
+                let col = content.parse()?;
+                input.parse::<Token![=>]>()?;
+                let layer = input.parse()?;
+                input.parse::<Token![;]>()?;
+                holds.push(Hold {
+                    side,
+                    row,
+                    col,
+                    layer,
+                    position: None,
+                });
+            } else if lookahead.peek(kw::layer) {
+                input.parse::<kw::layer>()?;
+                let name = input.parse()?;
+                let below = if input.peek(kw::below) {
+                    input.parse::<kw::below>()?;
+                    Some(input.parse()?)
+                } else {
+                    None
+                };
+                let mut grids = Vec::new();
+                while input.peek(LitStr) {
+                    grids.push(Table {
+                        literal: input.parse()?,
+                        origin: None,
+                    });
+                }
+                input.parse::<Token![;]>()?;
+                layers.push(LayerGrid {
+                    name,
+                    below,
+                    grids,
+                    position: None,
+                });
+            } else {
+                return Err(lookahead.error());
+            }
+        }
+
+        Ok(KeymapInput {
+            layout_vis,
+            layout,
+            size,
+            identifier,
+            layer_vis,
+            layer,
+            holds,
+            layers,
+            file: None,
+        })
+    }
+}
+
+impl KeymapInput {
+    /// ファイルから読んだときは、どのファイルのどの位置・レイヤのエラーかをメッセージに含める
+    fn locate(&self, error: Error, layer: Option<&Ident>, position: Option<Position>) -> Error {
+        let Some(file) = &self.file else {
+            return error;
+        };
+        let file = match position {
+            Some(Position { line, column }) => format!("{file}:{line}:{column}"),
+            None => file.clone(),
+        };
+        error
+            .into_iter()
+            .map(|e| match layer {
+                Some(layer) => Error::new(e.span(), format!("{file}: layer `{layer}`: {e}")),
+                None => Error::new(e.span(), format!("{file}: {e}")),
+            })
+            .reduce(|mut acc, error| {
+                acc.combine(error);
+                acc
+            })
+            .unwrap()
+    }
+}
+
+pub(crate) fn expand(input: KeymapInput) -> TokenStream {
+    let mut errors = Vec::<Error>::new();
+    let Some(first) = input.layers.first() else {
+        let error = Error::new(input.layer.span(), "at least one layer is needed");
+        return input.locate(error, None, None).to_compile_error();
+    };
+    let split = first.grids.len() == 2;
+
+    // 1つ目の表の大きさにすべての表を揃える
+    let mut shape = None;
+    let mut keymap = Vec::new();
+    for layer in input.layers.iter() {
+        if layer.grids.len() != first.grids.len() || !(1..=2).contains(&layer.grids.len()) {
+            let error = Error::new(
+                layer.name.span(),
+                if split {
+                    "split layers need a left and a right table"
+                } else {
+                    "layers need one table"
+                },
+            );
+            errors.push(input.locate(error, Some(&layer.name), layer.position));
+            continue;
+        }
+        let mut rows = Vec::new();
+        for grid in layer.grids.iter() {
+            let value = grid.literal.value();
+            let (keys, grid_errors) = parse_grid(&value, shape);
+            for error in grid_errors {
+                let position = grid
+                    .origin
+                    .map(|origin| origin.position(&value, error.offset));
+                let error = grid_error(&grid.literal, &value, error);
+                errors.push(input.locate(error, Some(&layer.name), position));
+            }
+            shape = shape.or(Some((keys.len(), keys.first().map_or(0, Vec::len))));
+            rows.extend(keys);
+        }
+        keymap.push(rows);
+    }
+    let (half_rows, cols) = shape.unwrap_or_default();
+    let rows = if split { half_rows * 2 } else { half_rows };
+
+    let layer = &input.layer;
+    let names = input
+        .layers
+        .iter()
+        .map(|layer| &layer.name)
+        .collect::<Vec<_>>();
+    for grid in input.layers.iter() {
+        if let Some(below) = grid.below.as_ref().filter(|below| !names.contains(below)) {
+            let error = Error::new(below.span(), format!("unknown layer `{below}`"));
+            errors.push(input.locate(error, Some(&grid.name), grid.position));
+        }
+    }
+    let mut layer_switches = Vec::new();
+    for hold in input.holds.iter() {
+        if !names.contains(&&hold.layer) {
+            let error = Error::new(hold.layer.span(), format!("unknown layer `{}`", hold.layer));
+            errors.push(input.locate(error, None, hold.position));
+        }
+        let (row, col) = match (
+            hold.row.base10_parse::<usize>(),
+            hold.col.base10_parse::<usize>(),
+        ) {
+            (Ok(row), Ok(col)) => (row, col),
+            (Err(error), _) | (_, Err(error)) => {
+                errors.push(input.locate(error, None, hold.position));
+                continue;
+            }
+        };
+        // 分割キーボードでは左手側の下に右手側を並べた位置になる
+        let hold_rows = match hold.side {
+            Side::Both if split => vec![row, half_rows + row],
+            Side::Both | Side::Left => vec![row],
+            Side::Right => vec![half_rows + row],
+        };
+        if !split && !matches!(hold.side, Side::Both) {
+            let error = Error::new(
+                hold.row.span(),
+                "`Left` and `Right` are only for split keymaps",
+            );
+            errors.push(input.locate(error, None, hold.position));
+        }
+        let name = &hold.layer;
+        layer_switches.extend(
+            hold_rows
+                .into_iter()
+                .map(|row| quote!(((#row, #col), #layer::#name),)),
+        );
+    }
+
+    if let Some(error) = errors.into_iter().reduce(|mut acc, error| {
+        acc.combine(error);
+        acc
+    }) {
+        return error.to_compile_error();
+    }
+
+    let layers = names.len();
+    let tables = keymap.iter().map(|rows| {
+        let rows = rows.iter().map(|keys| quote!([#(#keys,)*]));
+        quote!([#(#rows,)*])
+    });
+    let (layout_vis, layout_name) = (&input.layout_vis, &input.layout);
+    let layout_const = Ident::new(
+        &screaming_snake_case(&layout_name.to_string()),
+        layout_name.span(),
+    );
+    let layer_vis = &input.layer_vis;
+    let variants = input.layers.iter().enumerate().map(|(i, grid)| {
+        let name = &grid.name;
+        let default = (i == 0).then(|| quote!(#[default]));
+        let below = grid
+            .below
+            .as_ref()
+            .map(|below| quote!(#[layer(below = #below)]));
+        quote!(#default #below #name,)
+    });
+    let inner = &input.identifier;
+    let (layout_type, layout_new) = if split {
+        let size = input.size;
+        // 左手側の行と右手側の行に分ける
+        let (left, right): (Vec<_>, Vec<_>) = keymap
+            .iter()
+            .map(|rows| {
+                let (left, right) = rows.split_at(half_rows);
+                let left = left.iter().map(|keys| quote!([#(#keys,)*]));
+                let right = right.iter().map(|keys| quote!([#(#keys,)*]));
+                (quote!([#(#left,)*]), quote!([#(#right,)*]))
+            })
+            .unzip();
+        (
+            quote!(rustkbd::split::SplitArrayLayout<#size, #inner, #layer, #layers, #half_rows, #cols>),
+            quote! {
+                rustkbd::split::SplitArrayLayout::new(
+                    [#(#left,)*],
+                    [#(#right,)*],
+                    &[#(#layer_switches)*],
+                )
+            },
+        )
+    } else {
+        (
+            quote!(rustkbd::keyboard::ArrayLayout<#inner, #layer, #layers, #rows, #cols>),
+            quote!(rustkbd::keyboard::ArrayLayout::new([#(#tables,)*], &[#(#layer_switches)*])),
+        )
+    };
+
+    quote! {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, rustkbd::keyboard::Layer)]
+        #layer_vis enum #layer {
+            #(#variants)*
+        }
+
+        #layout_vis type #layout_name = #layout_type;
+
+        /// 書き換えられる前の、`keymap!`に書いたとおりのLayout
+        #layout_vis const #layout_const: #layout_name = #layout_new;
+    }
+}
+
+/// `SplitLayout`を`SPLIT_LAYOUT`にする
+fn screaming_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    let mut previous = None;
+    for c in name.chars() {
+        if c.is_uppercase()
+            && previous.is_some_and(|p: char| p.is_lowercase() || p.is_ascii_digit())
+        {
+            result.push('_');
+        }
+        result.push(c.to_ascii_uppercase());
+        previous = Some(c);
+    }
+    result
+}
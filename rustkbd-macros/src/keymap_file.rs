@@ -0,0 +1,249 @@
+use std::path::PathBuf;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use rustkbd_keymap::{FileError, KeymapFile, Origin, Position, Spanned};
+use syn::{Error, Ident, LitInt, LitStr, Type};
+
+use crate::keymap::{self, Hold, KeymapInput, LayerGrid, Side, Table};
+
+pub(crate) fn expand(path: LitStr) -> TokenStream {
+    let full_path =
+        PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default()).join(path.value());
+    let input = std::fs::read_to_string(&full_path)
+        .map_err(|e| Error::new(path.span(), format!("cannot read {}: {e}", path.value())))
+        .and_then(|content| parse(&content, &path));
+    match input {
+        Ok(input) => {
+            let full_path = full_path.to_string_lossy();
+            let expanded = keymap::expand(input);
+            // ファイルが変わったら作り直す
+            quote! {
+                const _: &str = include_str!(#full_path);
+                #expanded
+            }
+        }
+        Err(error) => error.to_compile_error(),
+    }
+}
+
+/// エラーのSpanはパスのリテラルになるので、メッセージにファイルの行と列を書く
+fn parse(content: &str, path: &LitStr) -> syn::Result<KeymapInput> {
+    let file = path.value();
+    let span = path.span();
+    let error = |error: FileError| match error.offset {
+        Some(offset) => {
+            let Position { line, column } = Position::new(content, offset);
+            Error::new(span, format!("{file}:{line}:{column}: {}", error.message))
+        }
+        None => Error::new(span, format!("{file}: {}", error.message)),
+    };
+    let ident = |name: &Spanned<String>| {
+        syn::parse_str::<Ident>(name.get_ref())
+            .map(|ident| Ident::new(&ident.to_string(), span))
+            .map_err(|_| {
+                error(FileError {
+                    offset: Some(name.span().start),
+                    message: format!("`{}` is not an identifier", name.get_ref()),
+                })
+            })
+    };
+
+    let keymap = KeymapFile::parse(content).map_err(error)?;
+    let identifier = &keymap.keymap.identifier;
+    let identifier = syn::parse_str::<Type>(identifier.get_ref()).map_err(|_| {
+        error(FileError {
+            offset: Some(identifier.span().start),
+            message: format!("`{}` is not a type", identifier.get_ref()),
+        })
+    })?;
+
+    let mut holds = Vec::new();
+    for hold in keymap.hold.iter() {
+        let (row, col) = hold.switch;
+        holds.push(Hold {
+            side: match hold.side {
+                None => Side::Both,
+                Some(rustkbd_keymap::Side::Left) => Side::Left,
+                Some(rustkbd_keymap::Side::Right) => Side::Right,
+            },
+            row: LitInt::new(&row.to_string(), span),
+            col: LitInt::new(&col.to_string(), span),
+            layer: ident(&hold.layer)?,
+            position: Some(Position::new(content, hold.layer.span().start)),
+        });
+    }
+
+    let mut layers = Vec::new();
+    for layer in keymap.layer.iter() {
+        layers.push(LayerGrid {
+            name: ident(&layer.name)?,
+            below: layer.below.as_ref().map(ident).transpose()?,
+            grids: layer
+                .tables()
+                .map_err(error)?
+                .into_iter()
+                .map(|table| Table {
+                    literal: LitStr::new(table.get_ref(), span),
+                    origin: Some(Origin::new(content, table)),
+                })
+                .collect(),
+            position: Some(Position::new(content, layer.name.span().start)),
+        });
+    }
+
+    Ok(KeymapInput {
+        layout_vis: syn::parse_quote!(pub),
+        layout: ident(&keymap.keymap.layout)?,
+        size: keymap.keymap.size,
+        identifier,
+        layer_vis: syn::parse_quote!(pub),
+        layer: ident(&keymap.keymap.layer)?,
+        holds,
+        layers,
+        file: Some(file),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proc_macro2::Span;
+
+    fn parse_str(content: &str) -> syn::Result<KeymapInput> {
+        parse(content, &LitStr::new("keymap.toml", Span::call_site()))
+    }
+
+    #[test]
+    // ファイルの内容がkeymap!の入力になる
+    fn test_parse() {
+        let input = parse_str(
+            r#"
+            [keymap]
+            layout = "Layout"
+            layer = "Layer"
+            identifier = "crate::Id"
+            size = 2
+
+            [[hold]]
+            switch = [0, 1]
+            side = "right"
+            layer = "Lower"
+
+            [[layer]]
+            name = "Default"
+            left = '|A|B|'
+            right = '|\|C|'
+
+            [[layer]]
+            name = "Lower"
+            below = "Default"
+            left = '|1|2|'
+            right = '|3|4|'
+            "#,
+        )
+        .unwrap();
+        assert_eq!("Layout", input.layout.to_string());
+        assert_eq!("Layer", input.layer.to_string());
+        assert_eq!(2, input.size);
+
+        let hold = &input.holds[0];
+        assert!(matches!(hold.side, Side::Right));
+        assert_eq!(0, hold.row.base10_parse::<usize>().unwrap());
+        assert_eq!(1, hold.col.base10_parse::<usize>().unwrap());
+        assert_eq!("Lower", hold.layer.to_string());
+
+        let layers = input
+            .layers
+            .iter()
+            .map(|layer| {
+                (
+                    layer.name.to_string(),
+                    layer.below.as_ref().map(Ident::to_string),
+                    layer
+                        .grids
+                        .iter()
+                        .map(|table| table.literal.value())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (
+                    "Default".to_string(),
+                    None,
+                    vec!["|A|B|".to_string(), r"|\|C|".to_string()]
+                ),
+                (
+                    "Lower".to_string(),
+                    Some("Default".to_string()),
+                    vec!["|1|2|".to_string(), "|3|4|".to_string()]
+                ),
+            ],
+            layers
+        );
+    }
+
+    #[test]
+    // エラーにはファイル名と行と列、レイヤ名が入る
+    fn test_errors() {
+        let error = parse_str("[keymap]\nlayout = \"Layout\"\n").err().unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("keymap.toml:1:1: missing field"));
+
+        let header = r#"[keymap]
+layout = "Layout"
+layer = "Layer"
+identifier = "Id"
+size = 2
+"#;
+        let error = parse_str(&header.replace("\"Layout\"", "\"Lay out\"")).err();
+        assert_eq!(
+            "keymap.toml:2:10: `Lay out` is not an identifier",
+            error.unwrap().to_string()
+        );
+
+        let input = parse_str(&format!(
+            "{header}
+[[hold]]
+switch = [0, 0]
+layer = \"Upper\"
+
+[[layer]]
+name = \"Default\"
+keys = '''
+| A |  B   |
+| C |Foobar|
+'''
+"
+        ))
+        .unwrap();
+        let expanded = keymap::expand(input).to_string();
+        assert!(expanded.contains("keymap.toml:9:9: unknown layer `Upper`"));
+        assert!(expanded.contains(
+            "keymap.toml:15:6: layer `Default`: row 2, column 2: unknown symbol `Foobar`"
+        ));
+
+        let input = parse_str(&format!(
+            "{header}
+[[layer]]
+name = \"Default\"
+keys = '|A|Foobar|'
+"
+        ))
+        .unwrap();
+        let expanded = keymap::expand(input).to_string();
+        assert!(expanded.contains(
+            "keymap.toml:9:12: layer `Default`: row 1, column 2: unknown symbol `Foobar`"
+        ));
+
+        // コンボはまだない
+        let error = parse_str(&format!("{header}\n[[combo]]\nkeys = [\"J\", \"K\"]\n")).err();
+        assert_eq!(
+            "keymap.toml:7:1: combos are not supported yet",
+            error.unwrap().to_string()
+        );
+    }
+}
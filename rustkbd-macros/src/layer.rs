@@ -0,0 +1,268 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, Ident, LitInt, LitStr};
+
+/// `#[layer(...)]`を読んだあとのヴァリアント
+struct LayerVariant {
+    ident: Ident,
+    name: String,
+    index: usize,
+    below: Option<Ident>,
+}
+
+/// `#[derive(Layer)]`の本体
+///
+/// `below`を指定しないヴァリアントは宣言順で1つ前のヴァリアントに落ちる。
+/// `index`を指定しないヴァリアントは1つ前のヴァリアントの`index`の次になる。
+/// `index`はキーマップの配列の添字になるので、0からヴァリアントの数-1までを重ならずに使う。
+pub(crate) fn derive(ast: DeriveInput) -> TokenStream {
+    match expand(&ast) {
+        Ok(expanded) => expanded,
+        Err(error) => error.to_compile_error(),
+    }
+}
+
+fn expand(ast: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = &ast.ident;
+    let Data::Enum(ref data) = ast.data else {
+        return Err(Error::new(
+            name.span(),
+            "Layer can only be derived for enums",
+        ));
+    };
+
+    let mut errors = Vec::<Error>::new();
+    let mut variants = Vec::<LayerVariant>::new();
+    for variant in data.variants.iter() {
+        if !matches!(variant.fields, Fields::Unit) {
+            errors.push(Error::new(
+                variant.ident.span(),
+                "layers cannot have fields",
+            ));
+        }
+        let mut layer = LayerVariant {
+            ident: variant.ident.clone(),
+            name: variant.ident.to_string(),
+            index: variants.last().map_or(0, |v| v.index + 1),
+            below: variants.last().map(|v| v.ident.clone()),
+        };
+        for attr in variant.attrs.iter().filter(|a| a.path().is_ident("layer")) {
+            let result = attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("below") {
+                    layer.below = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("name") {
+                    layer.name = meta.value()?.parse::<LitStr>()?.value();
+                } else if meta.path.is_ident("index") {
+                    layer.index = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                } else {
+                    return Err(meta.error("expected `below`, `name` or `index`"));
+                }
+                Ok(())
+            });
+            errors.extend(result.err());
+        }
+        if let Some(other) = variants.iter().find(|v| v.index == layer.index) {
+            errors.push(Error::new(
+                layer.ident.span(),
+                format!("index {} is already used by `{}`", layer.index, other.ident),
+            ));
+        }
+        variants.push(layer);
+    }
+    if let Some(variant) = variants.iter().find(|v| v.index >= variants.len()) {
+        errors.push(Error::new(
+            variant.ident.span(),
+            format!(
+                "layer indices must be 0 to {} without gaps, but `{}` has index {}",
+                variants.len() - 1,
+                variant.ident,
+                variant.index
+            ),
+        ));
+    }
+
+    for variant in variants.iter() {
+        if let Some(below) = &variant.below {
+            if !variants.iter().any(|v| &v.ident == below) {
+                errors.push(Error::new(below.span(), format!("unknown layer `{below}`")));
+            }
+        }
+    }
+    if errors.is_empty() {
+        if let Some((variant, cycle)) = find_cycle(&variants) {
+            errors.push(Error::new(
+                variant.span(),
+                format!("layers fall through in a cycle: {}", cycle.join(" -> ")),
+            ));
+        }
+    }
+    if let Some(error) = errors.into_iter().reduce(|mut acc, error| {
+        acc.combine(error);
+        acc
+    }) {
+        return Err(error);
+    }
+
+    let all = variants.iter().map(|v| &v.ident);
+    let belows = variants.iter().map(|v| {
+        let ident = &v.ident;
+        match &v.below {
+            Some(below) => quote!(#name::#ident => Some(#name::#below),),
+            None => quote!(#name::#ident => None,),
+        }
+    });
+    let indices = variants
+        .iter()
+        .map(|v| {
+            let (ident, index) = (&v.ident, v.index);
+            quote!(#name::#ident => #index,)
+        })
+        .collect::<Vec<_>>();
+    let from_indices = variants.iter().map(|v| {
+        let (ident, index) = (&v.ident, v.index);
+        quote!(#index => Some(#name::#ident),)
+    });
+    let names = variants.iter().map(|v| {
+        let (ident, layer_name) = (&v.ident, &v.name);
+        quote!(#name::#ident => #layer_name,)
+    });
+
+    let const_indices = indices.clone();
+
+    Ok(quote! {
+        impl #name {
+            /// `Layer::index`を定数の式で使えるようにしたもの。`layout!`の`LT(Lower, Space)`で使う
+            pub const fn index(&self) -> usize {
+                match self {
+                    #(#const_indices)*
+                }
+            }
+        }
+
+        impl rustkbd::keyboard::Layer for #name {
+            const ALL: &'static [Self] = &[#(#name::#all,)*];
+
+            fn below(&self) -> Option<Self> {
+                match self {
+                    #(#belows)*
+                }
+            }
+
+            fn index(&self) -> usize {
+                match self {
+                    #(#indices)*
+                }
+            }
+
+            fn from_index(index: usize) -> Option<Self> {
+                match index {
+                    #(#from_indices)*
+                    _ => None,
+                }
+            }
+
+            fn name(&self) -> &'static str {
+                match self {
+                    #(#names)*
+                }
+            }
+        }
+    })
+}
+
+/// `below`をたどって元に戻るレイヤがあれば、そのレイヤと一周分の名前
+fn find_cycle(variants: &[LayerVariant]) -> Option<(&Ident, Vec<String>)> {
+    variants.iter().find_map(|start| {
+        let mut path = vec![&start.ident];
+        let mut current = start;
+        while let Some(below) = &current.below {
+            if below == &start.ident {
+                path.push(below);
+                return Some((&start.ident, path.iter().map(|i| i.to_string()).collect()));
+            }
+            // startを含まない循環は、その循環の中のレイヤから見つける
+            if path.contains(&below) {
+                return None;
+            }
+            path.push(below);
+            current = variants.iter().find(|v| &v.ident == below)?;
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derive_str(input: &str) -> String {
+        derive(syn::parse_str::<DeriveInput>(input).unwrap()).to_string()
+    }
+
+    #[test]
+    // 属性で落ちる先・名前・番号を変えられる
+    fn test_attributes() {
+        let expanded = derive_str(
+            r#"
+            enum Layer {
+                Default,
+                Lower,
+                #[layer(below = Default, name = "Sym", index = 3)]
+                Raise,
+                #[layer(index = 2)]
+                Adjust,
+            }
+            "#,
+        );
+        assert!(expanded.contains("Layer :: Default => None ,"));
+        assert!(expanded.contains("Layer :: Raise => Some (Layer :: Default) ,"));
+        assert!(expanded.contains("Layer :: Adjust => Some (Layer :: Raise) ,"));
+        assert!(expanded.contains("Layer :: Adjust => 2usize ,"));
+        assert!(expanded.contains("3usize => Some (Layer :: Raise) ,"));
+        assert!(expanded.contains("Layer :: Raise => \"Sym\" ,"));
+        assert!(expanded.contains("Layer :: Lower => \"Lower\" ,"));
+    }
+
+    #[test]
+    // 落ちる先が循環していたり、番号が重なったり飛んでいたりするとエラー
+    fn test_errors() {
+        let expanded = derive_str(
+            r#"
+            enum Layer {
+                #[layer(below = Raise)]
+                Default,
+                Lower,
+                Raise,
+            }
+            "#,
+        );
+        assert!(expanded
+            .contains("layers fall through in a cycle: Default -> Raise -> Lower -> Default"));
+
+        let expanded = derive_str(
+            r#"
+            enum Layer {
+                Default,
+                #[layer(index = 0, below = Upper)]
+                Lower,
+            }
+            "#,
+        );
+        assert!(expanded.contains("index 0 is already used by `Default`"));
+        assert!(expanded.contains("unknown layer `Upper`"));
+
+        // キーマップの添字になるので、番号は飛ばせない
+        let expanded = derive_str(
+            r#"
+            enum Layer {
+                Default,
+                #[layer(index = 2)]
+                Lower,
+            }
+            "#,
+        );
+        assert!(
+            expanded.contains("layer indices must be 0 to 1 without gaps, but `Lower` has index 2")
+        );
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use rustkbd_keymap::{split_grid, GridError, KEY_NAMES, SYMBOLS};
+use syn::{
+    parse::{Parse, ParseStream},
+    Error, Ident, LitInt, LitStr, Token,
+};
+
+/// `layout!`の入力。`rows = 4, cols = 12,`で表の大きさを宣言できる
+pub(crate) struct LayoutInput {
+    shape: Option<(usize, usize)>,
+    literal: LitStr,
+}
+
+impl Parse for LayoutInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut rows = None;
+        let mut cols = None;
+        while input.peek(Ident) {
+            let name = input.parse::<Ident>()?;
+            input.parse::<Token![=]>()?;
+            let value = input.parse::<LitInt>()?.base10_parse::<usize>()?;
+            input.parse::<Token![,]>()?;
+            match name.to_string().as_str() {
+                "rows" => rows = Some(value),
+                "cols" => cols = Some(value),
+                _ => return Err(Error::new(name.span(), "expected `rows` or `cols`")),
+            }
+        }
+        let literal = input.parse()?;
+        let shape = match (rows, cols) {
+            (Some(rows), Some(cols)) => Some((rows, cols)),
+            (None, None) => None,
+            _ => return Err(input.error("both `rows` and `cols` are needed")),
+        };
+        Ok(LayoutInput { shape, literal })
+    }
+}
+
+pub(crate) fn expand(input: LayoutInput) -> TokenStream {
+    let value = input.literal.value();
+    let (grid, errors) = parse_grid(&value, input.shape);
+    let error = errors
+        .into_iter()
+        .map(|error| grid_error(&input.literal, &value, error))
+        .reduce(|mut acc, error| {
+            acc.combine(error);
+            acc
+        });
+    if let Some(error) = error {
+        // 型のエラーが重ならないように、宣言された大きさの配列にしておく
+        let errors = error.to_compile_error();
+        let (rows, cols) = input
+            .shape
+            .unwrap_or((grid.len(), grid.first().map_or(0, Vec::len)));
+        return quote! {
+            {
+                #errors
+                [[rustkbd::keyboard::Key::None; #cols]; #rows]
+            }
+        };
+    }
+    let rows = grid.iter().map(|row| quote!([#(#row,)*]));
+    quote! {
+        [#(#rows,)*]
+    }
+}
+
+/// 表を解釈して、キーの式の2次元配列にする。エラーはまとめて返す
+///
+/// 安定版のコンパイラではSpanがリテラル全体を指すので、メッセージに行と列とセルの文字列を書く。
+pub(crate) fn parse_grid(
+    value: &str,
+    shape: Option<(usize, usize)>,
+) -> (Vec<Vec<TokenStream>>, Vec<GridError>) {
+    let table = symbols();
+    let (cells, mut errors) = split_grid(value, shape);
+    let mut grid = Vec::new();
+    for (row, cells) in cells.iter().enumerate() {
+        let mut keys = Vec::new();
+        for (col, cell) in cells.iter().enumerate() {
+            match parse_key(cell.text, &table) {
+                Ok(key) => keys.push(key),
+                Err(message) => {
+                    // 入れ子のキーのエラーでは、どのセルかわかるようにセル全体も書く
+                    let message = if message.contains(&format!("`{}`", cell.text)) {
+                        message
+                    } else {
+                        format!("`{}`: {}", cell.text, message)
+                    };
+                    errors.push(GridError {
+                        offset: cell.offset,
+                        len: cell.text.len().max(1),
+                        message: format!("row {}, column {}: {}", row + 1, col + 1, message),
+                    });
+                    keys.push(quote!(rustkbd::keyboard::Key::None));
+                }
+            }
+        }
+        grid.push(keys);
+    }
+    (grid, errors)
+}
+
+/// 表の誤りを、リテラルの中を指すエラーにする
+pub(crate) fn grid_error(literal: &LitStr, value: &str, error: GridError) -> Error {
+    Error::new(
+        subspan(literal, value, error.offset, error.len),
+        error.message,
+    )
+}
+
+/// 文字列リテラルの`offset`から`len`バイトを指すSpan
+///
+/// コンパイラが対応していないときやエスケープを含むときは、リテラル全体を指す
+fn subspan(literal: &LitStr, value: &str, offset: usize, len: usize) -> Span {
+    let token = literal.token();
+    let text = token.to_string();
+    let prefix = text.find('"').map_or(0, |i| i + 1);
+    if text.get(prefix..(prefix + value.len())) != Some(value) {
+        return literal.span();
+    }
+    token
+        .subspan((prefix + offset)..(prefix + offset + len))
+        .unwrap_or_else(|| literal.span())
+}
+
+/// 記号とキーの対応
+fn symbols() -> HashMap<&'static str, TokenStream> {
+    SYMBOLS
+        .iter()
+        .map(|(symbol, name)| {
+            let name = format_ident!("{}", name);
+            (*symbol, quote!(rustkbd::keyboard::Key::#name))
+        })
+        .collect()
+}
+
+/// 1つのセルをキーの式にする
+fn parse_key(cell: &str, table: &HashMap<&str, TokenStream>) -> Result<TokenStream, String> {
+    if let Some(key) = table.get(cell) {
+        return Ok(key.clone());
+    }
+    let name = cell.strip_prefix("Key::").unwrap_or(cell);
+    if KEY_NAMES.contains(&name) {
+        let name = format_ident!("{}", name);
+        return Ok(quote!(rustkbd::keyboard::Key::#name));
+    }
+
+    let unknown = || unknown_symbol(cell, table);
+    let (wrapper, args) = cell
+        .strip_suffix(')')
+        .and_then(|cell| cell.split_once('('))
+        .ok_or_else(unknown)?;
+    if let Some(modifiers) = modifier_flags(wrapper.trim()) {
+        let key = parse_key(args.trim(), table)?;
+        return Ok(quote!(rustkbd::keyboard::Key::with_modifiers(#modifiers, #key)));
+    }
+    match wrapper.trim() {
+        "LT" => {
+            let (layer, key) = args
+                .split_once(',')
+                .ok_or_else(|| format!("`{}` needs a layer and a key", cell))?;
+            let layer = parse_layer(layer.trim()).ok_or_else(unknown)?;
+            let key = parse_key(key.trim(), table)?;
+            Ok(quote!(rustkbd::keyboard::Key::layer_tap(#layer, #key)))
+        }
+        "U" => {
+            let mut chars = args.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(quote!(rustkbd::keyboard::Key::Unicode(#c))),
+                _ => Err(unknown()),
+            }
+        }
+        _ => Err(unknown()),
+    }
+}
+
+/// 近い名前があれば、それを提案する
+fn unknown_symbol(cell: &str, table: &HashMap<&str, TokenStream>) -> String {
+    let threshold = match cell.chars().count() {
+        0..=3 => 1,
+        4..=8 => 2,
+        _ => 3,
+    };
+    let suggestion = table
+        .keys()
+        .chain(KEY_NAMES.iter())
+        .filter(|name| !name.is_empty())
+        .map(|name| (distance(cell, name), *name))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min();
+    match suggestion {
+        Some((_, name)) => format!("unknown symbol `{}`, did you mean `{}`?", cell, name),
+        None => format!("unknown symbol `{}`", cell),
+    }
+}
+
+/// 大文字と小文字を区別しない編集距離
+fn distance(a: &str, b: &str) -> usize {
+    let a = a.to_lowercase().chars().collect::<Vec<_>>();
+    let b = b.to_lowercase().chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.iter().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == cb {
+                previous
+            } else {
+                previous.min(current).min(row[j]) + 1
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// 修飾キーを足すラッパーの名前から、修飾キーのフラグを返す
+fn modifier_flags(wrapper: &str) -> Option<u8> {
+    match wrapper {
+        "C" => Some(0x01),
+        "S" => Some(0x02),
+        "A" => Some(0x04),
+        "G" => Some(0x08),
+        "RC" => Some(0x10),
+        "RS" => Some(0x20),
+        "RA" => Some(0x40),
+        "RG" => Some(0x80),
+        _ => None,
+    }
+}
+
+/// レイヤの番号か、`Layer`のヴァリアント（`Lower`、`Layer::Lower`）
+///
+/// ヴァリアントは判別値ではなく`index()`にする。`derive(Layer)`の`index()`は定数の式で使える
+fn parse_layer(layer: &str) -> Option<TokenStream> {
+    if let Ok(index) = layer.parse::<u8>() {
+        return Some(quote!(#index));
+    }
+    let path = syn::parse_str::<syn::Path>(layer).ok()?;
+    if path.segments.len() == 1 {
+        Some(quote!(Layer::#path.index() as u8))
+    } else {
+        Some(quote!(#path.index() as u8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str, shape: Option<(usize, usize)>) -> (usize, Vec<String>) {
+        let (grid, errors) = parse_grid(value, shape);
+        (grid.len(), errors.into_iter().map(|e| e.message).collect())
+    }
+
+    #[test]
+    // 記号、ヴァリアント名、ラッパーが書ける
+    fn test_parse_grid() {
+        let (rows, messages) = parse(
+            r"
+            | Esc | Key::Lang1 | S(1) |
+            | F13 | LT(1, Spce)|  U(あ)|
+            ",
+            Some((2, 3)),
+        );
+        assert_eq!(2, rows);
+        assert_eq!(
+            vec!["row 2, column 2: `LT(1, Spce)`: unknown symbol `Spce`, did you mean `Space`?"],
+            messages
+        );
+        // 入れ子のキーのエラーにはセル全体も書く
+        let (_, messages) = parse("| C(Tabb) | LT(1) |\n", None);
+        assert_eq!(
+            vec![
+                "row 1, column 1: `C(Tabb)`: unknown symbol `Tabb`, did you mean `Tab`?",
+                "row 1, column 2: `LT(1)` needs a layer and a key",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    // 大文字と小文字は区別せずに近い名前を提案する
+    fn test_suggestion() {
+        let table = symbols();
+        assert_eq!(
+            "unknown symbol `lang1`, did you mean `Lang1`?",
+            unknown_symbol("lang1", &table)
+        );
+        assert_eq!(
+            "unknown symbol `Spcae`, did you mean `Space`?",
+            unknown_symbol("Spcae", &table)
+        );
+        assert_eq!("unknown symbol `Foobar`", unknown_symbol("Foobar", &table));
+    }
+}
@@ -1,216 +1,83 @@
-use std::collections::HashMap;
+use syn::{parse_macro_input, DeriveInput};
 
-use proc_macro2::TokenStream;
-use quote::{quote, TokenStreamExt};
-use syn::{parse_macro_input, Data, DeriveInput, LitStr};
+mod key_switch_identifier;
+mod keymap;
+mod keymap_file;
+mod layer;
+mod layout;
 
-#[proc_macro_derive(Layer)]
+/// `Layer`を実装する
+///
+/// ヴァリアントに`#[layer(below = Default, name = "Sym", index = 3)]`のように書くと、
+/// 透過キーで落ちる先のレイヤ、表示用の名前、通し番号を変えられる。
+/// 落ちる先が循環しているとコンパイルエラーになる。
+#[proc_macro_derive(Layer, attributes(layer))]
 pub fn derive_layer(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
-    let name = ast.ident;
-    let variants = match ast.data {
-        Data::Enum(ref data) => data.variants.iter().map(|v| &v.ident),
-        _ => unimplemented!(),
-    }
-    .map(|v| {
-        quote! {
-            #name::#v,
-        }
-    })
-    .reduce(|mut acc, v| {
-        acc.append_all(v);
-        acc
-    });
-
-    let expanded = quote! {
-        impl rustkbd::keyboard::Layer for #name {
-            fn below(&self) -> Option<Self> {
-                let layers = [#variants];
-                layers
-                    .iter()
-                    .enumerate()
-                    .find(|(_, l)| l == &self)
-                    .and_then(|(i, _)| if i > 0 { layers.get(i - 1) } else { None })
-                    .copied()
-            }
-        }
-    };
-
-    proc_macro::TokenStream::from(expanded)
+    proc_macro::TokenStream::from(layer::derive(ast))
 }
 
-macro_rules! key {
-    ($n:tt, $i:ident) => {
-        ($n, quote!(rustkbd::keyboard::Key::$i))
-    };
-    ($i:ident) => {
-        (stringify!($i), quote!(rustkbd::keyboard::Key::$i))
-    };
+/// `KeySwitchIdentifier`と、バイト列との変換を実装する
+///
+/// フィールドは`u8`・`i8`・`u16`・`i16`・`u32`・`i32`・`bool`だけが使え、
+/// `SZ`はフィールドのバイト数の合計（列挙型ではヴァリアントの番号の1バイトを足したもの）になる。
+/// 読めないバイト列（範囲外のヴァリアントの番号や`bool`）は`TryFrom`がErrを返す。
+#[proc_macro_derive(KeySwitchIdentifier)]
+pub fn derive_key_switch_identifier(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    proc_macro::TokenStream::from(key_switch_identifier::derive(ast))
 }
 
+/// ASCIIの表からキーの2次元配列を作る
+///
+/// セルには記号（`"*"`や`"Esc"`）のほか、`Key`のヴァリアント名（`Lang1`、`Key::Lang1`）、
+/// 修飾キー付きのキー（`S(1)`、`C(Tab)`、`C(S(Tab))`）、
+/// レイヤタップ（`LT(Lower, Space)`。スコープにある`Layer`のヴァリアントか番号）、
+/// Unicodeの文字（`U(あ)`）が書ける。
+///
+/// 列の数と`|`の位置は揃えなければならない。
+/// `layout! {rows = 4, cols = 12, r"..."}`のように大きさを宣言すると、それとも比べる。
 #[proc_macro]
 pub fn layout(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let input = parse_macro_input!(input as LitStr).value();
-
-    let table = [
-        key!("", None),
-        key!("Trn", Transparent),
-        key!(A),
-        key!(B),
-        key!(C),
-        key!(D),
-        key!(E),
-        key!(F),
-        key!(G),
-        key!(H),
-        key!(I),
-        key!(J),
-        key!(K),
-        key!(L),
-        key!(M),
-        key!(N),
-        key!(O),
-        key!(P),
-        key!(Q),
-        key!(R),
-        key!(S),
-        key!(T),
-        key!(U),
-        key!(V),
-        key!(W),
-        key!(X),
-        key!(Y),
-        key!(Z),
-        key!("1", Digit1_Exclamation),
-        key!("2", Digit2_At),
-        key!("3", Digit3_Number),
-        key!("4", Digit4_Dollar),
-        key!("5", Digit5_Percent),
-        key!("6", Digit6_Circumflex),
-        key!("7", Digit7_Ampersand),
-        key!("8", Digit8_Asterisk),
-        key!("9", Digit9_LeftParenthesis),
-        key!("0", Digit0_RightParenthesis),
-        key!(Enter),
-        key!("Esc", Escape),
-        key!("Del", Delete),
-        key!(Tab),
-        key!(Space),
-        key!("-", HyphenMinus_LowLine),
-        key!("=", Equal_Plus),
-        key!("[", LeftSquareBracket_LeftCurlyBracket),
-        key!("]", RightSquareBracket_RightCurlyBracket),
-        key!("\\", Backslash_VerticalBar),
-        // key!("", NonUs_Number_Tilde),
-        key!(";", Semicolon_Colon),
-        key!("'", Apostrophe_Quotation),
-        key!("`", Grave_Tilde),
-        key!(",", Comma_LessThan),
-        key!(".", Period_GreaterThan),
-        key!("/", Slash_Question),
-        key!("Caps", CapsLock),
-        key!(F1),
-        key!(F2),
-        key!(F3),
-        key!(F4),
-        key!(F5),
-        key!(F6),
-        key!(F7),
-        key!(F8),
-        key!(F9),
-        key!(F10),
-        key!(F11),
-        key!(F12),
-        key!("PrScr", PrintScreen),
-        key!("ScLck", ScrollLock),
-        key!(Pause),
-        key!("Ins", Insert),
-        key!(Home),
-        key!("PgUp", PageUp),
-        key!("DelFw", DeleteForward),
-        key!(End),
-        key!("PgDn", PageDown),
-        key!("Right", RightArrow),
-        key!("Left", LeftArrow),
-        key!("Down", DownArrow),
-        key!("Up", UpArrow),
-        key!("LCtl", LeftControl),
-        key!("LSft", LeftShift),
-        key!("LAlt", LeftAlt),
-        key!("LGui", LeftGui),
-        key!("RCtl", RightControl),
-        key!("RSft", RightShift),
-        key!("RAlt", RightAlt),
-        key!("RGui", RightGui),
-        key!("MPlay", MediaPlay),
-        key!("MPau", MediaPause),
-        key!("MNext", MediaNextTrack),
-        key!("MPrev", MediaPrevTrack),
-        key!("MStop", MediaStop),
-        key!("MPlPs", MediaPlayPause),
-        key!("MMute", MediaMute),
-        key!("MVlUp", MediaVolumeIncrement),
-        key!("MVlDn", MediaVolumeDecrement),
-        key!("~", Tilde),
-        key!("!", Exclamation),
-        key!("@", At),
-        key!("#", Hash),
-        key!("$", Dollar),
-        key!("%", Percent),
-        key!("&", Ampersand),
-        key!("^", Circumflex),
-        key!("*", Asterisk),
-        key!("(", LeftParenthesis),
-        key!(")", RightParenthesis),
-        key!("_", LowLine),
-        key!("+", Plus),
-        key!("{", LeftCurlyBracket),
-        key!("}", RightCurlyBracket),
-        key!("Pipe", VerticalBar),
-        key!(":", Colon),
-        key!("\"", Quotation),
-        key!("<", LessThan),
-        key!(">", GreaterThan),
-        key!("?", Question),
-    ]
-    .clone()
-    .into_iter()
-    .collect::<HashMap<_, _>>();
-
-    let array = input
-        .trim()
-        .lines()
-        .map(&str::trim)
-        .map(|line| {
-            let array = line
-                .split('|')
-                .map(&str::trim)
-                .collect::<Vec<_>>()
-                .into_iter()
-                .skip(1)
-                .rev()
-                .skip(1)
-                .rev()
-                .map(|k| {
-                    if let Some(st) = table.get(k) {
-                        st.clone()
-                    } else {
-                        let message = "layout: Unknown symbol: ".to_string() + k;
-                        quote!(compile_error!(#message))
-                    }
-                })
-                .map(|t| quote! {#t,})
-                .collect::<TokenStream>();
-            quote! {
-                [#array]
-            }
-        })
-        .map(|t| quote! {#t,})
-        .collect::<TokenStream>();
+    let input = parse_macro_input!(input as layout::LayoutInput);
+    proc_macro::TokenStream::from(layout::expand(input))
+}
 
-    let expanded = quote! {
-        [#array]
-    };
+/// レイヤごとの表とレイヤを切り替えるスイッチから、`Layer`と`ArrayLayout`をまとめて作る
+///
+/// ```ignore
+/// keymap! {
+///     pub type Layout: 2, KeySwitchIdentifier;
+///     pub enum Layer;
+///     hold (3, 7) => Lower;
+///     layer Default r"...";
+///     layer Lower r"...";
+///     layer Raise below Default r"...";
+/// }
+/// ```
+///
+/// `Layout`は`ArrayLayout`の別名になり、初期状態の値は`LAYOUT`のように名前を大文字にした定数になる。
+/// 透過キーは`below`に書いたレイヤか、書かなければ1つ前のレイヤに落ちる。
+/// 各レイヤに左手側と右手側の2つの表を書くと分割キーボード用の`SplitArrayLayout`になり、
+/// スイッチの識別子は`SplitKeySwitchIdentifier`になる。
+/// スイッチの位置は`MatrixPosition`で決めるので、識別子はこれを実装していなければならない。
+#[proc_macro]
+pub fn keymap(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as keymap::KeymapInput);
+    proc_macro::TokenStream::from(keymap::expand(input))
+}
 
-    proc_macro::TokenStream::from(expanded)
+/// クレートのディレクトリからの相対パスにあるTOMLファイルから、`keymap!`と同じものを作る
+///
+/// ```ignore
+/// include_keymap!("keymap.toml");
+/// ```
+///
+/// 表には`layout!`と同じ記号が使える。`\`をそのまま書けるように、表は`'''`で囲む。
+/// ファイルの書き方は`rustkbd_keymap::KeymapFile`を参照。エラーにはファイルの行と列が入る。
+/// コンボにはまだ対応していないので、`[[combo]]`はエラーになる。
+#[proc_macro]
+pub fn include_keymap(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let path = parse_macro_input!(input as syn::LitStr);
+    proc_macro::TokenStream::from(keymap_file::expand(path))
 }
@@ -0,0 +1,11 @@
+//! `rustkbd`の`Controller`をホストで動かすシミュレータ
+//!
+//! スイッチの押下を時刻つきで与えると、送られたレポートを読める形の時系列にする。
+
+mod matrix_switch;
+mod render;
+mod simulator;
+
+pub use matrix_switch::MatrixSwitch;
+pub use render::render;
+pub use simulator::{Event, Simulator};
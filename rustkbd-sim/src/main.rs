@@ -0,0 +1,191 @@
+//! necoboard v1のキーマップをホストで試す
+//!
+//! `cargo sim`で端末から、`cargo sim --script <file>`で台本から動かす。
+//! 端末ではキーボードの`1234567890-=`、`qwertyuiop[]`、`asdfghjkl;'\`、`zxcvbnm,./`の各段が
+//! キーマップの各行に対応する。Ctrl-Cで終わる。
+
+use std::{
+    cell::Cell,
+    io::{self, Write as _},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    event::{
+        self, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute,
+    terminal::{self, supports_keyboard_enhancement},
+};
+use rustkbd::keyboard::{include_keymap, DynamicKeymap as _};
+use rustkbd_sim::{render, MatrixSwitch, Simulator};
+
+mod switch_identifier {
+    pub use rustkbd_sim::MatrixSwitch as KeySwitchIdentifier;
+}
+
+include_keymap!("../necoboard-v1/keymap.toml");
+
+type Sim<'a> = Simulator<'a, 2, 6, Layout>;
+
+/// キーを離したことがわからない端末で、押してから離したことにするまでの時間
+const TAP_MS: u32 = 100;
+
+/// 端末のキーとキーマップの行の対応
+const ROWS: [&str; 4] = [
+    "1234567890-=",
+    "qwertyuiop[]",
+    "asdfghjkl;'\\",
+    "zxcvbnm,./",
+];
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.as_slice() {
+        [] => interactive().map_err(|e| e.to_string()),
+        [option, path] if option == "--script" => script(path),
+        _ => Err("usage: cargo sim [--script <file>]".to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// すべてのスイッチを行列に並べたもの
+fn switches() -> Vec<Vec<MatrixSwitch>> {
+    (0..LAYOUT.rows() as u8)
+        .map(|row| {
+            (0..LAYOUT.cols() as u8)
+                .map(|col| MatrixSwitch::new(row, col))
+                .collect()
+        })
+        .collect()
+}
+
+/// `press <行> <列>`、`release <行> <列>`、`wait <ミリ秒>`を1行ずつ書いた台本を動かす
+fn script(path: &str) -> Result<(), String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let clock = Cell::new(0);
+    let mut simulator = Sim::new(LAYOUT, &clock);
+    for (number, line) in content.lines().enumerate() {
+        let error = || format!("{path}:{}: cannot read `{line}`", number + 1);
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let switch =
+            |row: &str, col: &str| Some(MatrixSwitch::new(row.parse().ok()?, col.parse().ok()?));
+        match words.as_slice() {
+            [] => {}
+            [comment, ..] if comment.starts_with('#') => {}
+            ["press", row, col] => simulator.press(switch(row, col).ok_or_else(error)?),
+            ["release", row, col] => simulator.release(switch(row, col).ok_or_else(error)?),
+            ["wait", ms] => simulator.advance(ms.parse().map_err(|_| error())?),
+            _ => return Err(error()),
+        }
+    }
+    // 最後の操作の結果まで出す
+    simulator.advance(1);
+    for event in simulator.timeline() {
+        println!("{event}");
+    }
+    Ok(())
+}
+
+fn interactive() -> io::Result<()> {
+    let mut stdout = io::stdout();
+    let enhanced = supports_keyboard_enhancement().unwrap_or(false);
+    terminal::enable_raw_mode()?;
+    if enhanced {
+        let flags = KeyboardEnhancementFlags::REPORT_EVENT_TYPES;
+        execute!(stdout, PushKeyboardEnhancementFlags(flags))?;
+    }
+    let result = run(&mut stdout, enhanced);
+    if enhanced {
+        execute!(stdout, PopKeyboardEnhancementFlags)?;
+    }
+    terminal::disable_raw_mode()?;
+    result
+}
+
+fn run(stdout: &mut io::Stdout, enhanced: bool) -> io::Result<()> {
+    let clock = Cell::new(0);
+    let mut simulator = Sim::new(LAYOUT, &clock);
+    let switches = switches();
+    let mut print = |text: String| write!(stdout, "{}", text.replace('\n', "\r\n"));
+    if !enhanced {
+        print(format!(
+            "This terminal does not report key releases; keys are tapped for {TAP_MS}ms.\n"
+        ))?;
+    }
+    print(render(
+        &simulator.controller.layout,
+        Default::default(),
+        &switches,
+        &[],
+    ))?;
+
+    let start = Instant::now();
+    let mut releases = Vec::<(u32, MatrixSwitch)>::new();
+    let mut printed = 0;
+    loop {
+        if event::poll(Duration::from_millis(1))? {
+            if let event::Event::Key(key) = event::read()? {
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    return Ok(());
+                }
+                if let Some(switch) = switch(key.code) {
+                    match key.kind {
+                        KeyEventKind::Press => {
+                            simulator.press(switch);
+                            if !enhanced {
+                                releases.push((simulator.time() + TAP_MS, switch));
+                            }
+                        }
+                        KeyEventKind::Release => simulator.release(switch),
+                        KeyEventKind::Repeat => {}
+                    }
+                }
+            }
+        }
+        let now = start.elapsed().as_millis() as u32;
+        releases.retain(|(time, switch)| {
+            if *time <= now {
+                simulator.release(*switch);
+            }
+            *time > now
+        });
+        simulator.advance(now.saturating_sub(simulator.time()));
+
+        for event in simulator.timeline()[printed..].iter() {
+            print(format!("{event}\n"))?;
+            let layer_changed =
+                printed == 0 || simulator.timeline()[printed - 1].layer != event.layer;
+            if layer_changed {
+                let state = simulator.controller.get_state();
+                let pressed = simulator.controller.key_switches.pressed();
+                print(render(
+                    &simulator.controller.layout,
+                    state.layer,
+                    &switches,
+                    pressed,
+                ))?;
+            }
+            printed += 1;
+        }
+    }
+}
+
+fn switch(code: KeyCode) -> Option<MatrixSwitch> {
+    let KeyCode::Char(c) = code else {
+        return None;
+    };
+    let c = c.to_ascii_lowercase();
+    ROWS.iter().enumerate().find_map(|(row, keys)| {
+        let col = keys.chars().position(|k| k == c)?;
+        Some(MatrixSwitch::new(row as u8, col as u8))
+    })
+}
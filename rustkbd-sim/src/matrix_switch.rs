@@ -0,0 +1,20 @@
+use rustkbd::keyboard::{KeySwitchIdentifier, MatrixPosition};
+
+/// 行と列で表すスイッチ
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, KeySwitchIdentifier)]
+pub struct MatrixSwitch {
+    pub row: u8,
+    pub col: u8,
+}
+
+impl MatrixSwitch {
+    pub const fn new(row: u8, col: u8) -> MatrixSwitch {
+        MatrixSwitch { row, col }
+    }
+}
+
+impl MatrixPosition for MatrixSwitch {
+    fn position(&self, _rows: usize, _cols: usize) -> (usize, usize) {
+        (self.row as usize, self.col as usize)
+    }
+}
@@ -0,0 +1,62 @@
+use rustkbd::keyboard::{Key, Layer, Layout};
+
+/// `layer`のキーマップを表にする。透過キーは落ちた先のキーを、押されているスイッチは`[]`で囲んで書く
+pub fn render<const SZ: usize, L: Layout<SZ>>(
+    layout: &L,
+    layer: L::Layer,
+    switches: &[Vec<L::Identifier>],
+    pressed: &[L::Identifier],
+) -> String {
+    let labels = switches
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|switch| {
+                    let label = match key(layout, layer, switch) {
+                        Key::None => String::new(),
+                        key => format!("{key:?}"),
+                    };
+                    if pressed.contains(switch) {
+                        format!("[{label}]")
+                    } else {
+                        label
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let width = labels
+        .iter()
+        .flatten()
+        .map(|label| label.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+
+    let mut out = format!("{}\n", layer.name());
+    for row in labels {
+        out.push('|');
+        for label in row {
+            out += &format!("{label:^width$}|");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// `Controller`と同じく`below`をたどって透過キーを解決する
+fn key<const SZ: usize, L: Layout<SZ>>(
+    layout: &L,
+    mut layer: L::Layer,
+    switch: &L::Identifier,
+) -> Key {
+    let mut key = layout.key(layer, switch);
+    for _ in 0..L::Layer::ALL.len() {
+        let Some(below) = layer.below().filter(|_| key == Key::Transparent) else {
+            break;
+        };
+        layer = below;
+        key = layout.key(layer, switch);
+    }
+    key
+}
@@ -0,0 +1,151 @@
+use std::{cell::Cell, fmt};
+
+use rustkbd::{
+    keyboard::{Controller, Layer, Layout},
+    scenario::{MockSwitches, Report, ReportRecorder},
+};
+
+/// 同時に押せるスイッチの数
+const PRESSED: usize = 32;
+
+/// 1ミリ秒の間に送られるレポートの数
+const REPORTS: usize = 4;
+
+/// レポートかレイヤが変わった時刻
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// 始めてからのミリ秒
+    pub time: u32,
+    pub layer: &'static str,
+    pub report: Report,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>6}ms  {:<8} {}", self.time, self.layer, self.report)
+    }
+}
+
+/// 1ミリ秒ごとに`main_loop`と`send_keys`を呼ぶ`Controller`
+///
+/// 時刻は`clock`に書く。`ReportRecorder`はこれを読んでレポートに時刻をつける。
+pub struct Simulator<'a, const SZ: usize, const RO: usize, L: Layout<SZ>> {
+    pub controller:
+        Controller<SZ, RO, ReportRecorder<'a, REPORTS>, MockSwitches<L::Identifier, PRESSED>, L>,
+    clock: &'a Cell<u32>,
+    timeline: Vec<Event>,
+}
+
+impl<'a, const SZ: usize, const RO: usize, L: Layout<SZ>> Simulator<'a, SZ, RO, L> {
+    pub fn new(layout: L, clock: &'a Cell<u32>) -> Self {
+        Simulator {
+            controller: Controller::new(ReportRecorder::new(clock), MockSwitches::new(), layout),
+            clock,
+            timeline: Vec::new(),
+        }
+    }
+
+    pub fn press(&mut self, switch: L::Identifier) {
+        self.controller.key_switches.press(switch);
+    }
+
+    pub fn release(&mut self, switch: L::Identifier) {
+        self.controller.key_switches.release(switch);
+    }
+
+    /// `ms`ミリ秒進める
+    pub fn advance(&mut self, ms: u32) {
+        for _ in 0..ms {
+            self.controller.main_loop();
+            self.controller.send_keys().ok();
+            let time = self.time();
+            let layer = self.controller.get_state().layer.name();
+            // 始めはデフォルトのレイヤで何も押されていない
+            let (last_layer, last_report) = self
+                .timeline
+                .last()
+                .map_or((L::Layer::default().name(), Report::new(0, &[])), |last| {
+                    (last.layer, last.report)
+                });
+            // `ReportRecorder`は変わったレポートだけを記録している
+            let report = self.controller.communicator.take().last().copied();
+            if report.is_some() || layer != last_layer {
+                self.timeline.push(Event {
+                    time,
+                    layer,
+                    report: report.unwrap_or(last_report),
+                });
+            }
+            self.clock.set(time + 1);
+        }
+    }
+
+    pub fn time(&self) -> u32 {
+        self.clock.get()
+    }
+
+    /// これまでに変わったところ
+    pub fn timeline(&self) -> &[Event] {
+        &self.timeline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustkbd::keyboard::{keymap, Key};
+
+    use super::*;
+    use crate::MatrixSwitch;
+
+    keymap! {
+        pub type Layout: 2, crate::MatrixSwitch;
+        pub enum Layer;
+        hold (1, 0) => Lower;
+        layer Default r"
+            |  A  |  B  |
+            |     | LSft|
+        ";
+        layer Lower r"
+            |  1  | Trn |
+            | Trn | Trn |
+        ";
+    }
+
+    #[test]
+    // レイヤの切り替えと透過キーがレポートの時系列に出る
+    fn test_timeline() {
+        let clock = Cell::new(0);
+        let mut simulator = Simulator::<2, 6, _>::new(LAYOUT, &clock);
+        simulator.press(MatrixSwitch::new(1, 0));
+        simulator.advance(10);
+        simulator.press(MatrixSwitch::new(0, 0));
+        simulator.press(MatrixSwitch::new(0, 1));
+        simulator.advance(10);
+        simulator.release(MatrixSwitch::new(1, 0));
+        simulator.release(MatrixSwitch::new(0, 0));
+        simulator.release(MatrixSwitch::new(0, 1));
+        simulator.advance(10);
+
+        let timeline = simulator
+            .timeline()
+            .iter()
+            .map(|event| (event.time, event.layer, event.report.keys().to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (0, "Lower", vec![]),
+                (10, "Lower", vec![Key::Digit1_Exclamation, Key::B]),
+                (20, "Default", vec![]),
+            ],
+            timeline
+        );
+        assert_eq!(
+            "    10ms  Lower    Digit1_Exclamation + B",
+            simulator.timeline()[1].to_string()
+        );
+        assert_eq!(
+            "    20ms  Default  (none)",
+            simulator.timeline()[2].to_string()
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use rustkbd::keyboard::Key;
+use rustkbd_keymap::SYMBOLS;
+
+/// `layout!`の修飾キーの書き方。外側に書くものから順に並べる
+const MODIFIERS: [(u8, &str); 8] = [
+    (0x01, "C"),
+    (0x02, "S"),
+    (0x04, "A"),
+    (0x08, "G"),
+    (0x10, "RC"),
+    (0x20, "RS"),
+    (0x40, "RA"),
+    (0x80, "RG"),
+];
+
+/// `layout!`の表に書くセル。記号があれば記号、なければヴァリアント名にする
+pub(crate) fn cell(key: Key) -> String {
+    match key {
+        Key::Unicode(c) => format!("U({c})"),
+        Key::WithModifiers(modifiers, usage) => MODIFIERS
+            .iter()
+            .rev()
+            .filter(|(flag, _)| modifiers & flag != 0)
+            .fold(usage_cell(usage), |inner, (_, wrapper)| {
+                format!("{wrapper}({inner})")
+            }),
+        Key::LayerTap(layer, usage) => format!("LT({layer}, {})", usage_cell(usage)),
+        key => {
+            let name = format!("{key:?}");
+            SYMBOLS
+                .iter()
+                .find(|(_, n)| *n == name)
+                .map_or(name, |(symbol, _)| symbol.to_string())
+        }
+    }
+}
+
+fn usage_cell(usage: u8) -> String {
+    Key::try_from(usage as u16).map_or_else(|_| format!("{usage:#04x}"), cell)
+}
+
+/// セルを同じ幅で中央に並べた1行
+pub(crate) fn row(cells: &[String], width: usize) -> String {
+    let mut row = String::from("|");
+    for cell in cells {
+        let padding = width.saturating_sub(cell.chars().count());
+        let left = padding.div_ceil(2);
+        row.push_str(&" ".repeat(left));
+        row.push_str(cell);
+        row.push_str(&" ".repeat(padding - left));
+        row.push('|');
+    }
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 記号のあるキーは記号に、修飾キー付きのキーは入れ子にする
+    fn test_cell() {
+        assert_eq!("Esc", cell(Key::Escape));
+        assert_eq!("", cell(Key::None));
+        assert_eq!("Pipe", cell(Key::VerticalBar));
+        assert_eq!("Lang1", cell(Key::Lang1));
+        assert_eq!("C(S(Tab))", cell(Key::WithModifiers(0x03, 0x2b)));
+        assert_eq!("LT(1, Space)", cell(Key::LayerTap(1, 0x2c)));
+        assert_eq!("U(あ)", cell(Key::Unicode('あ')));
+    }
+
+    #[test]
+    // 幅に足りない分は左右に振り分ける
+    fn test_row() {
+        let cells = ["Esc", "Q", "LCtl", "Enter"].map(String::from);
+        assert_eq!("| Esc |  Q  | LCtl|Enter|", row(&cells, 5));
+    }
+}
@@ -0,0 +1,238 @@
+use std::{collections::HashMap, fs};
+
+use rustkbd::keyboard::Key;
+use serde::Deserialize;
+
+use crate::{
+    cell::{cell, row},
+    kle,
+    qmk::{self, Keycode},
+};
+
+/// QMKの`keymap.json`のうち使うところ
+#[derive(Deserialize)]
+struct QmkKeymap {
+    layers: Vec<Vec<String>>,
+}
+
+/// 変換したキーマップ
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Keymap {
+    /// レイヤ・行・列の順のセル
+    pub(crate) layers: Vec<Vec<Vec<String>>>,
+    /// (行, 列, レイヤ)。押している間そのレイヤになるスイッチ
+    pub(crate) holds: Vec<(usize, usize, u8)>,
+}
+
+pub(crate) fn run(options: &HashMap<&str, &str>) -> Result<(), String> {
+    let read = |path: &str| fs::read_to_string(path).map_err(|e| format!("{path}: {e}"));
+    let qmk_path = options.get("qmk").ok_or("`--qmk` is required")?;
+    let qmk: QmkKeymap =
+        serde_json::from_str(&read(qmk_path)?).map_err(|e| format!("{qmk_path}: {e}"))?;
+    let keys = qmk.layers.first().map_or(0, Vec::len);
+    let positions = match (options.get("kle"), options.get("cols")) {
+        (Some(path), _) => kle::positions(&read(path)?).map_err(|e| format!("{path}: {e}"))?,
+        (None, Some(cols)) => {
+            let cols = cols.parse::<usize>().map_err(|e| format!("--cols: {e}"))?;
+            (0..keys)
+                .map(|i| (i / cols.max(1), i % cols.max(1)))
+                .collect()
+        }
+        (None, None) => return Err("either `--kle` or `--cols` is required".to_string()),
+    };
+
+    let (keymap, diagnostics) = convert(&qmk.layers, &positions)?;
+    for diagnostic in diagnostics.iter() {
+        eprintln!("warning: {diagnostic}");
+    }
+    let identifier = options
+        .get("identifier")
+        .unwrap_or(&"crate::switch_identifier::KeySwitchIdentifier");
+    let size = options.get("size").unwrap_or(&"2");
+    match options.get("format").copied().unwrap_or("toml") {
+        "toml" => print!("{}", to_toml(&keymap, identifier, size)),
+        "rust" => print!("{}", to_rust(&keymap, identifier, size)),
+        format => return Err(format!("unknown format `{format}`")),
+    }
+    Ok(())
+}
+
+/// QMKのレイヤを行列に並べ直す。変換できなかったキーは空にして、理由を返す
+pub(crate) fn convert(
+    layers: &[Vec<String>],
+    positions: &[(usize, usize)],
+) -> Result<(Keymap, Vec<String>), String> {
+    let rows = positions.iter().map(|(r, _)| r + 1).max().unwrap_or(0);
+    let cols = positions.iter().map(|(_, c)| c + 1).max().unwrap_or(0);
+    let mut diagnostics = Vec::new();
+    let mut holds = Vec::<(usize, usize, u8)>::new();
+    let mut grids = Vec::new();
+    for (layer, keycodes) in layers.iter().enumerate() {
+        if keycodes.len() != positions.len() {
+            return Err(format!(
+                "layer {layer} has {} keys but the layout has {}",
+                keycodes.len(),
+                positions.len()
+            ));
+        }
+        let mut grid = vec![vec![String::new(); cols]; rows];
+        for (i, (keycode, &(row, col))) in keycodes.iter().zip(positions).enumerate() {
+            let at = format!("layer {layer}, key {i} (row {row}, column {col})");
+            let key = match qmk::parse(keycode) {
+                Ok(Keycode::Key(key)) => key,
+                Ok(Keycode::Momentary(target)) => {
+                    if target as usize >= layers.len() {
+                        diagnostics.push(format!("{at}: `{keycode}` switches to a missing layer"));
+                    } else if let Some((_, _, other)) =
+                        holds.iter().find(|(r, c, _)| (*r, *c) == (row, col))
+                    {
+                        if *other != target {
+                            diagnostics.push(format!(
+                                "{at}: `{keycode}` conflicts with MO({other}) on the same switch"
+                            ));
+                        }
+                    } else {
+                        holds.push((row, col, target));
+                    }
+                    Key::None
+                }
+                Err(reason) => {
+                    diagnostics.push(format!("{at}: {reason}, left empty"));
+                    Key::None
+                }
+            };
+            grid[row][col] = cell(key);
+        }
+        grids.push(grid);
+    }
+    Ok((
+        Keymap {
+            layers: grids,
+            holds,
+        },
+        diagnostics,
+    ))
+}
+
+fn layer_name(layer: usize) -> String {
+    if layer == 0 {
+        "Default".to_string()
+    } else {
+        format!("Layer{layer}")
+    }
+}
+
+/// すべてのセルが収まる幅。ボードの表に合わせて5文字以上にする
+fn width(keymap: &Keymap) -> usize {
+    keymap
+        .layers
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|cell| cell.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(5)
+}
+
+fn to_toml(keymap: &Keymap, identifier: &str, size: &str) -> String {
+    let width = width(keymap);
+    let mut out = format!(
+        "[keymap]\nlayout = \"Layout\"\nlayer = \"Layer\"\nidentifier = \"{identifier}\"\nsize = {size}\n"
+    );
+    for (row, col, layer) in keymap.holds.iter() {
+        let layer = layer_name(*layer as usize);
+        out += &format!("\n[[hold]]\nswitch = [{row}, {col}]\nlayer = \"{layer}\"\n");
+    }
+    for (layer, grid) in keymap.layers.iter().enumerate() {
+        out += &format!(
+            "\n[[layer]]\nname = \"{}\"\nkeys = '''\n",
+            layer_name(layer)
+        );
+        for cells in grid {
+            out += &row(cells, width);
+            out.push('\n');
+        }
+        out += "'''\n";
+    }
+    out
+}
+
+fn to_rust(keymap: &Keymap, identifier: &str, size: &str) -> String {
+    let width = width(keymap);
+    let mut out = format!(
+        "use rustkbd::keyboard::keymap;\n\nkeymap! {{\n    pub type Layout: {size}, {identifier};\n    pub enum Layer;\n"
+    );
+    for (row, col, layer) in keymap.holds.iter() {
+        out += &format!(
+            "    hold ({row}, {col}) => {};\n",
+            layer_name(*layer as usize)
+        );
+    }
+    for (layer, grid) in keymap.layers.iter().enumerate() {
+        out += &format!("    layer {} r#\"\n", layer_name(layer));
+        for cells in grid {
+            out += &format!("            {}\n", row(cells, width));
+        }
+        out += "        \"#;\n";
+    }
+    out += "}\n";
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layers(layers: &[&[&str]]) -> Vec<Vec<String>> {
+        layers
+            .iter()
+            .map(|keys| keys.iter().map(|k| k.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    // MOはレイヤを切り替えるスイッチになり、変換できないキーは空になる
+    fn test_convert() {
+        let layers = layers(&[
+            &["KC_ESC", "KC_Q", "MO(1)", "LT(1, KC_SPC)"],
+            &["_______", "RGB_TOG", "_______", "S(KC_1)"],
+        ]);
+        let positions = [(0, 0), (0, 1), (1, 1), (1, 0)];
+        let (keymap, diagnostics) = convert(&layers, &positions).unwrap();
+        assert_eq!(
+            vec![
+                vec![
+                    vec!["Esc".to_string(), "Q".to_string()],
+                    vec!["LT(1, Space)".to_string(), "".to_string()]
+                ],
+                vec![
+                    vec!["Trn".to_string(), "".to_string()],
+                    vec!["!".to_string(), "Trn".to_string()]
+                ],
+            ],
+            keymap.layers
+        );
+        assert_eq!(vec![(1, 1, 1)], keymap.holds);
+        assert_eq!(
+            vec!["layer 1, key 1 (row 0, column 1): `RGB_TOG` is not supported, left empty"],
+            diagnostics
+        );
+
+        let toml = to_toml(&keymap, "Id", "2");
+        assert!(toml.contains("[[hold]]\nswitch = [1, 1]\nlayer = \"Layer1\"\n"));
+        assert!(toml.contains("|LT(1, Space)|            |\n"));
+        let rust = to_rust(&keymap, "Id", "2");
+        assert!(rust.contains("    hold (1, 1) => Layer1;\n"));
+    }
+
+    #[test]
+    // キーの数が合わないときはエラー
+    fn test_key_count() {
+        let layers = layers(&[&["KC_A", "KC_B"]]);
+        assert_eq!(
+            Err("layer 0 has 2 keys but the layout has 3".to_string()),
+            convert(&layers, &[(0, 0), (0, 1), (0, 2)])
+        );
+    }
+}
@@ -0,0 +1,509 @@
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use rustkbd_keymap::{split_grid, KeymapFile, Position, Side};
+use syn::LitStr;
+
+/// 表のまま読んだキーマップ
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Keymap {
+    pub(crate) layers: Vec<Layer>,
+    pub(crate) holds: Vec<Hold>,
+    /// 分割キーボードなら片手の行数。右手側は左手側の下に並べる
+    pub(crate) half_rows: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Layer {
+    pub(crate) name: String,
+    /// 透過キーで落ちる先のレイヤの名前。なければ1つ前のレイヤに落ちる
+    pub(crate) below: Option<String>,
+    /// 行・列の順のセル
+    pub(crate) cells: Vec<Vec<String>>,
+}
+
+/// 押している間`layer`になるスイッチ
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Hold {
+    pub(crate) position: (usize, usize),
+    pub(crate) layer: String,
+}
+
+/// 読んだままのレイヤ
+struct Source {
+    name: String,
+    below: Option<String>,
+    tables: Vec<String>,
+}
+
+impl Keymap {
+    /// 拡張子が`.rs`なら`keymap!`や`layout!`の書かれたソースとして、そうでなければ`keymap.toml`として読む
+    pub(crate) fn read(path: &str) -> Result<Keymap, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let keymap = if path.ends_with(".rs") {
+            Keymap::from_rust(&content)
+        } else {
+            Keymap::from_toml(&content)
+        };
+        keymap.map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// `include_keymap!`と同じスキーマで読む
+    pub(crate) fn from_toml(content: &str) -> Result<Keymap, String> {
+        let file = KeymapFile::parse(content).map_err(|error| match error.offset {
+            Some(offset) => {
+                let Position { line, column } = Position::new(content, offset);
+                format!("line {line}, column {column}: {}", error.message)
+            }
+            None => error.message,
+        })?;
+        let mut sources = Vec::new();
+        for layer in file.layer.iter() {
+            let tables = layer.tables().map_err(|error| error.message)?;
+            sources.push(Source {
+                name: layer.name.get_ref().clone(),
+                below: layer.below.as_ref().map(|below| below.get_ref().clone()),
+                tables: tables.into_iter().map(|t| t.get_ref().clone()).collect(),
+            });
+        }
+        let holds = file
+            .hold
+            .into_iter()
+            .map(|hold| (hold.side, hold.switch, hold.layer.into_inner()))
+            .collect();
+        Keymap::new(sources, holds)
+    }
+
+    /// Rustのソースから`keymap!`の`layer`と`hold`を拾う
+    ///
+    /// `keymap!`の外にある`layout!`の表は、見つけた順に`#[derive(Layer)]`のヴァリアントの名前にする。
+    /// `derive(Layer)`がなければ`Layer0`、`Layer1`…とする。落ちる先は`#[layer(below = ..)]`から読む。
+    pub(crate) fn from_rust(content: &str) -> Result<Keymap, String> {
+        let tokens = content.parse::<TokenStream>().map_err(|e| e.to_string())?;
+        let mut found = Found::default();
+        scan(tokens, &mut found);
+        for (i, source) in found.sources.iter_mut().enumerate() {
+            if source.name == format!("Layer{i}") {
+                if let Some((name, _)) = found.variants.get(i) {
+                    source.name = name.clone();
+                }
+            }
+            if let Some((_, below)) = found.variants.iter().find(|(name, _)| *name == source.name) {
+                source.below = source.below.take().or(below.clone());
+            }
+        }
+        Keymap::new(found.sources, found.holds)
+    }
+
+    /// 表が2つのレイヤなら分割キーボード
+    fn new(sources: Vec<Source>, holds: Holds) -> Result<Keymap, String> {
+        let split = sources
+            .first()
+            .is_some_and(|source| source.tables.len() == 2);
+        let mut half_rows = None;
+        let mut layers = Vec::new();
+        for Source {
+            name,
+            below,
+            tables,
+        } in sources
+        {
+            if tables.len() != if split { 2 } else { 1 } {
+                return Err(format!("layer `{name}` has a different number of tables"));
+            }
+            let mut cells = Vec::new();
+            for table in tables.iter() {
+                let rows = parse_table(table).map_err(|e| format!("layer `{name}`: {e}"))?;
+                half_rows = half_rows.or(Some(rows.len()));
+                cells.extend(rows);
+            }
+            layers.push(Layer { name, below, cells });
+        }
+        let half_rows = half_rows.filter(|_| split);
+
+        let holds = holds
+            .into_iter()
+            .map(|(side, (row, col), layer)| {
+                let row = match side {
+                    None | Some(Side::Left) => row,
+                    Some(Side::Right) => half_rows.unwrap_or(0) + row,
+                };
+                Hold {
+                    position: (row, col),
+                    layer,
+                }
+            })
+            .collect();
+        Ok(Keymap {
+            layers,
+            holds,
+            half_rows,
+        })
+    }
+
+    /// `layer`が透過キーで落ちる先のレイヤの番号。`below`がなければ1つ前のレイヤに落ちる
+    pub(crate) fn below(&self, layer: usize) -> Option<usize> {
+        match &self.layers[layer].below {
+            Some(below) => self.layers.iter().position(|layer| layer.name == *below),
+            None => layer.checked_sub(1),
+        }
+    }
+
+    /// 透過キーを`below`を辿って解決したセルと、それが見つかったレイヤ。`below`が循環していればレイヤの数だけ辿ってやめる
+    pub(crate) fn resolve(&self, layer: usize, row: usize, col: usize) -> (&str, usize) {
+        let mut current = layer;
+        for _ in 0..self.layers.len() {
+            let cell = self.cell(current, row, col);
+            match self.below(current) {
+                Some(below) if is_transparent(cell) => current = below,
+                _ => break,
+            }
+        }
+        (self.cell(current, row, col), current)
+    }
+
+    pub(crate) fn cell(&self, layer: usize, row: usize, col: usize) -> &str {
+        self.layers[layer]
+            .cells
+            .get(row)
+            .and_then(|cells| cells.get(col))
+            .map_or("", String::as_str)
+    }
+
+    /// 1つ目のレイヤの表の行数と列数
+    pub(crate) fn shape(&self) -> (usize, usize) {
+        self.layers.first().map_or((0, 0), |layer| {
+            (
+                layer.cells.len(),
+                layer.cells.iter().map(Vec::len).max().unwrap_or(0),
+            )
+        })
+    }
+
+    pub(crate) fn hold(&self, row: usize, col: usize) -> Option<&str> {
+        self.holds
+            .iter()
+            .find(|hold| hold.position == (row, col))
+            .map(|hold| hold.layer.as_str())
+    }
+}
+
+pub(crate) fn is_transparent(cell: &str) -> bool {
+    matches!(cell, "Trn" | "Transparent")
+}
+
+/// `|`で区切られた表をセルに分ける。空行は読み飛ばす
+fn parse_table(table: &str) -> Result<Vec<Vec<String>>, String> {
+    let (grid, errors) = split_grid(table, None);
+    if let Some(error) = errors.into_iter().next() {
+        return Err(error.message);
+    }
+    Ok(grid
+        .into_iter()
+        .map(|cells| {
+            cells
+                .into_iter()
+                .map(|cell| cell.text.to_string())
+                .collect()
+        })
+        .collect())
+}
+
+type Holds = Vec<(Option<Side>, (usize, usize), String)>;
+
+/// Rustのソースで見つけたもの
+#[derive(Default)]
+struct Found {
+    sources: Vec<Source>,
+    holds: Holds,
+    /// `#[derive(Layer)]`のヴァリアントと、`#[layer(below = ..)]`で書いた落ちる先
+    variants: Vec<(String, Option<String>)>,
+}
+
+fn scan(tokens: TokenStream, found: &mut Found) {
+    let tokens = tokens.into_iter().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            TokenTree::Ident(ident) if ident == "layer" => {
+                if let Some(TokenTree::Ident(name)) = tokens.get(i + 1) {
+                    let mut start = i + 2;
+                    let below = match (tokens.get(start), tokens.get(start + 1)) {
+                        (Some(TokenTree::Ident(keyword)), Some(TokenTree::Ident(below)))
+                            if keyword == "below" =>
+                        {
+                            start += 2;
+                            Some(below.to_string())
+                        }
+                        _ => None,
+                    };
+                    let tables = tokens[start..].iter().map_while(string).collect::<Vec<_>>();
+                    if !tables.is_empty() {
+                        i = start + tables.len();
+                        found.sources.push(Source {
+                            name: name.to_string(),
+                            below,
+                            tables,
+                        });
+                        continue;
+                    }
+                }
+            }
+            TokenTree::Ident(ident) if ident == "hold" => {
+                let side = match tokens.get(i + 1) {
+                    Some(TokenTree::Ident(side)) if side == "Left" => Some(Side::Left),
+                    Some(TokenTree::Ident(side)) if side == "Right" => Some(Side::Right),
+                    _ => None,
+                };
+                let start = i + 1 + side.is_some() as usize;
+                if let (Some(TokenTree::Group(group)), Some(TokenTree::Ident(layer))) =
+                    (tokens.get(start), tokens.get(start + 3))
+                {
+                    let numbers = group
+                        .stream()
+                        .into_iter()
+                        .filter_map(|token| match token {
+                            TokenTree::Literal(literal) => literal.to_string().parse().ok(),
+                            _ => None,
+                        })
+                        .collect::<Vec<usize>>();
+                    if let [row, col] = numbers[..] {
+                        found.holds.push((side, (row, col), layer.to_string()));
+                        i = start + 4;
+                        continue;
+                    }
+                }
+            }
+            TokenTree::Ident(ident) if ident == "layout" => {
+                if let (Some(TokenTree::Punct(bang)), Some(TokenTree::Group(group))) =
+                    (tokens.get(i + 1), tokens.get(i + 2))
+                {
+                    let table = group.stream().into_iter().find_map(|t| string(&t));
+                    if let (Some(table), '!') = (table, bang.as_char()) {
+                        found.sources.push(Source {
+                            name: format!("Layer{}", found.sources.len()),
+                            below: None,
+                            tables: vec![table],
+                        });
+                        i += 3;
+                        continue;
+                    }
+                }
+            }
+            TokenTree::Ident(ident) if ident == "enum" && derives_layer(&tokens[..i]) => {
+                if let Some(TokenTree::Group(body)) = tokens.get(i + 2) {
+                    found.variants = variants(body.stream());
+                    i += 3;
+                    continue;
+                }
+            }
+            TokenTree::Group(group) => scan(group.stream(), found),
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+/// `#[layer(below = X)]`の`[...]`なら`X`
+fn layer_below(token: &TokenTree) -> Option<String> {
+    let TokenTree::Group(group) = token else {
+        return None;
+    };
+    if group.delimiter() != Delimiter::Bracket {
+        return None;
+    }
+    let tokens = group.stream().into_iter().collect::<Vec<_>>();
+    let (Some(TokenTree::Ident(layer)), Some(TokenTree::Group(args))) =
+        (tokens.first(), tokens.get(1))
+    else {
+        return None;
+    };
+    if layer != "layer" {
+        return None;
+    }
+    let args = args.stream().into_iter().collect::<Vec<_>>();
+    args.windows(3).find_map(|window| match window {
+        [TokenTree::Ident(key), TokenTree::Punct(eq), TokenTree::Ident(value)]
+            if key == "below" && eq.as_char() == '=' =>
+        {
+            Some(value.to_string())
+        }
+        _ => None,
+    })
+}
+
+/// `enum`の前の属性に`#[derive(.., Layer)]`があるか
+fn derives_layer(before: &[TokenTree]) -> bool {
+    let mut tokens = before.iter().rev().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ident) if ident == "pub" => {}
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Parenthesis => {}
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Bracket => {
+                let derive = group.stream().into_iter().collect::<Vec<_>>();
+                if let [TokenTree::Ident(name), TokenTree::Group(args)] = &derive[..] {
+                    let layer = args
+                        .stream()
+                        .into_iter()
+                        .any(|token| matches!(token, TokenTree::Ident(ident) if ident == "Layer"));
+                    if name == "derive" && layer {
+                        return true;
+                    }
+                }
+                // `#`を読み飛ばす
+                tokens.next();
+            }
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// `enum`の中身のヴァリアントの名前と、`#[layer(below = ..)]`で書いた落ちる先
+fn variants(body: TokenStream) -> Vec<(String, Option<String>)> {
+    let tokens = body.into_iter().collect::<Vec<_>>();
+    let mut variants = Vec::new();
+    let mut below = None;
+    let mut expect = true;
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            TokenTree::Punct(punct) if punct.as_char() == '#' => {
+                if let Some(layer) = tokens.get(i + 1).and_then(layer_below) {
+                    below = Some(layer);
+                }
+                i += 2;
+                continue;
+            }
+            TokenTree::Punct(punct) if punct.as_char() == ',' => expect = true,
+            TokenTree::Ident(ident) if expect => {
+                variants.push((ident.to_string(), below.take()));
+                expect = false;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    variants
+}
+
+fn string(token: &TokenTree) -> Option<String> {
+    match token {
+        TokenTree::Literal(literal) => syn::parse_str::<LitStr>(&literal.to_string())
+            .ok()
+            .map(|literal| literal.value()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // keymap.tomlとkeymap!から同じものが読める
+    fn test_read() {
+        let toml = Keymap::from_toml(
+            r#"
+            [keymap]
+            layout = "Layout"
+            layer = "Layer"
+            identifier = "crate::Id"
+            size = 2
+
+            [[hold]]
+            switch = [0, 1]
+            side = "right"
+            layer = "Lower"
+
+            [[layer]]
+            name = "Default"
+            left = '| A | B |'
+            right = '| C |   |'
+
+            [[layer]]
+            name = "Lower"
+            left = '| 1 |Trn|'
+            right = '|Trn|Trn|'
+            "#,
+        )
+        .unwrap();
+        let rust = Keymap::from_rust(
+            r##"
+            keymap! {
+                pub type Layout: 2, crate::Id;
+                pub enum Layer;
+                hold Right(0, 1) => Lower;
+                layer Default r"| A | B |" "| C |   |";
+                layer Lower r#"| 1 |Trn|"# r"|Trn|Trn|";
+            }
+            "##,
+        )
+        .unwrap();
+        assert_eq!(toml, rust);
+        assert_eq!(Some(1), toml.half_rows);
+        assert_eq!(Some("Lower"), toml.hold(1, 1));
+        assert_eq!(("B", 0), toml.resolve(1, 0, 1));
+        assert_eq!(("C", 0), toml.resolve(1, 1, 0));
+        assert_eq!(("1", 1), toml.resolve(1, 0, 0));
+    }
+
+    #[test]
+    // 透過キーの落ちる先はkeymap.toml、keymap!、derive(Layer)の`below`から読む
+    fn test_below() {
+        let toml = Keymap::from_toml(
+            r#"
+            [keymap]
+            layout = "Layout"
+            layer = "Layer"
+            identifier = "crate::Id"
+            size = 2
+
+            [[layer]]
+            name = "Default"
+            keys = '| A |'
+
+            [[layer]]
+            name = "Lower"
+            keys = '| 1 |'
+
+            [[layer]]
+            name = "Raise"
+            below = "Default"
+            keys = '|Trn|'
+            "#,
+        )
+        .unwrap();
+        let rust = Keymap::from_rust(
+            r#"
+            keymap! {
+                pub type Layout: 2, crate::Id;
+                pub enum Layer;
+                layer Default "| A |";
+                layer Lower "| 1 |";
+                layer Raise below Default "|Trn|";
+            }
+            "#,
+        )
+        .unwrap();
+        let derived = Keymap::from_rust(
+            r#"
+            #[derive(Clone, Copy, Default, rustkbd::keyboard::Layer)]
+            pub enum Layer {
+                #[default]
+                Default,
+                Lower,
+                /// 記号
+                #[layer(below = Default, name = "Sym")]
+                Raise,
+            }
+
+            const KEYMAP: [[[Key; 1]; 1]; 3] = [layout! {"| A |"}, layout! {"| 1 |"}, layout! {"|Trn|"}];
+            "#,
+        )
+        .unwrap();
+        assert_eq!(toml, rust);
+        assert_eq!(toml, derived);
+        assert_eq!(Some(0), toml.below(2));
+        assert_eq!(Some(0), toml.below(1));
+        assert_eq!(("A", 0), toml.resolve(2, 0, 0));
+    }
+}
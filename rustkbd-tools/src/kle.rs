@@ -0,0 +1,105 @@
+use serde_json::Value;
+
+/// keyboard-layout-editorのキー1つ。大きさと位置は1Uを1とする
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct KleKey {
+    /// 行列上の位置
+    pub(crate) position: (usize, usize),
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    pub(crate) w: f32,
+    pub(crate) h: f32,
+}
+
+/// keyboard-layout-editorのJSONから、キーの並び順に行列上の位置を読む
+pub(crate) fn positions(json: &str) -> Result<Vec<(usize, usize)>, String> {
+    Ok(keys(json)?.into_iter().map(|key| key.position).collect())
+}
+
+/// keyboard-layout-editorのJSONからキーを読む
+///
+/// 左上の凡例が`"行,列"`ならその位置を使い（VIAの定義と同じ書き方）、
+/// そうでなければKLEの行と、その行で何番目のキーかを使う。
+/// VIAの定義ファイルを渡したときは`layouts.keymap`を読む。回転は無視する。
+pub(crate) fn keys(json: &str) -> Result<Vec<KleKey>, String> {
+    let value: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let rows = match &value {
+        Value::Array(rows) => rows,
+        Value::Object(_) => value
+            .pointer("/layouts/keymap")
+            .and_then(Value::as_array)
+            .ok_or("expected a KLE array or a VIA definition with `layouts.keymap`")?,
+        _ => return Err("expected a KLE array".to_string()),
+    };
+
+    let mut keys = Vec::new();
+    let mut y = 0.0;
+    // 先頭のオブジェクトはキーボード全体の情報なので読み飛ばす
+    for (row, items) in rows.iter().filter_map(Value::as_array).enumerate() {
+        let (mut x, mut w, mut h) = (0.0, 1.0, 1.0);
+        let mut col = 0;
+        for item in items {
+            match item {
+                Value::Object(properties) => {
+                    let get = |name| properties.get(name).and_then(Value::as_f64);
+                    x += get("x").unwrap_or(0.0) as f32;
+                    y += get("y").unwrap_or(0.0) as f32;
+                    w = get("w").map_or(w, |w| w as f32);
+                    h = get("h").map_or(h, |h| h as f32);
+                }
+                Value::String(legend) => {
+                    keys.push(KleKey {
+                        position: matrix_position(legend).unwrap_or((row, col)),
+                        x,
+                        y,
+                        w,
+                        h,
+                    });
+                    x += w;
+                    (w, h) = (1.0, 1.0);
+                    col += 1;
+                }
+                _ => {}
+            }
+        }
+        y += 1.0;
+    }
+    Ok(keys)
+}
+
+fn matrix_position(legend: &str) -> Option<(usize, usize)> {
+    let (row, col) = legend.lines().next()?.split_once(',')?;
+    Some((row.trim().parse().ok()?, col.trim().parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 凡例に行列の位置があればそれを使う
+    fn test_positions() {
+        let json = r#"[{"name": "test"}, ["0,1", {"w": 2}, "0,0"], ["1,0\nA", "B"]]"#;
+        assert_eq!(Ok(vec![(0, 1), (0, 0), (1, 0), (1, 1)]), positions(json));
+
+        let json = r#"{"layouts": {"keymap": [["Q", "W"], ["A"]]}}"#;
+        assert_eq!(Ok(vec![(0, 0), (0, 1), (1, 0)]), positions(json));
+    }
+
+    #[test]
+    // 大きさと位置は前のキーとプロパティから決まる
+    fn test_geometry() {
+        let json = r#"[["Q", {"w": 1.5}, "W", "E"], [{"x": 0.25, "y": 0.5}, "A"]]"#;
+        let keys = keys(json).unwrap();
+        let geometry = keys.iter().map(|k| (k.x, k.y, k.w)).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (0.0, 0.0, 1.0),
+                (1.0, 0.0, 1.5),
+                (2.5, 0.0, 1.0),
+                (0.25, 1.5, 1.0)
+            ],
+            geometry
+        );
+    }
+}
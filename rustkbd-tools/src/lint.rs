@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+
+use crate::keymap::{is_transparent, Keymap};
+
+pub(crate) fn run(options: &HashMap<&str, &str>) -> Result<(), String> {
+    let path = options.get("keymap").ok_or("`--keymap` is required")?;
+    let keymap = Keymap::read(path)?;
+    let warnings = lint(&keymap);
+    for warning in warnings.iter() {
+        eprintln!("warning: {path}: {warning}");
+    }
+    if warnings.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{path}: {} warning(s)", warnings.len()))
+    }
+}
+
+/// レイヤを切り替えるスイッチ。`hold`と`LT(n, x)`
+struct LayerSwitch {
+    /// 書かれたレイヤ。`hold`はすべてのレイヤで有効
+    on: Option<usize>,
+    position: (usize, usize),
+    target: usize,
+}
+
+/// 実行するまで気づけない間違いを探す
+pub(crate) fn lint(keymap: &Keymap) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let names = keymap
+        .layers
+        .iter()
+        .map(|layer| layer.name.as_str())
+        .collect::<Vec<_>>();
+    let at = |layer: usize, (row, col): (usize, usize)| {
+        format!("layer `{}`, row {row}, column {col}", names[layer])
+    };
+
+    let mut switches = Vec::new();
+    let mut seen = HashMap::new();
+    for hold in keymap.holds.iter() {
+        let (row, col) = hold.position;
+        let Some(target) = names.iter().position(|name| *name == hold.layer) else {
+            warnings.push(format!(
+                "hold ({row}, {col}) switches to unknown layer `{}`",
+                hold.layer
+            ));
+            continue;
+        };
+        match seen.insert(hold.position, &hold.layer) {
+            Some(other) => warnings.push(format!(
+                "hold ({row}, {col}) is assigned to both `{other}` and `{}`",
+                hold.layer
+            )),
+            None => switches.push(LayerSwitch {
+                on: None,
+                position: hold.position,
+                target,
+            }),
+        }
+    }
+    for (layer, cells) in keymap.layers.iter().enumerate() {
+        for (row, cells) in cells.cells.iter().enumerate() {
+            for (col, cell) in cells.iter().enumerate() {
+                let Some(target) = layer_tap(cell) else {
+                    continue;
+                };
+                match layer_index(&names, target) {
+                    Some(target) => switches.push(LayerSwitch {
+                        on: Some(layer),
+                        position: (row, col),
+                        target,
+                    }),
+                    None => warnings.push(format!(
+                        "{}: `{cell}` switches to a missing layer",
+                        at(layer, (row, col))
+                    )),
+                }
+            }
+        }
+    }
+
+    for layer in keymap.layers.iter() {
+        if let Some(below) = layer
+            .below
+            .as_ref()
+            .filter(|below| !names.contains(&below.as_str()))
+        {
+            warnings.push(format!(
+                "layer `{}` falls through to unknown layer `{below}`",
+                layer.name
+            ));
+        }
+    }
+
+    // belowを辿って一番下のレイヤに着かなければ循環している
+    for layer in 0..names.len() {
+        let mut current = layer;
+        for _ in 0..names.len() {
+            match keymap.below(current) {
+                Some(below) => current = below,
+                None => break,
+            }
+        }
+        if keymap.below(current).is_some() {
+            warnings.push(format!("layer `{}` falls through a cycle", names[layer]));
+        }
+    }
+
+    for (layer, name) in names.iter().enumerate().skip(1) {
+        if !switches.iter().any(|switch| switch.target == layer) {
+            warnings.push(format!("layer `{name}` cannot be activated by any key"));
+        }
+    }
+
+    let (rows, cols) = keymap.shape();
+    for row in 0..rows {
+        for col in 0..cols {
+            let cell = keymap.cell(0, row, col);
+            if is_transparent(cell) {
+                warnings.push(format!(
+                    "{}: `{cell}` on the bottom layer does nothing",
+                    at(0, (row, col))
+                ));
+            }
+        }
+    }
+
+    // 切り替えた先のレイヤで同じスイッチにキーがあると、離すときにそのキーが押されてしまう
+    for switch in switches.iter() {
+        let (row, col) = switch.position;
+        let (cell, found) = keymap.resolve(switch.target, row, col);
+        if !cell.is_empty() && !is_transparent(cell) {
+            let from = match switch.on {
+                Some(layer) => format!(
+                    "`{}` on layer `{}`",
+                    keymap.cell(layer, row, col),
+                    names[layer]
+                ),
+                None => format!("hold ({row}, {col})"),
+            };
+            warnings.push(format!(
+                "{}: `{cell}` shadows {from} that switches to this layer; use `Trn` or leave it empty",
+                at(found, (row, col))
+            ));
+        }
+        if switch.on.is_none() {
+            // holdのスイッチに割り当てたキーは押されることがない
+            for layer in (0..switch.target).filter(|layer| *layer != found) {
+                let cell = keymap.cell(layer, row, col);
+                if !cell.is_empty() && !is_transparent(cell) {
+                    warnings.push(format!(
+                        "{}: `{cell}` is never sent because the switch is a hold for `{}`",
+                        at(layer, (row, col)),
+                        names[switch.target]
+                    ));
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// `LT(n, x)`や`LT(Lower, x)`に書かれたレイヤ
+fn layer_tap(cell: &str) -> Option<&str> {
+    let (layer, _) = cell.strip_prefix("LT(")?.split_once(',')?;
+    Some(layer.trim())
+}
+
+/// `layout!`と同じく、番号か`Lower`や`Layer::Lower`のようなヴァリアント名をレイヤの番号にする
+fn layer_index(names: &[&str], layer: &str) -> Option<usize> {
+    match layer.parse::<usize>() {
+        Ok(index) => (index < names.len()).then_some(index),
+        Err(_) => {
+            let name = layer.rsplit("::").next()?.trim();
+            names.iter().position(|n| *n == name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 正しいキーマップには何も言わない
+    fn test_clean() {
+        let keymap = Keymap::from_toml(
+            r#"
+            [keymap]
+            layout = "Layout"
+            layer = "Layer"
+            identifier = "crate::Id"
+            size = 2
+
+            [[hold]]
+            switch = [1, 0]
+            layer = "Lower"
+
+            [[layer]]
+            name = "Default"
+            keys = '''
+            | Esc |LT(2, Space)|
+            |     |            |
+            '''
+
+            [[layer]]
+            name = "Lower"
+            keys = '''
+            |  1  |  2  |
+            | Trn | Trn |
+            '''
+
+            [[layer]]
+            name = "Raise"
+            keys = '''
+            |  A  |     |
+            |     |  B  |
+            '''
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Vec::<String>::new(), lint(&keymap));
+    }
+
+    #[test]
+    // 切り替えられないレイヤ、一番下の透過キー、切り替え先のキー、重複を見つける
+    fn test_warnings() {
+        let keymap = Keymap::from_toml(
+            r#"
+            [keymap]
+            layout = "Layout"
+            layer = "Layer"
+            identifier = "crate::Id"
+            size = 2
+
+            [[hold]]
+            switch = [1, 0]
+            layer = "Lower"
+
+            [[hold]]
+            switch = [1, 0]
+            layer = "Raise"
+
+            [[layer]]
+            name = "Default"
+            keys = '''
+            | Trn |  Q  |
+            |  A  |     |
+            '''
+
+            [[layer]]
+            name = "Lower"
+            keys = '''
+            |  1  |  2  |
+            |  3  |  4  |
+            '''
+
+            [[layer]]
+            name = "Raise"
+            keys = '''
+            |     |     |
+            |     |     |
+            '''
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "hold (1, 0) is assigned to both `Lower` and `Raise`",
+                "layer `Raise` cannot be activated by any key",
+                "layer `Default`, row 0, column 0: `Trn` on the bottom layer does nothing",
+                "layer `Lower`, row 1, column 0: `3` shadows hold (1, 0) that switches to this layer; use `Trn` or leave it empty",
+                "layer `Default`, row 1, column 0: `A` is never sent because the switch is a hold for `Lower`",
+            ],
+            lint(&keymap)
+        );
+    }
+
+    #[test]
+    // `LT`にはレイヤの名前も書ける。`below`の循環や存在しないレイヤを見つける
+    fn test_layers() {
+        let keymap = Keymap::from_toml(
+            r#"
+            [keymap]
+            layout = "Layout"
+            layer = "Layer"
+            identifier = "crate::Id"
+            size = 2
+
+            [[layer]]
+            name = "Default"
+            keys = '|LT(Lower, Space)|LT(Layer::Raise, A)|LT(3, B)|LT(Upper, C)|'
+
+            [[layer]]
+            name = "Lower"
+            below = "Raise"
+            keys = '|                 |        Trn         |    1    |            |'
+
+            [[layer]]
+            name = "Raise"
+            below = "Lower"
+            keys = '|                 |                    |   Trn   |            |'
+
+            [[layer]]
+            name = "Adjust"
+            below = "Upper"
+            keys = '|                 |                    |         |            |'
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "layer `Default`, row 0, column 3: `LT(Upper, C)` switches to a missing layer",
+                "layer `Adjust` falls through to unknown layer `Upper`",
+                "layer `Lower` falls through a cycle",
+                "layer `Raise` falls through a cycle",
+            ],
+            lint(&keymap)
+        );
+    }
+}
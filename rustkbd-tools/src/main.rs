@@ -0,0 +1,58 @@
+//! キーマップを扱うホスト側のツール
+//!
+//! ボードとは違うターゲットでビルドするので、`cargo tools <command>`で動かす。
+
+use std::{collections::HashMap, process::ExitCode};
+
+mod cell;
+mod import;
+mod keymap;
+mod kle;
+mod lint;
+mod qmk;
+mod render;
+
+const USAGE: &str = "\
+usage: cargo tools <command> [options]
+
+commands:
+    import  --qmk <keymap.json> (--kle <layout.json> | --cols <n>)
+            [--format toml|rust] [--identifier <type>] [--size <n>]
+            QMKのkeymap.jsonをkeymap.tomlかkeymap!に変換する
+    render  --keymap <keymap.toml|layout.rs> [--kle <layout.json>] [--format text|svg]
+            すべてのレイヤを表かSVGにする
+    lint    --keymap <keymap.toml|layout.rs>
+            切り替えられないレイヤや押されることのないキーを探す";
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.first().map(String::as_str) {
+        Some("import") => options(&args[1..]).and_then(|options| import::run(&options)),
+        Some("lint") => options(&args[1..]).and_then(|options| lint::run(&options)),
+        Some("render") => options(&args[1..]).and_then(|options| render::run(&options)),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// `--name value`の並びを読む
+fn options(args: &[String]) -> Result<HashMap<&str, &str>, String> {
+    let mut options = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .ok_or_else(|| format!("unexpected argument `{arg}`\n\n{USAGE}"))?;
+        let value = args
+            .next()
+            .ok_or_else(|| format!("`--{name}` needs a value\n\n{USAGE}"))?;
+        options.insert(name, value.as_str());
+    }
+    Ok(options)
+}
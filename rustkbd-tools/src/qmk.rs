@@ -0,0 +1,343 @@
+use rustkbd::keyboard::Key;
+
+/// QMKのキーコードを読んだもの
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Keycode {
+    Key(Key),
+    /// `MO(n)`。rustkbdではレイヤを切り替えるスイッチになる
+    Momentary(u8),
+}
+
+/// `KC_`を除いた名前と、QMKのキーコード
+/// cf. https://github.com/qmk/qmk_firmware/blob/master/docs/keycodes_basic.md
+const BASIC: &[(&[&str], u16)] = &[
+    (&["NO"], 0x0000),
+    (&["TRNS", "TRANSPARENT"], 0x0001),
+    (&["ENTER", "ENT"], 0x0028),
+    (&["ESCAPE", "ESC"], 0x0029),
+    (&["BACKSPACE", "BSPC"], 0x002a),
+    (&["TAB"], 0x002b),
+    (&["SPACE", "SPC"], 0x002c),
+    (&["MINUS", "MINS"], 0x002d),
+    (&["EQUAL", "EQL"], 0x002e),
+    (&["LEFT_BRACKET", "LBRC"], 0x002f),
+    (&["RIGHT_BRACKET", "RBRC"], 0x0030),
+    (&["BACKSLASH", "BSLS"], 0x0031),
+    (&["NONUS_HASH", "NUHS"], 0x0032),
+    (&["SEMICOLON", "SCLN"], 0x0033),
+    (&["QUOTE", "QUOT"], 0x0034),
+    (&["GRAVE", "GRV"], 0x0035),
+    (&["COMMA", "COMM"], 0x0036),
+    (&["DOT"], 0x0037),
+    (&["SLASH", "SLSH"], 0x0038),
+    (&["CAPS_LOCK", "CAPS"], 0x0039),
+    (&["PRINT_SCREEN", "PSCR"], 0x0046),
+    (&["SCROLL_LOCK", "SCRL"], 0x0047),
+    (&["PAUSE", "PAUS", "BRK"], 0x0048),
+    (&["INSERT", "INS"], 0x0049),
+    (&["HOME"], 0x004a),
+    (&["PAGE_UP", "PGUP"], 0x004b),
+    (&["DELETE", "DEL"], 0x004c),
+    (&["END"], 0x004d),
+    (&["PAGE_DOWN", "PGDN"], 0x004e),
+    (&["RIGHT", "RGHT"], 0x004f),
+    (&["LEFT"], 0x0050),
+    (&["DOWN"], 0x0051),
+    (&["UP"], 0x0052),
+    (&["NUM_LOCK", "NUM"], 0x0053),
+    (&["KP_SLASH", "PSLS"], 0x0054),
+    (&["KP_ASTERISK", "PAST"], 0x0055),
+    (&["KP_MINUS", "PMNS"], 0x0056),
+    (&["KP_PLUS", "PPLS"], 0x0057),
+    (&["KP_ENTER", "PENT"], 0x0058),
+    (&["KP_1", "P1"], 0x0059),
+    (&["KP_2", "P2"], 0x005a),
+    (&["KP_3", "P3"], 0x005b),
+    (&["KP_4", "P4"], 0x005c),
+    (&["KP_5", "P5"], 0x005d),
+    (&["KP_6", "P6"], 0x005e),
+    (&["KP_7", "P7"], 0x005f),
+    (&["KP_8", "P8"], 0x0060),
+    (&["KP_9", "P9"], 0x0061),
+    (&["KP_0", "P0"], 0x0062),
+    (&["KP_DOT", "PDOT"], 0x0063),
+    (&["NONUS_BACKSLASH", "NUBS"], 0x0064),
+    (&["APPLICATION", "APP"], 0x0065),
+    (&["KB_POWER"], 0x0066),
+    (&["KP_EQUAL", "PEQL"], 0x0067),
+    (&["EXECUTE", "EXEC"], 0x0074),
+    (&["HELP"], 0x0075),
+    (&["MENU"], 0x0076),
+    (&["SELECT", "SLCT"], 0x0077),
+    (&["STOP"], 0x0078),
+    (&["AGAIN", "AGIN"], 0x0079),
+    (&["UNDO"], 0x007a),
+    (&["CUT"], 0x007b),
+    (&["COPY"], 0x007c),
+    (&["PASTE", "PSTE"], 0x007d),
+    (&["FIND"], 0x007e),
+    (&["KB_MUTE"], 0x007f),
+    (&["KB_VOLUME_UP"], 0x0080),
+    (&["KB_VOLUME_DOWN"], 0x0081),
+    (&["LOCKING_CAPS_LOCK", "LCAP"], 0x0082),
+    (&["LOCKING_NUM_LOCK", "LNUM"], 0x0083),
+    (&["LOCKING_SCROLL_LOCK", "LSCR"], 0x0084),
+    (&["KP_COMMA", "PCMM"], 0x0085),
+    (&["KP_EQUAL_AS400"], 0x0086),
+    (&["INTERNATIONAL_1", "INT1"], 0x0087),
+    (&["INTERNATIONAL_2", "INT2"], 0x0088),
+    (&["INTERNATIONAL_3", "INT3"], 0x0089),
+    (&["INTERNATIONAL_4", "INT4"], 0x008a),
+    (&["INTERNATIONAL_5", "INT5"], 0x008b),
+    (&["INTERNATIONAL_6", "INT6"], 0x008c),
+    (&["INTERNATIONAL_7", "INT7"], 0x008d),
+    (&["INTERNATIONAL_8", "INT8"], 0x008e),
+    (&["INTERNATIONAL_9", "INT9"], 0x008f),
+    (&["LANGUAGE_1", "LNG1"], 0x0090),
+    (&["LANGUAGE_2", "LNG2"], 0x0091),
+    (&["LANGUAGE_3", "LNG3"], 0x0092),
+    (&["LANGUAGE_4", "LNG4"], 0x0093),
+    (&["LANGUAGE_5", "LNG5"], 0x0094),
+    (&["LANGUAGE_6", "LNG6"], 0x0095),
+    (&["LANGUAGE_7", "LNG7"], 0x0096),
+    (&["LANGUAGE_8", "LNG8"], 0x0097),
+    (&["LANGUAGE_9", "LNG9"], 0x0098),
+    (&["ALTERNATE_ERASE", "ERAS"], 0x0099),
+    (&["SYSTEM_REQUEST", "SYRQ"], 0x009a),
+    (&["CANCEL", "CNCL"], 0x009b),
+    (&["CLEAR", "CLR"], 0x009c),
+    (&["PRIOR", "PRIR"], 0x009d),
+    (&["RETURN", "RETN"], 0x009e),
+    (&["SEPARATOR", "SEPR"], 0x009f),
+    (&["OUT"], 0x00a0),
+    (&["OPER"], 0x00a1),
+    (&["CLEAR_AGAIN", "CLAG"], 0x00a2),
+    (&["CRSEL", "CRSL"], 0x00a3),
+    (&["EXSEL", "EXSL"], 0x00a4),
+    (&["AUDIO_MUTE", "MUTE"], 0x00a8),
+    (&["AUDIO_VOL_UP", "VOLU"], 0x00a9),
+    (&["AUDIO_VOL_DOWN", "VOLD"], 0x00aa),
+    (&["MEDIA_NEXT_TRACK", "MNXT"], 0x00ab),
+    (&["MEDIA_PREV_TRACK", "MPRV"], 0x00ac),
+    (&["MEDIA_STOP", "MSTP"], 0x00ad),
+    (&["MEDIA_PLAY_PAUSE", "MPLY"], 0x00ae),
+    (&["LEFT_CTRL", "LCTL"], 0x00e0),
+    (&["LEFT_SHIFT", "LSFT"], 0x00e1),
+    (&["LEFT_ALT", "LALT", "LOPT"], 0x00e2),
+    (&["LEFT_GUI", "LGUI", "LCMD", "LWIN"], 0x00e3),
+    (&["RIGHT_CTRL", "RCTL"], 0x00e4),
+    (&["RIGHT_SHIFT", "RSFT"], 0x00e5),
+    (&["RIGHT_ALT", "RALT", "ROPT", "ALGR"], 0x00e6),
+    (&["RIGHT_GUI", "RGUI", "RCMD", "RWIN"], 0x00e7),
+    (&["TILDE", "TILD"], 0x0235),
+    (&["EXCLAIM", "EXLM"], 0x021e),
+    (&["AT"], 0x021f),
+    (&["HASH"], 0x0220),
+    (&["DOLLAR", "DLR"], 0x0221),
+    (&["PERCENT", "PERC"], 0x0222),
+    (&["CIRCUMFLEX", "CIRC"], 0x0223),
+    (&["AMPERSAND", "AMPR"], 0x0224),
+    (&["ASTERISK", "ASTR"], 0x0225),
+    (&["LEFT_PAREN", "LPRN"], 0x0226),
+    (&["RIGHT_PAREN", "RPRN"], 0x0227),
+    (&["UNDERSCORE", "UNDS"], 0x022d),
+    (&["PLUS"], 0x022e),
+    (&["LEFT_CURLY_BRACE", "LCBR"], 0x022f),
+    (&["RIGHT_CURLY_BRACE", "RCBR"], 0x0230),
+    (&["PIPE"], 0x0231),
+    (&["COLON", "COLN"], 0x0233),
+    (&["DOUBLE_QUOTE", "DQUO", "DQT"], 0x0234),
+    (&["LEFT_ANGLE_BRACKET", "LABK", "LT"], 0x0236),
+    (&["RIGHT_ANGLE_BRACKET", "RABK", "GT"], 0x0237),
+    (&["QUESTION", "QUES"], 0x0238),
+];
+
+/// 修飾キーで包む関数と、QMKの修飾キーのビット（0x10は右手側）
+const MODS: &[(&[&str], u16)] = &[
+    (&["LCTL", "C"], 0x01),
+    (&["LSFT", "S"], 0x02),
+    (&["LALT", "A", "LOPT"], 0x04),
+    (&["LGUI", "G", "LCMD", "LWIN"], 0x08),
+    (&["LCS", "C_S"], 0x03),
+    (&["LCA"], 0x05),
+    (&["LSA"], 0x06),
+    (&["LSG", "SGUI"], 0x0a),
+    (&["LAG"], 0x0c),
+    (&["MEH"], 0x07),
+    (&["HYPR"], 0x0f),
+    (&["RCTL"], 0x11),
+    (&["RSFT"], 0x12),
+    (&["RALT", "ROPT", "ALGR"], 0x14),
+    (&["RGUI", "RCMD", "RWIN"], 0x18),
+];
+
+/// QMKのキーコードの文字列を読む。読めなければ理由を返す
+pub(crate) fn parse(keycode: &str) -> Result<Keycode, String> {
+    let keycode = keycode.trim();
+    if let Some((name, args)) = function(keycode) {
+        match (name, args.as_slice()) {
+            ("MO", [layer]) => return layer_number(layer).map(Keycode::Momentary),
+            ("UC", [code]) => {
+                return u32::from_str_radix(code.trim_start_matches("0x"), 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .map(|c| Keycode::Key(Key::Unicode(c)))
+                    .ok_or_else(|| format!("`{code}` is not a Unicode code point"));
+            }
+            _ => {}
+        }
+    }
+    let code = code(keycode)?;
+    Key::from_qmk_keycode(code)
+        .map(Keycode::Key)
+        .ok_or_else(|| format!("`{keycode}` ({code:#06x}) cannot be represented as a key"))
+}
+
+/// 数値のキーコードにする
+fn code(keycode: &str) -> Result<u16, String> {
+    if let Some((name, args)) = function(keycode) {
+        if let Some((_, mods)) = MODS.iter().find(|(names, _)| names.contains(&name)) {
+            let [inner] = args.as_slice() else {
+                return Err(format!("`{name}` takes one keycode"));
+            };
+            let inner = code(inner)?;
+            if inner > 0x1fff {
+                return Err(format!(
+                    "`{keycode}` wraps a keycode that is not a basic key"
+                ));
+            }
+            let inner_mods = inner >> 8;
+            if inner_mods != 0 && (inner_mods & 0x10) != (mods & 0x10) {
+                return Err(format!("`{keycode}` mixes left and right modifiers"));
+            }
+            return Ok((inner_mods | mods) << 8 | (inner & 0xff));
+        }
+        return match (name, args.as_slice()) {
+            ("LT", [layer, inner]) => {
+                let layer = layer_number(layer)?;
+                let inner = code(inner)?;
+                if layer > 0x0f || inner > 0xff {
+                    return Err(format!("`{keycode}` cannot be represented as a layer-tap"));
+                }
+                Ok(0x4000 | (layer as u16) << 8 | inner)
+            }
+            _ => Err(format!("`{name}(...)` is not supported")),
+        };
+    }
+
+    match keycode {
+        "_______" => return Ok(0x0001),
+        "XXXXXXX" => return Ok(0x0000),
+        _ => {}
+    }
+    let Some(name) = keycode.strip_prefix("KC_") else {
+        return Err(format!("`{keycode}` is not supported"));
+    };
+    if let [c @ b'A'..=b'Z'] = name.as_bytes() {
+        return Ok(0x0004 + (c - b'A') as u16);
+    }
+    match name.as_bytes() {
+        [b'0'] => return Ok(0x0027),
+        [c @ b'1'..=b'9'] => return Ok(0x001e + (c - b'1') as u16),
+        _ => {}
+    }
+    if let Some(n) = name.strip_prefix('F').and_then(|n| n.parse::<u16>().ok()) {
+        match n {
+            1..=12 => return Ok(0x003a + n - 1),
+            13..=24 => return Ok(0x0068 + n - 13),
+            _ => {}
+        }
+    }
+    BASIC
+        .iter()
+        .find(|(names, _)| names.contains(&name))
+        .map(|(_, code)| *code)
+        .ok_or_else(|| format!("`{keycode}` is not a known keycode"))
+}
+
+/// `NAME(a, b)`を名前と引数に分ける
+fn function(keycode: &str) -> Option<(&str, Vec<&str>)> {
+    let (name, args) = keycode.strip_suffix(')')?.split_once('(')?;
+    let mut result = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(args[start..].trim());
+    Some((name.trim(), result))
+}
+
+fn layer_number(layer: &str) -> Result<u8, String> {
+    layer
+        .parse()
+        .map_err(|_| format!("`{layer}` is not a layer number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 基本のキーコードと別名
+    fn test_basic() {
+        assert_eq!(Ok(Keycode::Key(Key::A)), parse("KC_A"));
+        assert_eq!(Ok(Keycode::Key(Key::Digit1_Exclamation)), parse("KC_1"));
+        assert_eq!(
+            Ok(Keycode::Key(Key::Digit0_RightParenthesis)),
+            parse("KC_0")
+        );
+        assert_eq!(Ok(Keycode::Key(Key::F13)), parse("KC_F13"));
+        assert_eq!(Ok(Keycode::Key(Key::Delete)), parse("KC_BSPC"));
+        assert_eq!(Ok(Keycode::Key(Key::DeleteForward)), parse("KC_DEL"));
+        assert_eq!(Ok(Keycode::Key(Key::Transparent)), parse("_______"));
+        assert_eq!(Ok(Keycode::Key(Key::None)), parse("KC_NO"));
+        assert_eq!(Ok(Keycode::Key(Key::Exclamation)), parse("KC_EXLM"));
+        assert_eq!(Ok(Keycode::Key(Key::MediaPlayPause)), parse("KC_MPLY"));
+    }
+
+    #[test]
+    // 修飾キー・レイヤ・Unicode
+    fn test_functions() {
+        assert_eq!(Ok(Keycode::Key(Key::Exclamation)), parse("S(KC_1)"));
+        assert_eq!(
+            Ok(Keycode::Key(Key::WithModifiers(0x03, 0x2b))),
+            parse("LCTL(LSFT(KC_TAB))")
+        );
+        assert_eq!(
+            Ok(Keycode::Key(Key::LayerTap(2, 0x2c))),
+            parse("LT(2, KC_SPC)")
+        );
+        assert_eq!(Ok(Keycode::Momentary(1)), parse("MO(1)"));
+        assert_eq!(Ok(Keycode::Key(Key::Unicode('あ'))), parse("UC(0x3042)"));
+    }
+
+    #[test]
+    // 対応していないキーコードは理由を返す
+    fn test_unsupported() {
+        assert_eq!(
+            Err("`TG(...)` is not supported".to_string()),
+            parse("TG(1)")
+        );
+        assert_eq!(
+            Err("`RGB_TOG` is not supported".to_string()),
+            parse("RGB_TOG")
+        );
+        assert_eq!(
+            Err("`KC_FOO` is not a known keycode".to_string()),
+            parse("KC_FOO")
+        );
+        assert_eq!(
+            Err("`LCTL(RSFT(KC_A))` mixes left and right modifiers".to_string()),
+            parse("LCTL(RSFT(KC_A))")
+        );
+    }
+}
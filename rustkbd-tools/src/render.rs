@@ -0,0 +1,228 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use crate::{
+    cell::row,
+    keymap::{is_transparent, Keymap},
+    kle::{self, KleKey},
+};
+
+/// SVGでの1Uの大きさ
+const UNIT: f32 = 60.0;
+/// SVGでレイヤ名を書く高さ
+const TITLE: f32 = 30.0;
+
+pub(crate) fn run(options: &HashMap<&str, &str>) -> Result<(), String> {
+    let path = options.get("keymap").ok_or("`--keymap` is required")?;
+    let keymap = Keymap::read(path)?;
+    let geometry = match options.get("kle") {
+        Some(path) => {
+            let json = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+            kle::keys(&json).map_err(|e| format!("{path}: {e}"))?
+        }
+        None => grid_geometry(&keymap),
+    };
+    match options.get("format").copied().unwrap_or("text") {
+        "text" => print!("{}", text(&keymap)),
+        "svg" => print!("{}", svg(&keymap, &geometry)),
+        format => return Err(format!("unknown format `{format}`")),
+    }
+    Ok(())
+}
+
+/// 表示するセル。透過キーは落ちた先のキーを括弧に入れ、空いているスイッチにはレイヤの切り替えを書く
+///
+/// 2つ目は落ちた先のキーかどうか。
+fn label(keymap: &Keymap, layer: usize, row: usize, col: usize) -> (String, bool) {
+    let mut cell = keymap.cell(layer, row, col);
+    if is_transparent(cell) {
+        let (below, _) = keymap.resolve(layer, row, col);
+        if below.is_empty() {
+            cell = below;
+        } else if !is_transparent(below) {
+            return (format!("({below})"), true);
+        }
+    }
+    match (cell, keymap.hold(row, col)) {
+        ("", Some(hold)) => (format!("[{hold}]"), false),
+        (cell, _) => (cell.to_string(), false),
+    }
+}
+
+/// 端末に出す表。分割キーボードは左右を横に並べる
+fn text(keymap: &Keymap) -> String {
+    let (rows, cols) = keymap.shape();
+    let labels = (0..keymap.layers.len())
+        .map(|layer| {
+            (0..rows)
+                .map(|r| (0..cols).map(|c| label(keymap, layer, r, c).0).collect())
+                .collect::<Vec<Vec<_>>>()
+        })
+        .collect::<Vec<_>>();
+    let width = labels
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|label| label.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+
+    let mut out = String::new();
+    for (layer, lines) in keymap.layers.iter().zip(labels.iter()) {
+        let _ = writeln!(out, "{}", layer.name);
+        match keymap.half_rows {
+            Some(half) => {
+                for (left, right) in lines[..half].iter().zip(lines[half..].iter()) {
+                    let _ = writeln!(out, "{}   {}", row(left, width), row(right, width));
+                }
+            }
+            None => {
+                for cells in lines {
+                    let _ = writeln!(out, "{}", row(cells, width));
+                }
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// KLEがないときの配置。表のとおりに並べ、分割キーボードの右手側は1U空けて右に置く
+fn grid_geometry(keymap: &Keymap) -> Vec<KleKey> {
+    let (rows, cols) = keymap.shape();
+    (0..rows)
+        .flat_map(|row| (0..cols).map(move |col| (row, col)))
+        .map(|(row, col)| {
+            let (x, y) = match keymap.half_rows {
+                Some(half) if row >= half => (cols + 1 + col, row - half),
+                _ => (col, row),
+            };
+            KleKey {
+                position: (row, col),
+                x: x as f32,
+                y: y as f32,
+                w: 1.0,
+                h: 1.0,
+            }
+        })
+        .collect()
+}
+
+/// すべてのレイヤを縦に並べたSVG
+fn svg(keymap: &Keymap, geometry: &[KleKey]) -> String {
+    let width = geometry.iter().map(|k| k.x + k.w).fold(0.0, f32::max) * UNIT;
+    let height = geometry.iter().map(|k| k.y + k.h).fold(0.0, f32::max) * UNIT + TITLE;
+    let total = height * keymap.layers.len() as f32;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{total}" viewBox="0 0 {width} {total}" font-family="sans-serif">"#
+    );
+    for (index, layer) in keymap.layers.iter().enumerate() {
+        let top = height * index as f32;
+        let _ = writeln!(
+            out,
+            r#"  <text x="4" y="{}" font-size="18" font-weight="bold">{}</text>"#,
+            top + TITLE - 8.0,
+            escape(&layer.name)
+        );
+        for key in geometry {
+            let (row, col) = key.position;
+            let (label, fallen) = label(keymap, index, row, col);
+            let (x, y) = (key.x * UNIT, top + TITLE + key.y * UNIT);
+            let (w, h) = (key.w * UNIT, key.h * UNIT);
+            let _ = writeln!(
+                out,
+                r##"  <rect x="{}" y="{}" width="{}" height="{}" rx="6" fill="#f4f4f4" stroke="#888"/>"##,
+                x + 2.0,
+                y + 2.0,
+                w - 4.0,
+                h - 4.0
+            );
+            if !label.is_empty() {
+                let fill = if fallen { "#999" } else { "#000" };
+                let _ = writeln!(
+                    out,
+                    r#"  <text x="{}" y="{}" font-size="12" text-anchor="middle" dominant-baseline="middle" fill="{fill}">{}</text>"#,
+                    x + w / 2.0,
+                    y + h / 2.0,
+                    escape(&label)
+                );
+            }
+        }
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap() -> Keymap {
+        Keymap::from_toml(
+            r#"
+            [keymap]
+            layout = "Layout"
+            layer = "Layer"
+            identifier = "crate::Id"
+            size = 2
+
+            [[hold]]
+            switch = [1, 0]
+            layer = "Lower"
+
+            [[layer]]
+            name = "Default"
+            keys = '''
+            | Esc |  <  |
+            |     |Space|
+            '''
+
+            [[layer]]
+            name = "Lower"
+            keys = '''
+            | Trn |  1  |
+            | Trn | Trn |
+            '''
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    // 透過キーには落ちた先のキーを、空いたスイッチにはレイヤを書く
+    fn test_text() {
+        assert_eq!(
+            "Default\n\
+             |  Esc  |   <   |\n\
+             |[Lower]| Space |\n\
+             \n\
+             Lower\n\
+             | (Esc) |   1   |\n\
+             |[Lower]|(Space)|\n\
+             \n",
+            text(&keymap())
+        );
+    }
+
+    #[test]
+    // SVGでは記号をエスケープし、落ちた先のキーを薄く書く
+    fn test_svg() {
+        let keymap = keymap();
+        let svg = svg(&keymap, &grid_geometry(&keymap));
+        assert!(
+            svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="120" height="300""#)
+        );
+        assert!(svg.contains(r##"fill="#000">&lt;</text>"##));
+        assert!(svg.contains(r##"fill="#999">(Space)</text>"##));
+    }
+}
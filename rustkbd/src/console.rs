@@ -0,0 +1,171 @@
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+
+/// コンソールの1行の最大の長さ
+pub const CONSOLE_LINE_LEN: usize = 64;
+
+/// コンソールに入力された1行
+#[derive(Debug, Clone, Copy)]
+pub struct Command<'a> {
+    line: &'a str,
+}
+
+impl<'a> Command<'a> {
+    /// 空行ならNone
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let line = line.trim();
+        (!line.is_empty()).then_some(Command { line })
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.line.split_whitespace().next().unwrap_or_default()
+    }
+
+    /// `words`（"ec raw"のような空白区切りの語）で始まっていれば、残りの引数を返す
+    pub fn matches(&self, words: &str) -> Option<core::str::SplitWhitespace<'a>> {
+        let mut args = self.line.split_whitespace();
+        words
+            .split_whitespace()
+            .all(|word| args.next() == Some(word))
+            .then_some(args)
+    }
+}
+
+/// 入力された1文字の結果
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Edit {
+    /// 何もしない
+    None,
+    /// 文字を行に足したのでエコーバックする
+    Echo(u8),
+    /// 最後の文字を消した
+    Erase,
+    /// 行が揃った
+    Line(String<CONSOLE_LINE_LEN>),
+}
+
+/// 1行揃うまで入力を溜める
+#[derive(Debug, Default)]
+pub(crate) struct LineBuffer {
+    line: Vec<u8, CONSOLE_LINE_LEN>,
+    /// 直前の文字が`\r`だった。続く`\n`は同じ改行として読み捨てる
+    after_cr: bool,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        LineBuffer {
+            line: Vec::new(),
+            after_cr: false,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) -> Edit {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => Edit::None,
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                String::from_utf8(line).map_or(Edit::None, Edit::Line)
+            }
+            // Backspace, Delete
+            0x08 | 0x7f if self.line.pop().is_some() => Edit::Erase,
+            c @ 0x20..=0x7e if self.line.push(c).is_ok() => Edit::Echo(c),
+            _ => Edit::None,
+        }
+    }
+}
+
+/// コンソールのコマンドを処理するハンドラ
+///
+/// 出力は`out`に書く。バッファがいっぱいになった分は捨てられるので、書き込みのエラーは無視してよい。
+pub trait ConsoleHandler {
+    /// コマンドを処理したらtrueを返す。falseなら次のハンドラに回される
+    fn handle(&mut self, _command: &Command<'_>, _out: &mut dyn Write) -> bool {
+        false
+    }
+
+    /// `help`で表示するコマンドの一覧を書く
+    fn help(&self, _out: &mut dyn Write) {}
+}
+
+/// 1行を解釈して、処理できるハンドラに渡す
+pub fn execute(line: &str, handlers: &mut [&mut dyn ConsoleHandler], out: &mut dyn Write) {
+    let Some(command) = Command::parse(line) else {
+        return;
+    };
+    if command.matches("help").is_some() {
+        handlers.iter().for_each(|handler| handler.help(out));
+        return;
+    }
+    if !handlers
+        .iter_mut()
+        .any(|handler| handler.handle(&command, out))
+    {
+        writeln!(out, "unknown command: {}", command.name()).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl ConsoleHandler for Echo {
+        fn handle(&mut self, command: &Command<'_>, out: &mut dyn Write) -> bool {
+            let Some(args) = command.matches("echo") else {
+                return false;
+            };
+            for arg in args {
+                write!(out, "{} ", arg).ok();
+            }
+            true
+        }
+
+        fn help(&self, out: &mut dyn Write) {
+            writeln!(out, "echo <args>").ok();
+        }
+    }
+
+    #[test]
+    // 複数語のコマンドは語ごとに一致を見る
+    fn test_matches() {
+        let command = Command::parse("  ec  raw 3 ").unwrap();
+        assert_eq!("ec", command.name());
+        assert_eq!(Some(1), command.matches("ec raw").map(|args| args.count()));
+        assert!(command.matches("ec rawx").is_none());
+        assert!(command.matches("e").is_none());
+        assert!(Command::parse("   ").is_none());
+    }
+
+    #[test]
+    // 処理できるハンドラがなければunknown command
+    fn test_execute() {
+        let mut out = String::<64>::new();
+        execute("echo a b", &mut [&mut Echo], &mut out);
+        assert_eq!("a b ", out);
+
+        out.clear();
+        execute("foo", &mut [&mut Echo], &mut out);
+        assert_eq!("unknown command: foo\n", out);
+
+        out.clear();
+        execute("help", &mut [&mut Echo], &mut out);
+        assert_eq!("echo <args>\n", out);
+    }
+
+    #[test]
+    // \r\nは1つの改行になり、\rだけ、\nだけでも改行になる
+    fn test_line_buffer() {
+        let mut buffer = LineBuffer::new();
+        let mut lines = Vec::<String<CONSOLE_LINE_LEN>, 4>::new();
+        for byte in b"ab\x7fc\r\nd\re\n\n".iter() {
+            if let Edit::Line(line) = buffer.push(*byte) {
+                lines.push(line).unwrap();
+            }
+        }
+        assert_eq!(["ac", "d", "e", ""], lines.as_slice());
+    }
+}
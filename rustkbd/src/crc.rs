@@ -0,0 +1,51 @@
+/// CRC-16/CCITT-FALSE
+pub(crate) struct Crc16(u16);
+
+impl Crc16 {
+    pub fn new() -> Self {
+        Crc16(0xffff)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= (*byte as u16) << 8;
+            for _ in 0..8 {
+                self.0 = if self.0 & 0x8000 != 0 {
+                    (self.0 << 1) ^ 0x1021
+                } else {
+                    self.0 << 1
+                };
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u16 {
+        self.0
+    }
+
+    pub fn checksum(bytes: &[u8]) -> u16 {
+        let mut crc = Crc16::new();
+        crc.update(bytes);
+        crc.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // "123456789"のチェック値
+    fn test_check_value() {
+        assert_eq!(0x29b1, Crc16::checksum(b"123456789"));
+    }
+
+    #[test]
+    // 分割して計算しても同じ値になる
+    fn test_update_in_pieces() {
+        let mut crc = Crc16::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(0x29b1, crc.finish());
+    }
+}
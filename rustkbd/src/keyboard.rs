@@ -1,15 +1,33 @@
+mod array_layout;
 mod controller;
+mod dynamic_keymap;
 mod external_communicator;
+mod host_layout;
+mod host_leds;
 mod key;
 mod key_switches;
 mod keyboard_state;
 mod layer;
 mod layout;
+mod qmk_keycode;
+mod report_queue;
+mod unicode;
+mod wpm_counter;
 
+pub(crate) use array_layout::active_layer;
+#[cfg(test)]
+pub(crate) use array_layout::tests as array_layout_tests;
+pub use array_layout::ArrayLayout;
 pub use controller::Controller;
+pub use dynamic_keymap::DynamicKeymap;
 pub use external_communicator::ExternalCommunicator;
+pub use host_layout::HostLayout;
+pub use host_leds::HostLeds;
 pub use key::Key;
-pub use key_switches::{KeySwitchIdentifier, KeySwitches};
+pub use key_switches::{InvalidSwitchIdentifier, KeySwitchIdentifier, KeySwitches, MatrixPosition};
 pub use keyboard_state::KeyboardState;
 pub use layer::Layer;
-pub use layout::{layout, Layout};
+pub use layout::{include_keymap, keymap, layout, Layout};
+pub use report_queue::{QueueFull, ReportQueue};
+pub use unicode::UnicodeMode;
+pub(crate) use wpm_counter::WpmCounter;
@@ -0,0 +1,214 @@
+use core::marker::PhantomData;
+
+use super::{DynamicKeymap, Key, KeySwitchIdentifier, Layer, Layout, MatrixPosition};
+
+/// 配列のキーマップと、レイヤを切り替えるスイッチの表で決まるLayout
+///
+/// キーマップには`layout!`で作った配列を`Layer::index()`の順に、レイヤの数だけ並べて渡す。
+/// `layer_switches`には押している間レイヤを切り替えるスイッチを、行列の位置で渡す。
+/// レイヤは押されているそれらのスイッチのうち、`index()`が最も大きいものになる。
+/// キーは実行時に書き換えられるので、そのまま`DynamicKeymap`としてVIAやStorageから使える。
+pub struct ArrayLayout<I, Y: 'static, const LAYERS: usize, const ROWS: usize, const COLS: usize> {
+    default_keymap: [[[Key; COLS]; ROWS]; LAYERS],
+    keymap: [[[Key; COLS]; ROWS]; LAYERS],
+    layer_switches: &'static [((usize, usize), Y)],
+    _identifier: PhantomData<I>,
+}
+
+impl<I, Y, const LAYERS: usize, const ROWS: usize, const COLS: usize>
+    ArrayLayout<I, Y, LAYERS, ROWS, COLS>
+{
+    pub const fn new(
+        keymap: [[[Key; COLS]; ROWS]; LAYERS],
+        layer_switches: &'static [((usize, usize), Y)],
+    ) -> Self {
+        ArrayLayout {
+            default_keymap: keymap,
+            keymap,
+            layer_switches,
+            _identifier: PhantomData,
+        }
+    }
+}
+
+impl<
+        const SZ: usize,
+        I: KeySwitchIdentifier<SZ> + MatrixPosition,
+        Y: Layer,
+        const LAYERS: usize,
+        const ROWS: usize,
+        const COLS: usize,
+    > Layout<SZ> for ArrayLayout<I, Y, LAYERS, ROWS, COLS>
+{
+    type Identifier = I;
+    type Layer = Y;
+
+    fn layer(&self, switches: &[I]) -> Y {
+        let positions = switches.iter().map(|switch| switch.position(ROWS, COLS));
+        active_layer(self.layer_switches, positions)
+    }
+
+    fn key(&self, layer: Y, switch: &I) -> Key {
+        const {
+            assert!(
+                Y::ALL.len() == LAYERS,
+                "the keymap needs one table per layer"
+            )
+        };
+        let (row, col) = switch.position(ROWS, COLS);
+        self.get_key(layer.index(), row, col).unwrap_or(Key::None)
+    }
+}
+
+impl<I, Y, const LAYERS: usize, const ROWS: usize, const COLS: usize> DynamicKeymap
+    for ArrayLayout<I, Y, LAYERS, ROWS, COLS>
+{
+    fn layer_count(&self) -> usize {
+        LAYERS
+    }
+
+    fn rows(&self) -> usize {
+        ROWS
+    }
+
+    fn cols(&self) -> usize {
+        COLS
+    }
+
+    fn get_key(&self, layer: usize, row: usize, col: usize) -> Option<Key> {
+        self.keymap.get(layer)?.get(row)?.get(col).copied()
+    }
+
+    fn set_key(&mut self, layer: usize, row: usize, col: usize, key: Key) -> bool {
+        if let Some(k) = self
+            .keymap
+            .get_mut(layer)
+            .and_then(|l| l.get_mut(row))
+            .and_then(|r| r.get_mut(col))
+        {
+            *k = key;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn reset(&mut self) {
+        self.keymap = self.default_keymap;
+    }
+}
+
+/// 押されているスイッチの位置のうち、`layer_switches`にあるもので最も上のレイヤ
+pub(crate) fn active_layer<Y: Layer>(
+    layer_switches: &[((usize, usize), Y)],
+    positions: impl Iterator<Item = (usize, usize)>,
+) -> Y {
+    positions
+        .filter_map(|position| {
+            layer_switches
+                .iter()
+                .find(|(p, _)| *p == position)
+                .map(|(_, layer)| *layer)
+        })
+        .max_by_key(|layer| layer.index())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub(crate) struct Switch(pub u8, pub u8);
+
+    impl From<[u8; 2]> for Switch {
+        fn from(value: [u8; 2]) -> Self {
+            Switch(value[0], value[1])
+        }
+    }
+
+    impl From<Switch> for [u8; 2] {
+        fn from(value: Switch) -> Self {
+            [value.0, value.1]
+        }
+    }
+
+    impl KeySwitchIdentifier<2> for Switch {}
+
+    impl MatrixPosition for Switch {
+        fn position(&self, _rows: usize, _cols: usize) -> (usize, usize) {
+            (self.0 as usize, self.1 as usize)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub(crate) enum TestLayer {
+        #[default]
+        Default,
+        Lower,
+        Raise,
+    }
+
+    impl Layer for TestLayer {
+        const ALL: &'static [Self] = &[TestLayer::Default, TestLayer::Lower, TestLayer::Raise];
+
+        fn below(&self) -> Option<Self> {
+            Self::from_index(self.index().checked_sub(1)?)
+        }
+
+        fn index(&self) -> usize {
+            *self as usize
+        }
+
+        fn from_index(index: usize) -> Option<Self> {
+            Self::ALL.get(index).copied()
+        }
+
+        fn name(&self) -> &'static str {
+            match self {
+                TestLayer::Default => "Default",
+                TestLayer::Lower => "Lower",
+                TestLayer::Raise => "Raise",
+            }
+        }
+    }
+
+    static LAYER_SWITCHES: [((usize, usize), TestLayer); 2] =
+        [((1, 0), TestLayer::Lower), ((1, 1), TestLayer::Raise)];
+
+    #[test]
+    // 押されているレイヤのスイッチのうち上のレイヤになる
+    fn test_layer() {
+        let layout =
+            ArrayLayout::<Switch, _, 3, 2, 2>::new([[[Key::None; 2]; 2]; 3], &LAYER_SWITCHES);
+        assert_eq!(TestLayer::Default, layout.layer(&[Switch(0, 0)]));
+        assert_eq!(
+            TestLayer::Lower,
+            layout.layer(&[Switch(0, 0), Switch(1, 0)])
+        );
+        assert_eq!(
+            TestLayer::Raise,
+            layout.layer(&[Switch(1, 1), Switch(1, 0)])
+        );
+    }
+
+    #[test]
+    // キーは書き換えられて、resetで元に戻る
+    fn test_keys() {
+        let keymap = [
+            [[Key::A, Key::B], [Key::None, Key::None]],
+            [[Key::Digit1_Exclamation, Key::Transparent], [Key::None; 2]],
+            [[Key::None; 2]; 2],
+        ];
+        let mut layout = ArrayLayout::<Switch, _, 3, 2, 2>::new(keymap, &LAYER_SWITCHES);
+        assert_eq!(Key::B, layout.key(TestLayer::Default, &Switch(0, 1)));
+        assert!(layout.set_key(1, 0, 1, Key::C));
+        assert!(!layout.set_key(3, 0, 0, Key::C));
+        assert_eq!(Key::C, layout.key(TestLayer::Lower, &Switch(0, 1)));
+        layout.reset();
+        assert_eq!(
+            Key::Transparent,
+            layout.key(TestLayer::Lower, &Switch(0, 1))
+        );
+    }
+}
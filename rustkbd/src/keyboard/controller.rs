@@ -1,9 +1,30 @@
-use heapless::{FnvIndexMap, Vec};
+use core::fmt::Write;
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::Vec;
+
+use crate::{
+    console::{Command, ConsoleHandler},
+    storage::{self, Storage},
+};
 
 use super::{
-    ExternalCommunicator, Key, KeySwitchIdentifier, KeySwitches, KeyboardState, Layer, Layout,
+    DynamicKeymap, ExternalCommunicator, HostLayout, Key, KeySwitchIdentifier, KeySwitches,
+    KeyboardState, Layer, Layout, ReportQueue, UnicodeMode, WpmCounter,
 };
 
+/// 入力用に積んでおけるレポートの数
+const REPORT_QUEUE_LEN: usize = 32;
+
+/// 押されている`Key::LayerTap`のスイッチ
+struct HeldLayerTap<SI> {
+    switch: SI,
+    layer: u8,
+    usage: u8,
+    /// 押している間にほかのスイッチが押されたら、離してもタップしない
+    interrupted: bool,
+}
+
 pub struct Controller<
     const SZ: usize,
     const RO: usize,
@@ -13,10 +34,22 @@ pub struct Controller<
 > {
     pub communicator: C,
     pub key_switches: K,
+    pub layout: L,
     layer: L::Layer,
-    layout: L,
     keys: Vec<Key, RO>,
-    pressed_switches: FnvIndexMap<K::Identifier, L::Layer, 16>,
+    /// 押されているスイッチと、押されたときのレイヤ
+    pressed_switches: Vec<(K::Identifier, L::Layer), RO>,
+    reports: ReportQueue<RO, REPORT_QUEUE_LEN>,
+    unicode_mode: UnicodeMode,
+    host_layout: HostLayout,
+    /// 実行中に切り替えられて、まだ保存されていない設定がある
+    unsaved_settings: bool,
+    /// VIAで書き換えられて、まだ保存されていないキーマップがある
+    unsaved_keymap: bool,
+    layer_taps: Vec<HeldLayerTap<K::Identifier>, RO>,
+    /// いまの時刻(µs)。打鍵の速さを測るのに使う
+    clock: Option<fn() -> u32>,
+    wpm: WpmCounter,
 }
 
 impl<
@@ -31,38 +64,187 @@ impl<
         Controller {
             communicator,
             key_switches,
-            layer: L::Layer::default(),
             layout,
+            layer: L::Layer::default(),
             keys: Vec::new(),
-            pressed_switches: FnvIndexMap::new(),
+            pressed_switches: Vec::new(),
+            reports: ReportQueue::new(),
+            unicode_mode: UnicodeMode::default(),
+            host_layout: HostLayout::default(),
+            unsaved_settings: false,
+            unsaved_keymap: false,
+            layer_taps: Vec::new(),
+            clock: None,
+            wpm: WpmCounter::new(),
+        }
+    }
+
+    /// 打鍵の速さを測るための時計(µs)を設定する
+    pub fn set_clock(&mut self, clock: fn() -> u32) {
+        self.clock = Some(clock);
+    }
+
+    pub fn unicode_mode(&self) -> UnicodeMode {
+        self.unicode_mode
+    }
+
+    pub fn set_unicode_mode(&mut self, mode: UnicodeMode) {
+        self.unsaved_settings |= self.unicode_mode != mode;
+        self.unicode_mode = mode;
+    }
+
+    pub fn host_layout(&self) -> HostLayout {
+        self.host_layout
+    }
+
+    /// キーの送信と文字の表示の両方に効く
+    pub fn set_host_layout(&mut self, layout: HostLayout) {
+        self.unsaved_settings |= self.host_layout != layout;
+        self.host_layout = layout;
+    }
+
+    /// 保存されている設定を読み込む。起動時に呼ぶ
+    pub fn load_settings<F: NorFlash>(
+        &mut self,
+        storage: &mut Storage<F>,
+    ) -> Result<(), storage::Error<F::Error>> {
+        if let Some(mode) = storage.load()? {
+            self.unicode_mode = mode;
+        }
+        if let Some(layout) = storage.load()? {
+            self.host_layout = layout;
+        }
+        self.unsaved_settings = false;
+        Ok(())
+    }
+
+    pub fn has_unsaved_settings(&self) -> bool {
+        self.unsaved_settings || self.unsaved_keymap
+    }
+
+    /// キーマップが書き換えられたことを知らせる。次の`save_keymap`で保存される
+    pub fn keymap_edited(&mut self) {
+        self.unsaved_keymap = true;
+    }
+
+    /// 実行中に切り替えられた設定を保存する
+    pub fn save_settings<F: NorFlash>(
+        &mut self,
+        storage: &mut Storage<F>,
+    ) -> Result<(), storage::Error<F::Error>> {
+        if !self.unsaved_settings {
+            return Ok(());
         }
+        storage.save(&self.unicode_mode)?;
+        storage.save(&self.host_layout)?;
+        self.unsaved_settings = false;
+        Ok(())
     }
 
+    /// 分割キーボードのReceiverでは、Controllerから送られてきた状態を返す
     pub fn get_state(&self) -> KeyboardState<L::Layer, RO> {
+        self.key_switches
+            .shared_state()
+            .unwrap_or_else(|| self.local_state())
+    }
+
+    fn local_state(&self) -> KeyboardState<L::Layer, RO> {
         KeyboardState {
             layer: self.layer,
             keys: self.keys.clone(),
+            host_layout: self.host_layout,
+            host_leds: self.communicator.host_leds(),
+            wpm: self.wpm.wpm(),
         }
     }
 
     pub fn main_loop(&mut self) {
+        self.key_switches
+            .set_host_connected(self.communicator.is_ready());
         let switches = self.key_switches.scan();
 
-        // グローバルなレイヤの決定
-        let global_layer = self.layout.layer(&switches);
+        // 離されたレイヤタップのキーは、ほかのスイッチが押されていなければタップする
+        let interrupted = switches
+            .iter()
+            .any(|s| !self.pressed_switches.iter().any(|(p, _)| p == s));
+        let mut taps = Vec::<Key, RO>::new();
+        self.layer_taps.retain_mut(|tap| {
+            if switches.contains(&tap.switch) {
+                tap.interrupted |= interrupted;
+                true
+            } else {
+                if !tap.interrupted {
+                    taps.extend(Key::try_from(tap.usage as u16).ok());
+                }
+                false
+            }
+        });
+
+        // グローバルなレイヤの決定。レイヤタップのキーが押されていればそのレイヤにする
+        let global_layer = self
+            .layer_taps
+            .last()
+            .and_then(|tap| L::Layer::from_index(tap.layer as usize))
+            .unwrap_or_else(|| self.layout.layer(&switches));
 
         // 個別のスイッチのレイヤの決定
         let switches_and_layers: Vec<_, RO> =
             determine_layers(&self.pressed_switches, &switches, global_layer);
 
         // キーの決定
-        let keys = determine_keys(&self.layout, &switches_and_layers);
+        let keys: Vec<_, RO> = determine_keys(&self.layout, &switches_and_layers);
+        for (switch, key) in keys.iter() {
+            if let Key::LayerTap(layer, usage) = *key {
+                if !self.layer_taps.iter().any(|tap| tap.switch == *switch) {
+                    let tap = HeldLayerTap {
+                        switch: *switch,
+                        layer,
+                        usage,
+                        interrupted: false,
+                    };
+                    self.layer_taps.push(tap).ok();
+                }
+            }
+        }
+        let keys = keys
+            .into_iter()
+            .map(|(_, key)| key)
+            .filter(|key| !matches!(key, Key::LayerTap(..)))
+            .collect();
         let keys = filter_keys(keys);
 
         if !keys.is_empty() {
             defmt::debug!("{}", keys.as_slice());
         }
 
+        // 押された瞬間だけ働くキーの処理
+        if let Some(clock) = self.clock {
+            self.wpm.tick(clock());
+        }
+        let previous = core::mem::take(&mut self.keys);
+        for key in keys.iter().filter(|key| !previous.contains(key)) {
+            if key.is_keyboard_key() {
+                self.wpm.press();
+            }
+            if let Key::Unicode(c) = key {
+                if self
+                    .unicode_mode
+                    .push_reports(*c, &mut self.reports)
+                    .is_err()
+                {
+                    defmt::warn!("Report queue is full");
+                }
+            } else if let Some(mode) = key.unicode_mode() {
+                self.set_unicode_mode(mode);
+            }
+        }
+        for key in taps {
+            self.wpm.press();
+            if self.reports.tap(&keys, key).is_err() {
+                defmt::warn!("Report queue is full");
+            }
+        }
+
         // スイッチ押下状態の更新
         self.pressed_switches = switches_and_layers
             .into_iter()
@@ -70,26 +252,114 @@ impl<
             .collect();
         self.layer = global_layer;
         self.keys = keys;
+        self.key_switches.share_state(&self.local_state());
     }
 
-    pub fn send_keys(&self) -> Result<(), C::Error> {
+    /// 積まれたレポートがあればその先頭を、なければ現在のキーを送る
+    pub fn send_keys(&mut self) -> Result<(), C::Error> {
         if !self.communicator.is_ready() {
             return Ok(());
         }
 
-        self.communicator.send_keys(&self.keys)
+        // 送れなかったレポートは次に送り直すので、送れてから取り除く
+        if let Some(report) = self.reports.front() {
+            self.communicator.send_keys(report, self.host_layout)?;
+            self.reports.pop();
+            return Ok(());
+        }
+        self.communicator.send_keys(&self.keys, self.host_layout)
     }
 }
 
-fn determine_layers<
-    'a,
-    Y: Layer,
-    SI: KeySwitchIdentifier<SZ>,
-    const SZ: usize,
-    const RO: usize,
-    const N: usize,
->(
-    pressed_switches: &FnvIndexMap<SI, Y, N>,
+impl<
+        const SZ: usize,
+        const RO: usize,
+        C: ExternalCommunicator,
+        K: KeySwitches<SZ, RO>,
+        L: Layout<SZ, Identifier = K::Identifier> + DynamicKeymap,
+    > Controller<SZ, RO, C, K, L>
+{
+    /// 保存されているキーマップを読み込む。起動時に呼ぶ
+    pub fn load_keymap<F: NorFlash>(
+        &mut self,
+        storage: &mut Storage<F>,
+    ) -> Result<(), storage::Error<F::Error>> {
+        storage.load_keymap(&mut self.layout)?;
+        self.unsaved_keymap = false;
+        Ok(())
+    }
+
+    /// 書き換えられたキーマップを保存する
+    pub fn save_keymap<F: NorFlash>(
+        &mut self,
+        storage: &mut Storage<F>,
+    ) -> Result<(), storage::Error<F::Error>> {
+        if !self.unsaved_keymap {
+            return Ok(());
+        }
+        storage.save_keymap(&self.layout)?;
+        self.unsaved_keymap = false;
+        Ok(())
+    }
+}
+
+impl<
+        const SZ: usize,
+        const RO: usize,
+        C: ExternalCommunicator,
+        K: KeySwitches<SZ, RO> + ConsoleHandler,
+        L: Layout<SZ, Identifier = K::Identifier>,
+    > ConsoleHandler for Controller<SZ, RO, C, K, L>
+{
+    fn handle(&mut self, command: &Command<'_>, out: &mut dyn Write) -> bool {
+        if command.matches("state").is_some() {
+            writeln!(out, "layer: {}", self.layer.index()).ok();
+            writeln!(out, "keys: {:?}", self.keys.as_slice()).ok();
+            writeln!(out, "leds: {:#04x}", self.communicator.host_leds().bits()).ok();
+            writeln!(out, "wpm: {}", self.wpm.wpm()).ok();
+            writeln!(out, "ready: {}", self.communicator.is_ready()).ok();
+        } else if command.matches("layer").is_some() {
+            writeln!(out, "{}", self.layer.index()).ok();
+        } else if let Some(mut args) = command.matches("unicode") {
+            let mode = match args.next() {
+                Some("linux") => UnicodeMode::Linux,
+                Some("macos") => UnicodeMode::MacOs,
+                Some("wincompose") => UnicodeMode::WinCompose,
+                Some("alt") => UnicodeMode::WindowsAlt,
+                _ => self.unicode_mode,
+            };
+            self.set_unicode_mode(mode);
+            writeln!(out, "{:?}", mode).ok();
+        } else if let Some(mut args) = command.matches("host") {
+            match args.next() {
+                Some("us") => self.set_host_layout(HostLayout::Us),
+                Some("jis") => self.set_host_layout(HostLayout::Jis),
+                _ => {}
+            }
+            writeln!(out, "{:?}", self.host_layout).ok();
+        } else if command.matches("matrix").is_some() {
+            for (switch, _) in self.pressed_switches.iter() {
+                let switch: [u8; SZ] = (*switch).into();
+                writeln!(out, "{:?}", switch).ok();
+            }
+        } else {
+            return self.key_switches.handle(command, out);
+        }
+        true
+    }
+
+    fn help(&self, out: &mut dyn Write) {
+        writeln!(out, "state   layer and keys").ok();
+        writeln!(out, "layer   current layer").ok();
+        writeln!(out, "matrix  pressed switches").ok();
+        writeln!(out, "unicode [linux|macos|wincompose|alt]").ok();
+        writeln!(out, "host    [us|jis]").ok();
+        self.key_switches.help(out);
+    }
+}
+
+fn determine_layers<'a, Y: Layer, SI: KeySwitchIdentifier<SZ>, const SZ: usize, const RO: usize>(
+    pressed_switches: &[(SI, Y)],
     switches: &'a [SI],
     global_layer: Y,
 ) -> Vec<(&'a SI, Y), RO> {
@@ -97,7 +367,7 @@ fn determine_layers<
     switches
         .iter()
         .map(|s| {
-            let layer = if let Some(layer) = pressed_switches.get(s) {
+            let layer = if let Some((_, layer)) = pressed_switches.iter().find(|(p, _)| p == s) {
                 *layer
             } else {
                 global_layer
@@ -110,37 +380,30 @@ fn determine_layers<
 fn determine_keys<L: Layout<SZ>, const SZ: usize, const RO: usize>(
     layout: &L,
     switches_and_layers: &[(&L::Identifier, L::Layer)],
-) -> Vec<Key, RO> {
+) -> Vec<(L::Identifier, Key), RO> {
     switches_and_layers
         .iter()
         .map(|(switch, mut layer)| {
             let mut key = layout.key(layer, switch);
-            while key == Key::Transparent {
-                if let Some(below) = layer.below() {
-                    assert!(
-                        layer != below,
-                        "{}.below() does not change layer",
-                        stringify!(L::Layer)
-                    );
-                    layer = below;
-                    key = layout.key(layer, switch);
-                } else {
+            // derive(Layer)では循環しないが、手で実装されていても止まるようにレイヤの数までたどる
+            for _ in 0..L::Layer::ALL.len() {
+                let Some(below) = layer.below().filter(|_| key == Key::Transparent) else {
                     break;
-                }
+                };
+                layer = below;
+                key = layout.key(layer, switch);
             }
-            key
+            (**switch, key)
         })
-        .filter(|key| !key.is_noop())
-        .collect::<Vec<Key, RO>>()
+        .filter(|(_, key)| !key.is_noop())
+        .collect()
 }
 
 fn filter_keys<const RO: usize>(mut keys: Vec<Key, RO>) -> Vec<Key, RO> {
-    if keys
-        .iter()
-        .any(|k| !k.is_modified_key() && !k.is_modifier_key())
-    {
+    let is_modified = |k: &Key| k.is_modified_key() || matches!(k, Key::WithModifiers(..));
+    if keys.iter().any(|k| !is_modified(k) && !k.is_modifier_key()) {
         // 修飾済みキー以外が押されているときは、修飾済みキーは無効化する
-        keys.retain(|k| !k.is_modified_key());
+        keys.retain(|k| !is_modified(k));
         // FIXME: シフトキーが押下されている状態でシフト修飾済みキーと非修飾キーが押下されたときは、シフト修飾済みキーを生かすべき
     }
     keys
@@ -148,7 +411,233 @@ fn filter_keys<const RO: usize>(mut keys: Vec<Key, RO>) -> Vec<Key, RO> {
 
 #[cfg(test)]
 mod tests {
+    use core::cell::{Cell, RefCell};
+
     use super::*;
+    use crate::{
+        keyboard::{
+            array_layout_tests::{Switch, TestLayer},
+            ArrayLayout, QueueFull,
+        },
+        scenario::{Report, Scenario},
+        storage::MockFlash,
+    };
+
+    static LAYER_SWITCHES: [((usize, usize), TestLayer); 1] = [((1, 0), TestLayer::Lower)];
+
+    fn layout() -> ArrayLayout<Switch, TestLayer, 3, 2, 2> {
+        let keymap = [
+            [[Key::A, Key::B], [Key::None, Key::LayerTap(2, 0x2c)]],
+            [
+                [Key::Digit1_Exclamation, Key::Transparent],
+                [Key::Transparent; 2],
+            ],
+            [[Key::C, Key::MediaMute], [Key::None; 2]],
+        ];
+        ArrayLayout::new(keymap, &LAYER_SWITCHES)
+    }
+
+    /// `script`を動かして、最後の操作の10ms後までに送られたレポート
+    fn run(script: &str) -> Vec<Report, 16> {
+        let scenario = Scenario::<16>::parse(script).unwrap();
+        let mut controller = Controller::<2, 6, _, _, _>::new(
+            scenario.recorder::<16>(),
+            scenario.switches(None, Switch),
+            layout(),
+        );
+        scenario.run_controller(&mut controller, 10);
+        controller.communicator.reports()
+    }
+
+    #[test]
+    // レイヤのスイッチを押している間はそのレイヤのキーになり、透過キーは下のレイヤのキーになる
+    fn test_scenario_layer() {
+        let reports = run("press(1,0) @0ms; press(0,0) @10ms; press(0,1) @20ms; \
+             release(1,0) @30ms; release(0,0) @40ms; release(0,1) @50ms");
+        assert_eq!(
+            [
+                Report::new(10, &[Key::Digit1_Exclamation]),
+                Report::new(20, &[Key::Digit1_Exclamation, Key::B]),
+                Report::new(40, &[Key::B]),
+                Report::new(50, &[]),
+            ],
+            reports.as_slice()
+        );
+    }
+
+    #[test]
+    // 16個より多くのスイッチを同時に押しても止まらない
+    fn test_scenario_many_switches() {
+        let scenario = Scenario::<18>::parse(
+            "press(0,0) @0ms; press(0,1) @0ms; press(0,2) @0ms; press(0,3) @0ms; \
+             press(0,4) @0ms; press(0,5) @0ms; press(1,0) @0ms; press(1,1) @0ms; \
+             press(1,2) @0ms; press(1,3) @0ms; press(1,4) @0ms; press(1,5) @0ms; \
+             press(2,0) @0ms; press(2,1) @0ms; press(2,2) @0ms; press(2,3) @0ms; \
+             press(2,4) @0ms; press(2,5) @0ms",
+        )
+        .unwrap();
+        let keymap = [
+            [
+                [Key::A, Key::B, Key::C, Key::D, Key::E, Key::F],
+                [Key::None; 6],
+                [Key::None; 6],
+            ],
+            [[Key::None; 6]; 3],
+            [[Key::None; 6]; 3],
+        ];
+        let mut controller = Controller::<2, 18, _, _, _>::new(
+            scenario.recorder::<4>(),
+            scenario.switches(None, Switch),
+            ArrayLayout::<Switch, TestLayer, 3, 3, 6>::new(keymap, &[]),
+        );
+        scenario.run_controller(&mut controller, 10);
+        assert_eq!(
+            [Report::new(
+                0,
+                &[Key::A, Key::B, Key::C, Key::D, Key::E, Key::F]
+            )],
+            controller.communicator.reports().as_slice()
+        );
+    }
+
+    #[test]
+    // レイヤタップは単独で離すとタップになり、押している間はそのレイヤのキーになる
+    fn test_scenario_layer_tap() {
+        let reports = run("press(1,1) @0ms; release(1,1) @10ms; \
+             press(1,1) @20ms; press(0,1) @30ms; release(0,1) @40ms; release(1,1) @50ms");
+        assert_eq!(
+            [
+                Report::new(10, &[Key::Space]),
+                Report::new(11, &[]),
+                Report::new(30, &[Key::MediaMute]),
+                Report::new(40, &[]),
+            ],
+            reports.as_slice()
+        );
+    }
+
+    #[test]
+    // 修飾済みキーはControllerに設定したホストのレイアウトで送られる
+    fn test_scenario_host_layout() {
+        let scenario = Scenario::<16>::parse("press(0,0) @0ms; release(0,0) @10ms").unwrap();
+        let keymap = [[[Key::Asterisk, Key::None], [Key::None; 2]]; 3];
+        let mut controller = Controller::<2, 6, _, _, _>::new(
+            scenario.recorder::<16>(),
+            scenario.switches(None, Switch),
+            ArrayLayout::<_, TestLayer, 3, 2, 2>::new(keymap, &LAYER_SWITCHES),
+        );
+        controller.set_host_layout(HostLayout::Jis);
+        scenario.run_controller(&mut controller, 10);
+        assert_eq!(
+            [
+                Report::with_layout(0, &[Key::Asterisk], HostLayout::Jis),
+                Report::new(10, &[]),
+            ],
+            controller.communicator.reports().as_slice()
+        );
+        assert_eq!(
+            Report::new(0, &[Key::LeftShift, Key::Apostrophe_Quotation]),
+            controller.communicator.reports()[0]
+        );
+    }
+
+    /// 1回おきに送信に失敗する
+    #[derive(Default)]
+    struct FlakyCommunicator {
+        fail: Cell<bool>,
+        sent: RefCell<Vec<Vec<Key, 6>, 8>>,
+    }
+
+    impl ExternalCommunicator for FlakyCommunicator {
+        type Error = QueueFull;
+
+        fn is_ready(&self) -> bool {
+            true
+        }
+
+        fn send_keys(&self, keys: &[Key], _layout: HostLayout) -> Result<(), QueueFull> {
+            if self.fail.replace(!self.fail.get()) {
+                return Err(QueueFull);
+            }
+            self.sent
+                .borrow_mut()
+                .push(Vec::from_slice(keys).unwrap())
+                .map_err(|_| QueueFull)
+        }
+    }
+
+    #[test]
+    // 積まれたレポートは送れるまで取り除かないので、送信に失敗しても欠けない
+    fn test_send_keys_retry() {
+        let scenario = Scenario::<1>::parse("").unwrap();
+        let mut controller = Controller::<2, 6, _, _, _>::new(
+            FlakyCommunicator::default(),
+            scenario.switches(None, Switch),
+            layout(),
+        );
+        controller.reports.push(&[Key::LeftControl]).unwrap();
+        controller.reports.tap(&[], Key::A).unwrap();
+        for _ in 0..6 {
+            controller.send_keys().ok();
+        }
+        let sent = controller.communicator.sent.borrow();
+        let expected: [&[Key]; 3] = [&[Key::LeftControl], &[Key::A], &[]];
+        assert_eq!(expected.len(), sent.len());
+        for (expected, report) in expected.iter().zip(sent.iter()) {
+            assert_eq!(*expected, report.as_slice());
+        }
+    }
+
+    #[test]
+    // 実行中に切り替えた入力方式とレイアウトを保存し、次に起動したときに読み込む
+    fn test_save_and_load_settings() {
+        let scenario = Scenario::<1>::parse("").unwrap();
+        let mut storage = Storage::new(MockFlash::<4096, 1024, 4>::new(), 0, 4).unwrap();
+        let mut controller = Controller::<2, 6, _, _, _>::new(
+            scenario.recorder::<1>(),
+            scenario.switches(None, Switch),
+            layout(),
+        );
+        controller.set_unicode_mode(UnicodeMode::WinCompose);
+        controller.set_host_layout(HostLayout::Jis);
+        assert!(controller.has_unsaved_settings());
+        controller.save_settings(&mut storage).unwrap();
+        assert!(!controller.has_unsaved_settings());
+
+        let mut controller = Controller::<2, 6, _, _, _>::new(
+            scenario.recorder::<1>(),
+            scenario.switches(None, Switch),
+            layout(),
+        );
+        controller.load_settings(&mut storage).unwrap();
+        assert_eq!(UnicodeMode::WinCompose, controller.unicode_mode());
+        assert_eq!(HostLayout::Jis, controller.host_layout());
+    }
+
+    #[test]
+    // 書き換えたキーマップを保存し、次に起動したときに読み込む
+    fn test_save_and_load_keymap() {
+        let scenario = Scenario::<1>::parse("").unwrap();
+        let mut storage = Storage::new(MockFlash::<4096, 1024, 4>::new(), 0, 4).unwrap();
+        let mut controller = Controller::<2, 6, _, _, _>::new(
+            scenario.recorder::<1>(),
+            scenario.switches(None, Switch),
+            layout(),
+        );
+        controller.layout.set_key(0, 0, 1, Key::Escape);
+        controller.keymap_edited();
+        assert!(controller.has_unsaved_settings());
+        controller.save_keymap(&mut storage).unwrap();
+        assert!(!controller.has_unsaved_settings());
+
+        let mut controller = Controller::<2, 6, _, _, _>::new(
+            scenario.recorder::<1>(),
+            scenario.switches(None, Switch),
+            layout(),
+        );
+        controller.load_keymap(&mut storage).unwrap();
+        assert_eq!(Some(Key::Escape), controller.layout.get_key(0, 0, 1));
+    }
 
     #[test]
     // 修飾キーと関係のない場合
@@ -0,0 +1,20 @@
+use super::Key;
+
+/// 実行時に書き換えられるキーマップ
+///
+/// レイヤは`Layer::index()`の番号、位置は`MatrixPosition`の(行, 列)で指定する。
+pub trait DynamicKeymap {
+    fn layer_count(&self) -> usize;
+
+    fn rows(&self) -> usize;
+
+    fn cols(&self) -> usize;
+
+    fn get_key(&self, layer: usize, row: usize, col: usize) -> Option<Key>;
+
+    /// 範囲外の位置を指定したときはfalse
+    fn set_key(&mut self, layer: usize, row: usize, col: usize, key: Key) -> bool;
+
+    /// 初期状態のキーマップに戻す
+    fn reset(&mut self);
+}
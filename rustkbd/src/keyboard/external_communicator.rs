@@ -1,7 +1,13 @@
-use super::Key;
+use super::{HostLayout, HostLeds, Key};
 
 pub trait ExternalCommunicator {
     type Error;
     fn is_ready(&self) -> bool;
-    fn send_keys(&self, keys: &[Key]) -> Result<(), Self::Error>;
+    /// 修飾済みキーは`layout`のホストで同じ文字になるように読み替えて送る
+    fn send_keys(&self, keys: &[Key], layout: HostLayout) -> Result<(), Self::Error>;
+
+    /// ホストから最後に届いたLEDの状態
+    fn host_leds(&self) -> HostLeds {
+        HostLeds::empty()
+    }
 }
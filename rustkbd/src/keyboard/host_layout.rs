@@ -0,0 +1,143 @@
+use defmt::Format;
+
+/// ホストで設定されているキーボードレイアウト
+///
+/// `Key::Asterisk`のような修飾済みキーはUSレイアウトでの文字を表すので、
+/// ホストで同じ文字になるようにUsage IDと修飾キーを読み替える。
+/// 修飾されていないキーは読み替えず、表示する文字だけがホストに合わせて変わる。
+/// 設定は`Controller`が持ち、`KeyboardState`で表示にも渡される。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Format)]
+#[repr(u8)]
+pub enum HostLayout {
+    #[default]
+    Us,
+    Jis,
+}
+
+/// 左Shiftの修飾キーのフラグ
+const SHIFT: u8 = 0x02;
+
+/// (Usage ID, 無修飾の文字, Shiftを押したときの文字)。文字がないところは空白
+type Symbol = (u8, char, char);
+
+static US_SYMBOLS: [Symbol; 21] = [
+    (0x1e, '1', '!'),
+    (0x1f, '2', '@'),
+    (0x20, '3', '#'),
+    (0x21, '4', '$'),
+    (0x22, '5', '%'),
+    (0x23, '6', '^'),
+    (0x24, '7', '&'),
+    (0x25, '8', '*'),
+    (0x26, '9', '('),
+    (0x27, '0', ')'),
+    (0x2d, '-', '_'),
+    (0x2e, '=', '+'),
+    (0x2f, '[', '{'),
+    (0x30, ']', '}'),
+    (0x31, '\\', '|'),
+    (0x33, ';', ':'),
+    (0x34, '\'', '"'),
+    (0x35, '`', '~'),
+    (0x36, ',', '<'),
+    (0x37, '.', '>'),
+    (0x38, '/', '?'),
+];
+
+static JIS_SYMBOLS: [Symbol; 24] = [
+    (0x1e, '1', '!'),
+    (0x1f, '2', '"'),
+    (0x20, '3', '#'),
+    (0x21, '4', '$'),
+    (0x22, '5', '%'),
+    (0x23, '6', '&'),
+    (0x24, '7', '\''),
+    (0x25, '8', '('),
+    (0x26, '9', ')'),
+    (0x27, '0', ' '),
+    (0x2d, '-', '='),
+    (0x2e, '^', '~'),
+    (0x2f, '@', '`'),
+    (0x30, '[', '{'),
+    // JISキーボードの]はNon-US #を送るが、Backslashでも同じ文字になる
+    (0x32, ']', '}'),
+    (0x31, ']', '}'),
+    (0x33, ';', '+'),
+    (0x34, ':', '*'),
+    (0x35, ' ', ' '), // 半角／全角
+    (0x36, ',', '<'),
+    (0x37, '.', '>'),
+    (0x38, '/', '?'),
+    (0x87, '\\', '_'), // ろ
+    (0x89, '\\', '|'), // ￥
+];
+
+impl HostLayout {
+    fn symbols(&self) -> &'static [Symbol] {
+        match self {
+            HostLayout::Us => &US_SYMBOLS,
+            HostLayout::Jis => &JIS_SYMBOLS,
+        }
+    }
+
+    /// 記号と数字のキーを押したときの文字。それ以外のキーならNone
+    pub(crate) fn char(&self, usage: u8, shift: bool) -> Option<char> {
+        self.symbols()
+            .iter()
+            .find(|(u, _, _)| *u == usage)
+            .map(|(_, c, shifted)| if shift { *shifted } else { *c })
+    }
+
+    /// `c`を入力するための(修飾キーのフラグ, Usage ID)
+    pub(crate) fn usage(&self, c: char) -> Option<(u8, u8)> {
+        if c == ' ' {
+            return None;
+        }
+        let symbols = self.symbols();
+        symbols
+            .iter()
+            .find(|(_, unshifted, _)| *unshifted == c)
+            .map(|(usage, _, _)| (0x00, *usage))
+            .or_else(|| {
+                symbols
+                    .iter()
+                    .find(|(_, _, shifted)| *shifted == c)
+                    .map(|(usage, _, _)| (SHIFT, *usage))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::Key;
+
+    #[test]
+    // USとJISで同じ文字になるキーの組み合わせを探せる
+    fn test_usage() {
+        assert_eq!(Some((SHIFT, 0x25)), HostLayout::Us.usage('*'));
+        assert_eq!(Some((SHIFT, 0x34)), HostLayout::Jis.usage('*'));
+        assert_eq!(Some((0x00, 0x2f)), HostLayout::Jis.usage('@'));
+        assert_eq!(Some((SHIFT, 0x32)), HostLayout::Jis.usage('}'));
+        assert_eq!(Some((SHIFT, 0x89)), HostLayout::Jis.usage('|'));
+        assert_eq!(None, HostLayout::Jis.usage(' '));
+        assert_eq!(Some(':'), HostLayout::Jis.char(0x34, false));
+        assert_eq!(None, HostLayout::Jis.char(0x04, false));
+    }
+
+    #[test]
+    // 修飾済みキーはJISでも同じ文字になり、修飾されていないキーはそのまま送られる
+    fn test_resolve_keys() {
+        assert_eq!((SHIFT, Some(0x25)), Key::Asterisk.resolve(HostLayout::Us));
+        assert_eq!((SHIFT, Some(0x34)), Key::Asterisk.resolve(HostLayout::Jis));
+        assert_eq!((0x00, Some(0x2f)), Key::At.resolve(HostLayout::Jis));
+        assert_eq!(
+            (0x00, Some(0x34)),
+            Key::Apostrophe_Quotation.resolve(HostLayout::Jis)
+        );
+        assert_eq!('*', Key::Asterisk.to_char(HostLayout::Jis));
+        assert_eq!('\'', Key::Apostrophe_Quotation.to_char(HostLayout::Us));
+        assert_eq!(':', Key::Apostrophe_Quotation.to_char(HostLayout::Jis));
+        assert_eq!('a', Key::A.to_char(HostLayout::Jis));
+    }
+}
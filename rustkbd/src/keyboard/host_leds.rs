@@ -0,0 +1,29 @@
+use defmt::Format;
+
+/// ホストから送られてくるキーボードのLEDの状態。HIDのLEDのページのビットの並び
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Format)]
+pub struct HostLeds(u8);
+
+impl HostLeds {
+    pub const NUM_LOCK: HostLeds = HostLeds(0b0000_0001);
+    pub const CAPS_LOCK: HostLeds = HostLeds(0b0000_0010);
+    pub const SCROLL_LOCK: HostLeds = HostLeds(0b0000_0100);
+    pub const COMPOSE: HostLeds = HostLeds(0b0000_1000);
+    pub const KANA: HostLeds = HostLeds(0b0001_0000);
+
+    pub const fn empty() -> Self {
+        HostLeds(0)
+    }
+
+    pub const fn from_bits(bits: u8) -> Self {
+        HostLeds(bits)
+    }
+
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: HostLeds) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
@@ -1,194 +1,241 @@
 use defmt::Format;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
-#[repr(u16)]
-#[allow(non_camel_case_types, dead_code)]
-pub enum Key {
+use super::{HostLayout, UnicodeMode};
+
+/// データを持たないキーの名前と判別値の表から、`Key`と判別値との変換を作る
+macro_rules! keys {
+    ($($name:ident = $code:literal,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+        #[repr(u16)]
+        #[allow(non_camel_case_types, dead_code)]
+        pub enum Key {
+            $($name = $code,)*
+            /// 任意の文字をホストの入力方式（`UnicodeMode`）で入力する
+            Unicode(char) = UNICODE,
+            /// 修飾キーのフラグとUsage IDの組。`layout!`の`C(Tab)`など
+            WithModifiers(u8, u8) = WITH_MODIFIERS,
+            /// 単独で押して離すとUsage IDのキーを入力し、押している間は`Layer::index()`のレイヤにする
+            LayerTap(u8, u8) = LAYER_TAP,
+        }
+
+        impl Key {
+            /// データを持たないキーすべて
+            pub const FIELDLESS: &'static [Key] = &[$(Key::$name,)*];
+
+            /// 判別値。修飾済みキーは修飾キーとの組、メディアキーは0x10xx
+            pub(crate) const fn code(&self) -> u16 {
+                match self {
+                    $(Key::$name => $code,)*
+                    Key::Unicode(_) => UNICODE,
+                    Key::WithModifiers(..) => WITH_MODIFIERS,
+                    Key::LayerTap(..) => LAYER_TAP,
+                }
+            }
+        }
+
+        impl TryFrom<u16> for Key {
+            type Error = u16;
+
+            fn try_from(value: u16) -> Result<Self, Self::Error> {
+                match value {
+                    $($code => Ok(Key::$name),)*
+                    _ => Err(value),
+                }
+            }
+        }
+    };
+}
+
+const UNICODE: u16 = 0x2100;
+const WITH_MODIFIERS: u16 = 0x2200;
+const LAYER_TAP: u16 = 0x2300;
+
+keys! {
     // FIXME: We need shorter notation.
     None = 0x0000,
-    Transparent,
+    Transparent = 0x0001,
     A = 0x0004,
-    B,
-    C,
-    D,
-    E,
-    F,
-    G,
-    H,
-    I,
-    J,
-    K,
-    L,
-    M,
-    N,
-    O,
-    P,
-    Q,
-    R,
-    S,
-    T,
-    U,
-    V,
-    W,
-    X,
-    Y,
-    Z,
-    Digit1_Exclamation,
-    Digit2_At,
-    Digit3_Number,
-    Digit4_Dollar,
-    Digit5_Percent,
-    Digit6_Circumflex,
-    Digit7_Ampersand,
-    Digit8_Asterisk,
-    Digit9_LeftParenthesis,
-    Digit0_RightParenthesis,
-    Enter,
-    Escape,
-    Delete,
-    Tab,
-    Space,
-    HyphenMinus_LowLine,
-    Equal_Plus,
-    LeftSquareBracket_LeftCurlyBracket,
-    RightSquareBracket_RightCurlyBracket,
-    Backslash_VerticalBar,
-    NonUs_Number_Tilde,
-    Semicolon_Colon,
-    Apostrophe_Quotation,
-    Grave_Tilde,
-    Comma_LessThan,
-    Period_GreaterThan,
-    Slash_Question,
-    CapsLock,
-    F1,
-    F2,
-    F3,
-    F4,
-    F5,
-    F6,
-    F7,
-    F8,
-    F9,
-    F10,
-    F11,
-    F12,
-    PrintScreen,
-    ScrollLock,
-    Pause,
-    Insert,
-    Home,
-    PageUp,
-    DeleteForward,
-    End,
-    PageDown,
-    RightArrow,
-    LeftArrow,
-    DownArrow,
-    UpArrow,
-    Keypad_NumLock_Clear,
-    Keypad_Slash,
-    Keypad_Asterisk,
-    Keypad_HyphenMinus,
-    Keypad_Plus,
-    Keypad_Enter,
-    Keypad_Digit1_End,
-    Keypad_Digit2_DownArrow,
-    Keypad_Digit3_PageDown,
-    Keypad_Digit4_LeftArrow,
-    Keypad_Digit5,
-    Keypad_Digit6_RightArrow,
-    Keypad_Digit7_Home,
-    Keypad_Digit8_UpArrow,
-    Keypad_Digit9_PageUp,
-    Keypad_Digit0_Insert,
-    Keypad_Period_Delete,
-    NonUs_BackSlash_VerticalBar,
-    Application,
-    Power,
-    Keypad_Equal,
-    F13,
-    F14,
-    F15,
-    F16,
-    F17,
-    F18,
-    F19,
-    F20,
-    F21,
-    F22,
-    F23,
-    F24,
-    Execute,
-    Help,
-    Menu,
-    Select,
-    Stop,
-    Again,
-    Undo,
-    Cut,
-    Copy,
-    Paste,
-    Find,
-    Mute,
-    VolumeUp,
-    VolumeDown,
-    LockingCapsLock,
-    LockingNumLock,
-    LockingScrollLock,
-    Keypad_Comma,
-    Keypad_EqualSign, // Used on AS/400 keyboards
-    International1,   // LowLine, VerticalBar, Backslash, ろ
-    International2,   // カタカナ／ひらがな, かな(PC98)
-    International3,   // LowLine, LeftSquareBracket, ￥, HyphenMinus
-    International4,   // 前候補, 変換, XFER(PC98)
-    International5,   // 無変換, NFER(PC98)
-    International6,   // Comma(PC98)
-    International7,   // Toggle Double-Byte/Single-Byte mode
-    International8,   // Undefined
-    International9,   // Undefined
-    Lang1,            // Hangul/English toggle key
-    Lang2,            // Hanja conversion key
-    Lang3,            // Katakana key
-    Lang4,            // Hiragana key
-    Lang5,            // Zenkaku/Hankaku key
-    Lang6,            // Reserved
-    Lang7,            // Reserved
-    Lang8,            // Reserved
-    Lang9,            // Reserved
-    AlternateErase,   // e.g. Erase-Eaze key
-    SysReq_Attention,
-    Cancel,
-    Clear,
-    Prior,
-    Return,
-    Separator,
-    Out,
-    Oper,
-    Clear_Again,
-    CrSel_Props,
-    ExSel,
+    B = 0x0005,
+    C = 0x0006,
+    D = 0x0007,
+    E = 0x0008,
+    F = 0x0009,
+    G = 0x000a,
+    H = 0x000b,
+    I = 0x000c,
+    J = 0x000d,
+    K = 0x000e,
+    L = 0x000f,
+    M = 0x0010,
+    N = 0x0011,
+    O = 0x0012,
+    P = 0x0013,
+    Q = 0x0014,
+    R = 0x0015,
+    S = 0x0016,
+    T = 0x0017,
+    U = 0x0018,
+    V = 0x0019,
+    W = 0x001a,
+    X = 0x001b,
+    Y = 0x001c,
+    Z = 0x001d,
+    Digit1_Exclamation = 0x001e,
+    Digit2_At = 0x001f,
+    Digit3_Number = 0x0020,
+    Digit4_Dollar = 0x0021,
+    Digit5_Percent = 0x0022,
+    Digit6_Circumflex = 0x0023,
+    Digit7_Ampersand = 0x0024,
+    Digit8_Asterisk = 0x0025,
+    Digit9_LeftParenthesis = 0x0026,
+    Digit0_RightParenthesis = 0x0027,
+    Enter = 0x0028,
+    Escape = 0x0029,
+    Delete = 0x002a,
+    Tab = 0x002b,
+    Space = 0x002c,
+    HyphenMinus_LowLine = 0x002d,
+    Equal_Plus = 0x002e,
+    LeftSquareBracket_LeftCurlyBracket = 0x002f,
+    RightSquareBracket_RightCurlyBracket = 0x0030,
+    Backslash_VerticalBar = 0x0031,
+    NonUs_Number_Tilde = 0x0032,
+    Semicolon_Colon = 0x0033,
+    Apostrophe_Quotation = 0x0034,
+    Grave_Tilde = 0x0035,
+    Comma_LessThan = 0x0036,
+    Period_GreaterThan = 0x0037,
+    Slash_Question = 0x0038,
+    CapsLock = 0x0039,
+    F1 = 0x003a,
+    F2 = 0x003b,
+    F3 = 0x003c,
+    F4 = 0x003d,
+    F5 = 0x003e,
+    F6 = 0x003f,
+    F7 = 0x0040,
+    F8 = 0x0041,
+    F9 = 0x0042,
+    F10 = 0x0043,
+    F11 = 0x0044,
+    F12 = 0x0045,
+    PrintScreen = 0x0046,
+    ScrollLock = 0x0047,
+    Pause = 0x0048,
+    Insert = 0x0049,
+    Home = 0x004a,
+    PageUp = 0x004b,
+    DeleteForward = 0x004c,
+    End = 0x004d,
+    PageDown = 0x004e,
+    RightArrow = 0x004f,
+    LeftArrow = 0x0050,
+    DownArrow = 0x0051,
+    UpArrow = 0x0052,
+    Keypad_NumLock_Clear = 0x0053,
+    Keypad_Slash = 0x0054,
+    Keypad_Asterisk = 0x0055,
+    Keypad_HyphenMinus = 0x0056,
+    Keypad_Plus = 0x0057,
+    Keypad_Enter = 0x0058,
+    Keypad_Digit1_End = 0x0059,
+    Keypad_Digit2_DownArrow = 0x005a,
+    Keypad_Digit3_PageDown = 0x005b,
+    Keypad_Digit4_LeftArrow = 0x005c,
+    Keypad_Digit5 = 0x005d,
+    Keypad_Digit6_RightArrow = 0x005e,
+    Keypad_Digit7_Home = 0x005f,
+    Keypad_Digit8_UpArrow = 0x0060,
+    Keypad_Digit9_PageUp = 0x0061,
+    Keypad_Digit0_Insert = 0x0062,
+    Keypad_Period_Delete = 0x0063,
+    NonUs_BackSlash_VerticalBar = 0x0064,
+    Application = 0x0065,
+    Power = 0x0066,
+    Keypad_Equal = 0x0067,
+    F13 = 0x0068,
+    F14 = 0x0069,
+    F15 = 0x006a,
+    F16 = 0x006b,
+    F17 = 0x006c,
+    F18 = 0x006d,
+    F19 = 0x006e,
+    F20 = 0x006f,
+    F21 = 0x0070,
+    F22 = 0x0071,
+    F23 = 0x0072,
+    F24 = 0x0073,
+    Execute = 0x0074,
+    Help = 0x0075,
+    Menu = 0x0076,
+    Select = 0x0077,
+    Stop = 0x0078,
+    Again = 0x0079,
+    Undo = 0x007a,
+    Cut = 0x007b,
+    Copy = 0x007c,
+    Paste = 0x007d,
+    Find = 0x007e,
+    Mute = 0x007f,
+    VolumeUp = 0x0080,
+    VolumeDown = 0x0081,
+    LockingCapsLock = 0x0082,
+    LockingNumLock = 0x0083,
+    LockingScrollLock = 0x0084,
+    Keypad_Comma = 0x0085,
+    Keypad_EqualSign = 0x0086, // Used on AS/400 keyboards
+    International1 = 0x0087,   // LowLine, VerticalBar, Backslash, ろ
+    International2 = 0x0088,   // カタカナ／ひらがな, かな(PC98)
+    International3 = 0x0089,   // LowLine, LeftSquareBracket, ￥, HyphenMinus
+    International4 = 0x008a,   // 前候補, 変換, XFER(PC98)
+    International5 = 0x008b,   // 無変換, NFER(PC98)
+    International6 = 0x008c,   // Comma(PC98)
+    International7 = 0x008d,   // Toggle Double-Byte/Single-Byte mode
+    International8 = 0x008e,   // Undefined
+    International9 = 0x008f,   // Undefined
+    Lang1 = 0x0090,            // Hangul/English toggle key
+    Lang2 = 0x0091,            // Hanja conversion key
+    Lang3 = 0x0092,            // Katakana key
+    Lang4 = 0x0093,            // Hiragana key
+    Lang5 = 0x0094,            // Zenkaku/Hankaku key
+    Lang6 = 0x0095,            // Reserved
+    Lang7 = 0x0096,            // Reserved
+    Lang8 = 0x0097,            // Reserved
+    Lang9 = 0x0098,            // Reserved
+    AlternateErase = 0x0099,   // e.g. Erase-Eaze key
+    SysReq_Attention = 0x009a,
+    Cancel = 0x009b,
+    Clear = 0x009c,
+    Prior = 0x009d,
+    Return = 0x009e,
+    Separator = 0x009f,
+    Out = 0x00a0,
+    Oper = 0x00a1,
+    Clear_Again = 0x00a2,
+    CrSel_Props = 0x00a3,
+    ExSel = 0x00a4,
     LeftControl = 0x00e0,
-    LeftShift,
-    LeftAlt,
-    LeftGui, // Win key(Windows), Command key(Mac), Meta key
-    RightControl,
-    RightShift,
-    RightAlt,
-    RightGui, // Win key(Windows), Command key(Mac), Meta key
+    LeftShift = 0x00e1,
+    LeftAlt = 0x00e2,
+    LeftGui = 0x00e3, // Win key(Windows), Command key(Mac), Meta key
+    RightControl = 0x00e4,
+    RightShift = 0x00e5,
+    RightAlt = 0x00e6,
+    RightGui = 0x00e7, // Win key(Windows), Command key(Mac), Meta key
     MediaZero = 0x1000,
-    MediaPlay = 0x10B0,
-    MediaPause = 0x10B1,
-    MediaRecord = 0x10B2,
-    MediaNextTrack = 0x10B5,
-    MediaPrevTrack = 0x10B6,
-    MediaStop = 0x10B7,
-    MediaRandomPlay = 0x10B9,
-    MediaRepeat = 0x10BC,
-    MediaPlayPause = 0x10CD,
-    MediaMute = 0x10E2,
-    MediaVolumeIncrement = 0x10E9,
-    MediaVolumeDecrement = 0x10EA,
+    MediaPlay = 0x10b0,
+    MediaPause = 0x10b1,
+    MediaRecord = 0x10b2,
+    MediaNextTrack = 0x10b5,
+    MediaPrevTrack = 0x10b6,
+    MediaStop = 0x10b7,
+    MediaRandomPlay = 0x10b9,
+    MediaRepeat = 0x10bc,
+    MediaPlayPause = 0x10cd,
+    MediaMute = 0x10e2,
+    MediaVolumeIncrement = 0x10e9,
+    MediaVolumeDecrement = 0x10ea,
     Tilde = 0xe135,
     Exclamation = 0xe11e,
     At = 0xe11f,
@@ -210,65 +257,206 @@ pub enum Key {
     LessThan = 0xe136,
     GreaterThan = 0xe137,
     Question = 0xe138,
+    UnicodeModeLinux = 0x2000,
+    UnicodeModeMacOs = 0x2001,
+    UnicodeModeWinCompose = 0x2002,
+    UnicodeModeWindowsAlt = 0x2003,
 }
 
 impl Key {
     pub fn is_noop(&self) -> bool {
-        *self as u16 <= 0x0001
+        self.code() <= 0x0001
     }
 
-    pub fn is_modifier_key(&self) -> bool {
-        (*self as u16) >= 0x00e0 && (*self as u16) <= 0x00e7
+    pub const fn is_modifier_key(&self) -> bool {
+        self.code() >= 0x00e0 && self.code() <= 0x00e7
     }
 
-    pub fn is_modified_key(&self) -> bool {
-        (*self as u16 >> 8) >= 0x00e0
-            && (*self as u16 >> 8) <= 0x00e7
-            && (*self as u16 & 0xff) >= 0x0004
-            && (*self as u16 & 0xff) < 0x00e0
+    pub const fn is_modified_key(&self) -> bool {
+        (self.code() >> 8) >= 0x00e0
+            && (self.code() >> 8) <= 0x00e7
+            && (self.code() & 0xff) >= 0x0004
+            && (self.code() & 0xff) < 0x00e0
     }
 
-    pub fn is_keyboard_key(&self) -> bool {
-        *self as u16 >= 0x0004 && (*self as u16) < 0x00e0
+    pub const fn is_keyboard_key(&self) -> bool {
+        self.code() >= 0x0004 && self.code() < 0x00e0
     }
 
     pub fn key_code(&self) -> Option<u8> {
         if self.is_modified_key() || self.is_keyboard_key() {
-            Some((*self as u16 & 0xff) as u8)
+            Some((self.code() & 0xff) as u8)
         } else {
             None
         }
     }
 
     pub fn is_media_key(&self) -> bool {
-        (*self as u16) >= 0x1000 && (*self as u16) < 0x2000
+        self.code() >= 0x1000 && self.code() < 0x2000
     }
 
-    pub(crate) fn modifier_key_flag(&self) -> u8 {
+    pub(crate) const fn modifier_key_flag(&self) -> u8 {
         if self.is_modifier_key() {
-            1 << ((*self as u16) - 0x00e0)
+            1 << (self.code() - 0x00e0)
         } else if self.is_modified_key() {
-            1 << ((*self as u16 >> 8) - 0x00e0)
+            1 << ((self.code() >> 8) - 0x00e0)
         } else {
             0x00
         }
     }
 
+    /// `key`に修飾キーを足す。修飾できないキーならpanicするので、定数ではコンパイルエラーになる
+    pub const fn with_modifiers(modifiers: u8, key: Key) -> Key {
+        match key {
+            Key::WithModifiers(m, usage) => Key::WithModifiers(modifiers | m, usage),
+            _ if key.is_keyboard_key() => Key::WithModifiers(modifiers, key.code() as u8),
+            _ if key.is_modified_key() => Key::WithModifiers(
+                modifiers | key.modifier_key_flag(),
+                (key.code() & 0xff) as u8,
+            ),
+            _ => panic!("only keyboard keys can be modified"),
+        }
+    }
+
+    /// 押している間は`layer`のレイヤにし、タップすると`key`を入力する
+    pub const fn layer_tap(layer: u8, key: Key) -> Key {
+        if key.is_keyboard_key() {
+            Key::LayerTap(layer, key.code() as u8)
+        } else {
+            panic!("only keyboard keys can be tapped")
+        }
+    }
+
+    /// ホストに送る(修飾キーのフラグ, Usage ID)
+    ///
+    /// 修飾済みキーは`layout`で同じ文字になるように読み替える
+    pub(crate) fn resolve(&self, layout: HostLayout) -> (u8, Option<u8>) {
+        if let Key::WithModifiers(modifiers, usage) = self {
+            return (*modifiers, Some(*usage));
+        }
+        if self.is_modified_key() && layout != HostLayout::Us {
+            let resolved = HostLayout::Us
+                .char((self.code() & 0xff) as u8, true)
+                .and_then(|c| layout.usage(c));
+            if let Some((modifier, usage)) = resolved {
+                return (modifier, Some(usage));
+            }
+        }
+        (self.modifier_key_flag(), self.key_code())
+    }
+
+    /// `layout`のホストで入力される文字。表示用なので、文字のないキーは記号で表す
+    pub fn to_char(&self, layout: HostLayout) -> char {
+        static CHARS: &[u8] = (r##"abcdefghijklmnopqrstuvwxyz1234567890REBT -=[]\#;'`,./ FFFFFFFFFFFF              /*-+R1234567890.\  =FFFFFFFFFFFF                 ,=IIIIIIIIILLLLLLLLLB    E      "##).as_bytes();
+        let usage = (self.code() & 0xff) as u8;
+        match self {
+            Key::Unicode(c) => *c,
+            Key::WithModifiers(_, usage) | Key::LayerTap(_, usage) => {
+                Key::try_from(*usage as u16).map_or(' ', |key| key.to_char(layout))
+            }
+            // 修飾済みキーはUSレイアウトでの文字を表すので、ホストによらない
+            _ if self.is_modified_key() => HostLayout::Us.char(usage, true).unwrap_or(' '),
+            _ if self.is_keyboard_key() && self.code() <= 0x00a4 => layout
+                .char(usage, false)
+                .unwrap_or(CHARS[(usage as usize) - 0x0004] as char),
+            _ => ' ',
+        }
+    }
+
+    /// `UnicodeMode`を切り替えるキーなら、その入力方式
+    pub fn unicode_mode(&self) -> Option<UnicodeMode> {
+        match self {
+            Key::UnicodeModeLinux => Some(UnicodeMode::Linux),
+            Key::UnicodeModeMacOs => Some(UnicodeMode::MacOs),
+            Key::UnicodeModeWinCompose => Some(UnicodeMode::WinCompose),
+            Key::UnicodeModeWindowsAlt => Some(UnicodeMode::WindowsAlt),
+            _ => None,
+        }
+    }
+
     pub(crate) fn media_usage_id(&self) -> u16 {
         if self.is_media_key() {
-            (*self as u16) & 0x0fff
+            self.code() & 0x0fff
         } else {
             0x0000
         }
     }
 }
 
-impl From<Key> for char {
+/// `Unicode`などのデータを持つキーはデータのない判別値になるので、`TryFrom<u16>`で元に戻せない
+impl From<Key> for u16 {
     fn from(key: Key) -> Self {
-        static CHARS: &[u8] = (r##"abcdefghijklmnopqrstuvwxyz1234567890REBT -=[]\#;'`,./ FFFFFFFFFFFF              /*-+R1234567890.\  =FFFFFFFFFFFF                 ,=IIIIIIIIILLLLLLLLLB    E      "##).as_bytes();
-        match key as u8 {
-            0x0004..=0x00a4 => CHARS[(key as usize) - 0x0004] as char,
-            _ => ' ',
+        key.code()
+    }
+}
+
+/// 保存や通信に使う、データを持つキーも元に戻せる4バイトの表現
+///
+/// 先頭の1バイトで種類を表す。データのないキーは判別値を、
+/// `Unicode`は文字のコードポイントを、`WithModifiers`と`LayerTap`は2つの値をリトルエンディアンで続ける。
+impl From<Key> for [u8; 4] {
+    fn from(key: Key) -> Self {
+        match key {
+            Key::Unicode(c) => {
+                let [a, b, c, _] = (c as u32).to_le_bytes();
+                [1, a, b, c]
+            }
+            Key::WithModifiers(modifiers, usage) => [2, modifiers, usage, 0],
+            Key::LayerTap(layer, usage) => [3, layer, usage, 0],
+            _ => {
+                let [a, b] = key.code().to_le_bytes();
+                [0, a, b, 0]
+            }
+        }
+    }
+}
+
+impl TryFrom<[u8; 4]> for Key {
+    type Error = [u8; 4];
+
+    fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
+        match value {
+            [0, a, b, 0] => Key::try_from(u16::from_le_bytes([a, b])).ok(),
+            [1, a, b, c] => char::from_u32(u32::from_le_bytes([a, b, c, 0])).map(Key::Unicode),
+            [2, modifiers, usage, 0] => Some(Key::WithModifiers(modifiers, usage)),
+            [3, layer, usage, 0] => Some(Key::LayerTap(layer, usage)),
+            _ => None,
+        }
+        .ok_or(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // データを持たないキーはすべて判別値から元に戻せる
+    fn test_code_round_trip() {
+        for key in Key::FIELDLESS {
+            assert_eq!(Ok(*key), Key::try_from(u16::from(*key)));
+        }
+        assert_eq!(Err(0x0002), Key::try_from(0x0002));
+        assert_eq!(Err(0x2100), Key::try_from(0x2100));
+    }
+
+    #[test]
+    // データを持つキーも4バイトの表現から元に戻せる
+    fn test_bytes_round_trip() {
+        for key in [
+            Key::A,
+            Key::Asterisk,
+            Key::MediaMute,
+            Key::UnicodeModeMacOs,
+            Key::Unicode('😀'),
+            Key::WithModifiers(0x01, 0x2b),
+            Key::LayerTap(3, 0x2c),
+        ] {
+            let bytes: [u8; 4] = key.into();
+            assert_eq!(Ok(key), Key::try_from(bytes));
         }
+        assert!(Key::try_from([0, 0x02, 0x00, 0]).is_err());
+        assert!(Key::try_from([1, 0x00, 0xd8, 0x00]).is_err());
+        assert!(Key::try_from([4, 0, 0, 0]).is_err());
     }
 }
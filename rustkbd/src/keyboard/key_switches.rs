@@ -1,13 +1,45 @@
 use core::hash::Hash;
 
-use crate::Vec;
+use defmt::Format;
+pub use rustkbd_macros::KeySwitchIdentifier;
+
+use crate::{
+    keyboard::{KeyboardState, Layer},
+    Vec,
+};
 
 pub trait KeySwitches<const SZ: usize, const RO: usize> {
     type Identifier: KeySwitchIdentifier<SZ>;
     fn scan(&mut self) -> Vec<Self::Identifier, RO>;
+
+    /// ホストにつながっているかを`scan`の前に知らせる。分割キーボードで役割を決めるのに使う
+    fn set_host_connected(&mut self, _connected: bool) {}
+
+    /// `main_loop`で決まった状態を知らせる。分割キーボードではControllerからReceiverに送る
+    fn share_state<L: Layer>(&mut self, _state: &KeyboardState<L, RO>) {}
+
+    /// Controllerから送られてきた状態。分割キーボードのReceiverで両方に同じ状態を表示するのに使う
+    fn shared_state<L: Layer>(&self) -> Option<KeyboardState<L, RO>> {
+        None
+    }
 }
 
+/// スイッチの識別子
+///
+/// 分割キーボードでは`SZ`バイトに変換して送る。
+/// 受け取ったバイト列は壊れていることがあるので、読むときは`TryFrom`で失敗できる。
+/// `From<[u8; SZ]>`を実装していればそのまま使える。
 pub trait KeySwitchIdentifier<const SZ: usize>:
-    Copy + Eq + From<[u8; SZ]> + Into<[u8; SZ]> + Hash
+    Copy + Eq + TryFrom<[u8; SZ]> + Into<[u8; SZ]> + Hash
 {
 }
+
+/// バイト列がスイッチの識別子として読めなかった
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct InvalidSwitchIdentifier;
+
+/// 行列上の位置に対応付けられるスイッチ
+pub trait MatrixPosition {
+    /// `rows`行`cols`列のキーマップ上での(行, 列)を返す
+    fn position(&self, rows: usize, cols: usize) -> (usize, usize);
+}
@@ -1,10 +1,15 @@
 use heapless::Vec;
 
-use super::{Key, Layer};
+use super::{HostLayout, HostLeds, Key, Layer};
 
 #[derive(Debug, Clone)]
 #[non_exhaustive]
 pub struct KeyboardState<L: Layer, const RO: usize> {
     pub layer: L,
     pub keys: Vec<Key, RO>,
+    /// キーを文字で表示するときに使う
+    pub host_layout: HostLayout,
+    pub host_leds: HostLeds,
+    /// 打鍵の速さ。`Controller`に時計がなければ0
+    pub wpm: u8,
 }
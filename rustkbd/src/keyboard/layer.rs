@@ -1,5 +1,20 @@
 pub use rustkbd_macros::Layer;
 
-pub trait Layer: Copy + Eq + Default {
+pub trait Layer: Copy + Eq + Default + 'static {
+    /// すべてのレイヤ（宣言順）
+    const ALL: &'static [Self];
+
+    /// 透過キーのときに落ちる先のレイヤ
     fn below(&self) -> Option<Self>;
+
+    /// レイヤの通し番号（0始まり）
+    ///
+    /// キーマップの配列の添字になるので、0から`ALL.len() - 1`までを重ならずに使う
+    fn index(&self) -> usize;
+
+    /// `index()`がその値になるレイヤ
+    fn from_index(index: usize) -> Option<Self>;
+
+    /// 表示用の名前
+    fn name(&self) -> &'static str;
 }
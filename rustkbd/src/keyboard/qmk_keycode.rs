@@ -0,0 +1,140 @@
+use super::Key;
+
+/**
+ * QMKのキーコードとの相互変換
+ * cf. https://github.com/qmk/qmk_firmware/blob/master/docs/keycodes_basic.md
+ */
+impl Key {
+    /// QMK/VIAのキーコードに変換する。対応するキーコードがなければNone
+    pub fn to_qmk_keycode(&self) -> Option<u16> {
+        let code = self.code();
+        if self.is_noop() || self.is_keyboard_key() || self.is_modifier_key() {
+            Some(code)
+        } else if self.is_modified_key() {
+            mods_keycode(self.modifier_key_flag(), (code & 0xff) as u8)
+        } else {
+            match self {
+                Key::WithModifiers(modifiers, usage) => mods_keycode(*modifiers, *usage),
+                Key::LayerTap(layer, usage) if *layer < 0x10 => {
+                    Some(0x4000 | (*layer as u16) << 8 | *usage as u16)
+                }
+                Key::MediaMute => Some(0x00a8),
+                Key::MediaVolumeIncrement => Some(0x00a9),
+                Key::MediaVolumeDecrement => Some(0x00aa),
+                Key::MediaNextTrack => Some(0x00ab),
+                Key::MediaPrevTrack => Some(0x00ac),
+                Key::MediaStop => Some(0x00ad),
+                Key::MediaPlayPause => Some(0x00ae),
+                _ => None,
+            }
+        }
+    }
+
+    /// QMK/VIAのキーコードから変換する。Keyで表せないキーコードはNone
+    pub fn from_qmk_keycode(keycode: u16) -> Option<Key> {
+        match keycode {
+            0x0000..=0x00a4 | 0x00e0..=0x00e7 => Key::try_from(keycode).ok(),
+            0x00a8 => Some(Key::MediaMute),
+            0x00a9 => Some(Key::MediaVolumeIncrement),
+            0x00aa => Some(Key::MediaVolumeDecrement),
+            0x00ab => Some(Key::MediaNextTrack),
+            0x00ac => Some(Key::MediaPrevTrack),
+            0x00ad => Some(Key::MediaStop),
+            0x00ae => Some(Key::MediaPlayPause),
+            0x0100..=0x1fff => {
+                let mods = ((keycode >> 8) & 0x0f) as u8;
+                let usage = (keycode & 0xff) as u8;
+                if mods == 0 || !Key::try_from(usage as u16).is_ok_and(|k| k.is_keyboard_key()) {
+                    return None;
+                }
+                let modifiers = if keycode & 0x1000 != 0 {
+                    mods << 4
+                } else {
+                    mods
+                };
+                // 修飾キー1つだけなら修飾済みキーで表せることがある
+                let modifier = 0x00e0 + modifiers.trailing_zeros() as u16;
+                Key::try_from(modifier << 8 | usage as u16)
+                    .ok()
+                    .filter(|key| modifiers.count_ones() == 1 && key.is_modified_key())
+                    .or(Some(Key::WithModifiers(modifiers, usage)))
+            }
+            0x4000..=0x4fff => {
+                let usage = (keycode & 0xff) as u8;
+                Key::try_from(usage as u16)
+                    .ok()
+                    .filter(Key::is_keyboard_key)
+                    .map(|_| Key::LayerTap((keycode >> 8) as u8 & 0x0f, usage))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// QMKの修飾キーは左右のどちらかにしかできない
+fn mods_keycode(modifiers: u8, usage: u8) -> Option<u16> {
+    let mods = if modifiers & 0xf0 == 0 {
+        (modifiers as u16) << 8
+    } else if modifiers & 0x0f == 0 {
+        0x1000 | ((modifiers >> 4) as u16) << 8
+    } else {
+        return None;
+    };
+    Some(mods | usage as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 基本的なキーはHIDのUsage IDと同じ
+    fn test_basic_keycodes() {
+        assert_eq!(Some(0x0000), Key::None.to_qmk_keycode());
+        assert_eq!(Some(0x0001), Key::Transparent.to_qmk_keycode());
+        assert_eq!(Some(0x0004), Key::A.to_qmk_keycode());
+        assert_eq!(Some(0x00e1), Key::LeftShift.to_qmk_keycode());
+        assert_eq!(Some(Key::Escape), Key::from_qmk_keycode(0x0029));
+        assert_eq!(Some(Key::RightGui), Key::from_qmk_keycode(0x00e7));
+    }
+
+    #[test]
+    // 修飾済みキーはLSFT(kc)などに対応する
+    fn test_modified_keycodes() {
+        assert_eq!(Some(0x0225), Key::Asterisk.to_qmk_keycode());
+        assert_eq!(Some(Key::Asterisk), Key::from_qmk_keycode(0x0225));
+        assert_eq!(Some(Key::Question), Key::from_qmk_keycode(0x0238));
+        // 修飾済みキーのない組み合わせ
+        assert_eq!(
+            Some(Key::WithModifiers(0x01, 0x04)),
+            Key::from_qmk_keycode(0x0104)
+        );
+        assert_eq!(
+            Some(Key::WithModifiers(0x06, 0x25)),
+            Key::from_qmk_keycode(0x0625)
+        );
+        assert_eq!(None, Key::WithModifiers(0x11, 0x04).to_qmk_keycode());
+        assert_eq!(Some(0x412c), Key::LayerTap(1, 0x2c).to_qmk_keycode());
+    }
+
+    #[test]
+    // メディアキーはKC_AUDIO_*, KC_MEDIA_*に対応する
+    fn test_media_keycodes() {
+        assert_eq!(Some(0x00ae), Key::MediaPlayPause.to_qmk_keycode());
+        assert_eq!(
+            Some(Key::MediaVolumeIncrement),
+            Key::from_qmk_keycode(0x00a9)
+        );
+        assert_eq!(None, Key::MediaRecord.to_qmk_keycode());
+    }
+
+    #[test]
+    // 変換できるキーコードは往復しても変わらない
+    fn test_round_trip() {
+        for keycode in 0x0000..=0x4fff {
+            if let Some(key) = Key::from_qmk_keycode(keycode) {
+                assert_eq!(Some(keycode), key.to_qmk_keycode());
+            }
+        }
+    }
+}
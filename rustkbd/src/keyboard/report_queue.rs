@@ -0,0 +1,63 @@
+use defmt::Format;
+use heapless::{Deque, Vec};
+
+use super::Key;
+
+/// キューがいっぱいで積めなかった
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct QueueFull;
+
+/// 押されているスイッチとは別に送るレポートの列
+///
+/// 空でない間は、現在のキーの代わりに先頭から1つずつ送られる。
+#[derive(Debug)]
+pub struct ReportQueue<const RO: usize, const N: usize> {
+    reports: Deque<Vec<Key, RO>, N>,
+}
+
+impl<const RO: usize, const N: usize> ReportQueue<RO, N> {
+    pub fn new() -> Self {
+        ReportQueue {
+            reports: Deque::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reports.is_empty()
+    }
+
+    /// あといくつ積めるか
+    pub fn remaining(&self) -> usize {
+        N - self.reports.len()
+    }
+
+    /// キューがいっぱいなら何もせずにErrを返す
+    pub fn push(&mut self, keys: &[Key]) -> Result<(), QueueFull> {
+        let report = Vec::from_slice(&keys[..keys.len().min(RO)]).map_err(|_| QueueFull)?;
+        self.reports.push_back(report).map_err(|_| QueueFull)
+    }
+
+    /// `held`を押したまま`key`を押して離す
+    pub fn tap(&mut self, held: &[Key], key: Key) -> Result<(), QueueFull> {
+        let mut keys =
+            Vec::<Key, RO>::from_slice(&held[..held.len().min(RO - 1)]).map_err(|_| QueueFull)?;
+        keys.push(key).ok();
+        self.push(&keys)?;
+        self.push(held)
+    }
+
+    /// 次に送るレポート。送れたら`pop`する
+    pub fn front(&self) -> Option<&Vec<Key, RO>> {
+        self.reports.front()
+    }
+
+    pub fn pop(&mut self) -> Option<Vec<Key, RO>> {
+        self.reports.pop_front()
+    }
+}
+
+impl<const RO: usize, const N: usize> Default for ReportQueue<RO, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
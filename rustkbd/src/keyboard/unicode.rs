@@ -0,0 +1,198 @@
+use defmt::Format;
+
+use super::{report_queue::QueueFull, Key, ReportQueue};
+
+/// 1文字の入力に使うレポートの最大数
+pub(crate) const UNICODE_MAX_REPORTS: usize = 18;
+
+/// ホストでUnicodeの文字を入力する方式
+/// cf. https://github.com/qmk/qmk_firmware/blob/master/docs/features/unicode.md
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Format)]
+pub enum UnicodeMode {
+    /// IBusのCtrl+Shift+U
+    #[default]
+    Linux,
+    /// 入力ソースをUnicode Hex Inputにして、Optionを押しながら入力する
+    MacOs,
+    /// WinComposeのCompose（右Alt）キーから入力する
+    WinCompose,
+    /// Alt+テンキーの+から入力する（レジストリのEnableHexNumpadが必要）
+    WindowsAlt,
+}
+
+impl UnicodeMode {
+    /// `c`を入力するレポートの列を積む。積みきれないときは何も積まない
+    pub(crate) fn push_reports<const RO: usize, const N: usize>(
+        &self,
+        c: char,
+        queue: &mut ReportQueue<RO, N>,
+    ) -> Result<(), QueueFull> {
+        if queue.remaining() < UNICODE_MAX_REPORTS {
+            return Err(QueueFull);
+        }
+        match self {
+            UnicodeMode::Linux => {
+                queue.push(&[Key::LeftControl, Key::LeftShift, Key::U])?;
+                queue.push(&[])?;
+                push_hex(queue, &[], c as u32, false)?;
+                queue.tap(&[], Key::Space)
+            }
+            UnicodeMode::MacOs => {
+                // 基本多言語面の外はサロゲートペアで入力する
+                let mut units = [0u16; 2];
+                queue.push(&[Key::LeftAlt])?;
+                for unit in c.encode_utf16(&mut units) {
+                    push_hex(queue, &[Key::LeftAlt], *unit as u32, false)?;
+                }
+                queue.push(&[])
+            }
+            UnicodeMode::WinCompose => {
+                queue.tap(&[], Key::RightAlt)?;
+                queue.tap(&[], Key::U)?;
+                push_hex(queue, &[], c as u32, false)?;
+                queue.tap(&[], Key::Enter)
+            }
+            UnicodeMode::WindowsAlt => {
+                queue.push(&[Key::LeftAlt])?;
+                queue.tap(&[Key::LeftAlt], Key::Keypad_Plus)?;
+                push_hex(queue, &[Key::LeftAlt], c as u32, true)?;
+                queue.push(&[])
+            }
+        }
+    }
+}
+
+/// 16進数で（最低4桁）1桁ずつタップする
+fn push_hex<const RO: usize, const N: usize>(
+    queue: &mut ReportQueue<RO, N>,
+    held: &[Key],
+    value: u32,
+    keypad: bool,
+) -> Result<(), QueueFull> {
+    let digits = (8 - value.leading_zeros() as usize / 4).max(4);
+    for i in (0..digits).rev() {
+        let key = hex_digit_key((value >> (i * 4)) as u8 & 0x0f, keypad);
+        queue.tap(held, key)?;
+    }
+    Ok(())
+}
+
+fn hex_digit_key(digit: u8, keypad: bool) -> Key {
+    const DIGITS: [Key; 10] = [
+        Key::Digit0_RightParenthesis,
+        Key::Digit1_Exclamation,
+        Key::Digit2_At,
+        Key::Digit3_Number,
+        Key::Digit4_Dollar,
+        Key::Digit5_Percent,
+        Key::Digit6_Circumflex,
+        Key::Digit7_Ampersand,
+        Key::Digit8_Asterisk,
+        Key::Digit9_LeftParenthesis,
+    ];
+    const KEYPAD_DIGITS: [Key; 10] = [
+        Key::Keypad_Digit0_Insert,
+        Key::Keypad_Digit1_End,
+        Key::Keypad_Digit2_DownArrow,
+        Key::Keypad_Digit3_PageDown,
+        Key::Keypad_Digit4_LeftArrow,
+        Key::Keypad_Digit5,
+        Key::Keypad_Digit6_RightArrow,
+        Key::Keypad_Digit7_Home,
+        Key::Keypad_Digit8_UpArrow,
+        Key::Keypad_Digit9_PageUp,
+    ];
+    const LETTERS: [Key; 6] = [Key::A, Key::B, Key::C, Key::D, Key::E, Key::F];
+    match digit {
+        0..=9 if keypad => KEYPAD_DIGITS[digit as usize],
+        0..=9 => DIGITS[digit as usize],
+        _ => LETTERS[(digit - 10) as usize],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+
+    fn reports<const N: usize>(queue: &mut ReportQueue<4, N>) -> Vec<Vec<Key, 4>, N> {
+        core::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    // Linuxでは Ctrl+Shift+U、16進数、Space の順に1つずつレポートを送る
+    fn test_linux() {
+        let mut queue = ReportQueue::<4, 32>::new();
+        UnicodeMode::Linux.push_reports('あ', &mut queue).unwrap();
+        let reports = reports(&mut queue);
+        let expected: [&[Key]; 12] = [
+            &[Key::LeftControl, Key::LeftShift, Key::U],
+            &[],
+            &[Key::Digit3_Number],
+            &[],
+            &[Key::Digit0_RightParenthesis],
+            &[],
+            &[Key::Digit4_Dollar],
+            &[],
+            &[Key::Digit2_At],
+            &[],
+            &[Key::Space],
+            &[],
+        ];
+        assert_eq!(expected.len(), reports.len());
+        for (expected, report) in expected.iter().zip(reports.iter()) {
+            assert_eq!(*expected, report.as_slice());
+        }
+    }
+
+    #[test]
+    // macOSでは基本多言語面の外の文字はサロゲートペアを入力する
+    fn test_mac_surrogate_pair() {
+        let mut queue = ReportQueue::<4, 32>::new();
+        UnicodeMode::MacOs.push_reports('😀', &mut queue).unwrap();
+        let reports = reports(&mut queue);
+        // Option、8桁分のタップ、離す
+        assert_eq!(1 + 16 + 1, reports.len());
+        assert_eq!([Key::LeftAlt], reports[0].as_slice());
+        // U+D83D
+        assert_eq!([Key::LeftAlt, Key::D], reports[1].as_slice());
+        assert_eq!([Key::LeftAlt, Key::Digit8_Asterisk], reports[3].as_slice());
+        // U+DE00
+        assert_eq!([Key::LeftAlt, Key::D], reports[9].as_slice());
+        assert_eq!([Key::LeftAlt, Key::E], reports[11].as_slice());
+        assert!(reports[17].is_empty());
+    }
+
+    #[test]
+    // Altコードでは数字はテンキーで入力する
+    fn test_windows_alt() {
+        let mut queue = ReportQueue::<4, 32>::new();
+        UnicodeMode::WindowsAlt
+            .push_reports('é', &mut queue)
+            .unwrap();
+        let reports = reports(&mut queue);
+        assert_eq!([Key::LeftAlt, Key::Keypad_Plus], reports[1].as_slice());
+        // U+00E9
+        assert_eq!(
+            [Key::LeftAlt, Key::Keypad_Digit0_Insert],
+            reports[3].as_slice()
+        );
+        assert_eq!([Key::LeftAlt, Key::E], reports[7].as_slice());
+        assert_eq!(
+            [Key::LeftAlt, Key::Keypad_Digit9_PageUp],
+            reports[9].as_slice()
+        );
+    }
+
+    #[test]
+    // キューに入りきらないときは何も積まない
+    fn test_queue_full() {
+        let mut queue = ReportQueue::<4, 20>::new();
+        queue.push(&[Key::A]).unwrap();
+        queue.push(&[Key::A]).unwrap();
+        queue.push(&[Key::A]).unwrap();
+        assert!(UnicodeMode::Linux.push_reports('a', &mut queue).is_err());
+        assert_eq!(17, queue.remaining());
+    }
+}
@@ -0,0 +1,89 @@
+/// 1つの区切りの長さ(µs)
+const BUCKET_US: u32 = 1_000_000;
+/// 数える区切りの数。直近10秒の打鍵から数える
+const BUCKETS: usize = 10;
+
+/// 打鍵の速さ(WPM)。5打鍵を1語とする
+///
+/// 1秒ごとの打鍵の数を直近の`BUCKETS`秒分だけ覚えておく。
+#[derive(Debug, Default)]
+pub struct WpmCounter {
+    buckets: [u16; BUCKETS],
+    /// いまの区切りの位置と、それが始まった時刻(µs)
+    current: usize,
+    started_at: Option<u32>,
+}
+
+impl WpmCounter {
+    pub const fn new() -> Self {
+        WpmCounter {
+            buckets: [0; BUCKETS],
+            current: 0,
+            started_at: None,
+        }
+    }
+
+    /// 時計を`now`(µs)まで進め、過ぎた区切りを空にする
+    pub fn tick(&mut self, now: u32) {
+        let Some(started_at) = self.started_at else {
+            self.started_at = Some(now);
+            return;
+        };
+        let elapsed = (now.wrapping_sub(started_at) / BUCKET_US) as usize;
+        if elapsed == 0 {
+            return;
+        }
+        for _ in 0..elapsed.min(BUCKETS) {
+            self.current = (self.current + 1) % BUCKETS;
+            self.buckets[self.current] = 0;
+        }
+        self.started_at = Some(started_at.wrapping_add(elapsed as u32 * BUCKET_US));
+    }
+
+    pub fn press(&mut self) {
+        self.buckets[self.current] = self.buckets[self.current].saturating_add(1);
+    }
+
+    pub fn wpm(&self) -> u8 {
+        let presses = self.buckets.iter().map(|n| *n as u32).sum::<u32>();
+        // 1分あたりの打鍵の数を5で割る
+        let wpm = presses * (60 / BUCKETS as u32) / 5;
+        wpm.min(u8::MAX as u32) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 直近10秒の打鍵から数え、古い打鍵は数えなくなる
+    fn test_wpm() {
+        let mut counter = WpmCounter::new();
+        counter.tick(0);
+        for i in 0..50 {
+            counter.tick(i * 100_000);
+            counter.press();
+        }
+        // 10秒で50打鍵は1分で300打鍵、60語
+        assert_eq!(60, counter.wpm());
+        counter.tick(9_900_000);
+        assert_eq!(60, counter.wpm());
+        // 始めの1秒の10打鍵が数えられなくなる
+        counter.tick(10_000_000);
+        assert_eq!(48, counter.wpm());
+        counter.tick(15_000_000);
+        assert_eq!(0, counter.wpm());
+    }
+
+    #[test]
+    // 時計が一周しても数えられる
+    fn test_wrap() {
+        let mut counter = WpmCounter::new();
+        counter.tick(u32::MAX - 500_000);
+        counter.press();
+        counter.tick(600_000);
+        counter.press();
+        assert_eq!(2 * 6 / 5, counter.wpm());
+    }
+}
@@ -1,7 +1,14 @@
 #![no_std]
 
+pub mod console;
+mod crc;
 pub mod keyboard;
+#[cfg(feature = "rp2040")]
+pub mod rp2040;
+#[cfg(any(test, feature = "scenario"))]
+pub mod scenario;
 pub mod split;
+pub mod storage;
 pub mod usb;
 
 pub use heapless::Vec;
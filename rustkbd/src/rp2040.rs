@@ -0,0 +1,2 @@
+mod flash;
+pub use flash::{park_core1, with_core1_paused, Flash, STORAGE_OFFSET, STORAGE_SECTORS};
@@ -0,0 +1,114 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+use rp2040_flash::flash;
+
+/// XIPでフラッシュが読めるアドレス
+const XIP_BASE: u32 = 0x1000_0000;
+/// Raspberry Pi Picoのフラッシュの大きさ
+const FLASH_SIZE: usize = 2048 * 1024;
+const PAGE_SIZE: usize = 256;
+const SECTOR_SIZE: usize = 4096;
+
+/// 設定の保存に使う末尾のセクタの数。ボードのmemory.xではFLASHからこの分を除いておく
+pub const STORAGE_SECTORS: usize = 4;
+pub const STORAGE_OFFSET: u32 = (FLASH_SIZE - STORAGE_SECTORS * SECTOR_SIZE) as u32;
+
+const CORE1_RUNNING: u8 = 0;
+const CORE1_PAUSE_REQUESTED: u8 = 1;
+const CORE1_PAUSED: u8 = 2;
+static CORE1: AtomicU8 = AtomicU8::new(CORE1_RUNNING);
+
+/// RP2040の内蔵フラッシュ
+///
+/// 消去と書き込みの間はXIPが止まるので、割り込みを止めて行う。
+/// core1が動いているときは、core1のループで`park_core1`を呼び、`with_core1_paused`の中で使う。
+pub struct Flash;
+
+impl ErrorType for Flash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for Flash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let src = (XIP_BASE + offset) as *const u8;
+        unsafe { core::ptr::copy_nonoverlapping(src, bytes.as_mut_ptr(), bytes.len()) };
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl NorFlash for Flash {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        cortex_m::interrupt::free(|_| unsafe { flash::flash_range_erase(from, to - from, true) });
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        // ページ単位でしか書けないので、ほかの部分は0xffにする。0xffを書いたビットは変わらない
+        let mut offset = offset as usize;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let start = offset % PAGE_SIZE;
+            let len = bytes.len().min(PAGE_SIZE - start);
+            let mut page = [0xff; PAGE_SIZE];
+            page[start..start + len].copy_from_slice(&bytes[..len]);
+            cortex_m::interrupt::free(|_| unsafe {
+                flash::flash_range_program((offset - start) as u32, &page, true)
+            });
+            offset += len;
+            bytes = &bytes[len..];
+        }
+        Ok(())
+    }
+}
+
+/// core1をRAMの上で待たせてから`f`を実行する
+pub fn with_core1_paused<R>(f: impl FnOnce() -> R) -> R {
+    CORE1.store(CORE1_PAUSE_REQUESTED, Ordering::SeqCst);
+    while CORE1.load(Ordering::SeqCst) != CORE1_PAUSED {
+        core::hint::spin_loop();
+    }
+    let result = f();
+    CORE1.store(CORE1_RUNNING, Ordering::SeqCst);
+    result
+}
+
+/// core1のループから呼ぶ。フラッシュに書き込んでいる間はここで止まる
+pub fn park_core1() {
+    if CORE1.load(Ordering::SeqCst) != CORE1_PAUSE_REQUESTED {
+        return;
+    }
+    cortex_m::interrupt::free(|_| {
+        CORE1.store(CORE1_PAUSED, Ordering::SeqCst);
+        unsafe { wait_while_paused(CORE1.as_ptr()) };
+    });
+}
+
+/// フラッシュから命令を読まないように、RAMに置いてアセンブリで待つ
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe extern "C" fn wait_while_paused(state: *const u8) {
+    core::arch::asm!(
+        "2:",
+        "ldrb {tmp}, [{state}]",
+        "cmp {tmp}, #{paused}",
+        "beq 2b",
+        state = in(reg) state,
+        tmp = out(reg) _,
+        paused = const CORE1_PAUSED,
+    );
+}
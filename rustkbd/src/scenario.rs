@@ -0,0 +1,22 @@
+//! スイッチの時系列を与えて、送られたレポートを確かめるためのもの
+//!
+//! ```text
+//! press(3,7) @0ms; press(0,1) @20ms; release(3,7) @50ms; release(0,1) @60ms
+//! ```
+//!
+//! 分割キーボードでは`press(right 1,0) @0ms`のようにどちら側かを書く。
+
+mod loopback_connection;
+mod mock_switches;
+mod mock_timer;
+mod null_logger;
+mod report_recorder;
+mod script;
+mod scripted_switches;
+
+pub use loopback_connection::{LoopbackConnection, Pipe};
+pub use mock_switches::MockSwitches;
+pub use mock_timer::MockTimer;
+pub use report_recorder::{Report, ReportRecorder};
+pub use script::{Action, ParseError, Scenario, Step};
+pub use scripted_switches::ScriptedSwitches;
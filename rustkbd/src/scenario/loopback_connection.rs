@@ -0,0 +1,86 @@
+use core::{cell::RefCell, convert::Infallible};
+
+use heapless::Deque;
+
+use crate::split::Connection;
+
+/// 片方向のバイト列
+pub struct Pipe {
+    bytes: RefCell<Deque<u8, 512>>,
+}
+
+impl Pipe {
+    pub const fn new() -> Self {
+        Pipe {
+            bytes: RefCell::new(Deque::new()),
+        }
+    }
+}
+
+impl Pipe {
+    /// 線を抜いたときのように、届いていないバイトを捨てる
+    pub fn clear(&self) {
+        self.bytes.borrow_mut().clear();
+    }
+}
+
+impl Default for Pipe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 2本の`Pipe`で向かい合わせにつないだ`Connection`
+///
+/// 読むものがないときは`idle`を呼ぶ。相手側の`poll`を渡しておけば、
+/// 1つのスレッドのまま相手側が応答したことにできる。
+pub struct LoopbackConnection<'a> {
+    rx: &'a Pipe,
+    tx: &'a Pipe,
+    idle: Option<&'a dyn Fn()>,
+}
+
+impl<'a> LoopbackConnection<'a> {
+    pub fn new(rx: &'a Pipe, tx: &'a Pipe) -> Self {
+        LoopbackConnection { rx, tx, idle: None }
+    }
+
+    pub fn with_idle(rx: &'a Pipe, tx: &'a Pipe, idle: &'a dyn Fn()) -> Self {
+        LoopbackConnection {
+            rx,
+            tx,
+            idle: Some(idle),
+        }
+    }
+}
+
+impl<'a> Connection for LoopbackConnection<'a> {
+    type Error = Infallible;
+
+    fn read_raw(&self, buffer: &mut [u8]) -> nb::Result<usize, Infallible> {
+        if self.rx.bytes.borrow().is_empty() {
+            if let Some(idle) = self.idle {
+                idle();
+            }
+        }
+        let mut bytes = self.rx.bytes.borrow_mut();
+        let mut len = 0;
+        while let (Some(slot), Some(byte)) = (buffer.get_mut(len), bytes.front()) {
+            *slot = *byte;
+            bytes.pop_front();
+            len += 1;
+        }
+        if len == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(len)
+    }
+
+    fn write(&self, data: &[u8]) {
+        let mut bytes = self.tx.bytes.borrow_mut();
+        for byte in data {
+            // あふれた分は線が切れたのと同じく届かない
+            bytes.push_back(*byte).ok();
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use heapless::Vec;
+
+use crate::keyboard::{KeySwitchIdentifier, KeySwitches};
+
+/// 押したスイッチを押した順に返す`KeySwitches`。`N`個を超えて押した分は読めなかったことにする
+#[derive(Debug, Clone)]
+pub struct MockSwitches<SI, const N: usize> {
+    pressed: Vec<SI, N>,
+}
+
+impl<SI: PartialEq, const N: usize> MockSwitches<SI, N> {
+    pub const fn new() -> Self {
+        MockSwitches {
+            pressed: Vec::new(),
+        }
+    }
+
+    pub fn press(&mut self, switch: SI) {
+        if !self.pressed.contains(&switch) {
+            self.pressed.push(switch).ok();
+        }
+    }
+
+    pub fn release(&mut self, switch: SI) {
+        self.pressed.retain(|s| *s != switch);
+    }
+
+    pub fn pressed(&self) -> &[SI] {
+        &self.pressed
+    }
+}
+
+impl<SI: PartialEq, const N: usize> Default for MockSwitches<SI, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SZ: usize, const RO: usize, SI: KeySwitchIdentifier<SZ>, const N: usize>
+    KeySwitches<SZ, RO> for MockSwitches<SI, N>
+{
+    type Identifier = SI;
+
+    /// ロールオーバーを超えた分は、実機と同じく読めなかったことにする
+    fn scan(&mut self) -> Vec<SI, RO> {
+        self.pressed.iter().take(RO).copied().collect()
+    }
+}
@@ -0,0 +1,30 @@
+use embedded_hal_0_2::timer::CountDown;
+use void::Void;
+
+/// `wait`を`count`回呼ぶと時間切れになるタイマ
+#[derive(Debug, Default)]
+pub struct MockTimer {
+    remaining: u32,
+}
+
+impl MockTimer {
+    pub fn new() -> Self {
+        MockTimer::default()
+    }
+}
+
+impl CountDown for MockTimer {
+    type Time = u32;
+
+    fn start<T: Into<u32>>(&mut self, count: T) {
+        self.remaining = count.into();
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        if self.remaining == 0 {
+            return Ok(());
+        }
+        self.remaining -= 1;
+        Err(nb::Error::WouldBlock)
+    }
+}
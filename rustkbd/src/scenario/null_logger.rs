@@ -0,0 +1,18 @@
+//! ホストで動かすときの、何も書き出さない`defmt`のロガー
+//!
+//! `DEFMT_LOG`でログを残したままホスト向けにビルドしても、リンクできるようにする。
+
+#[defmt::global_logger]
+struct NullLogger;
+
+unsafe impl defmt::Logger for NullLogger {
+    fn acquire() {}
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {}
+
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+defmt::timestamp!("{=u32}", 0);
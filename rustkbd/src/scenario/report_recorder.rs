@@ -0,0 +1,167 @@
+use core::{cell::Cell, cell::RefCell, fmt};
+
+use defmt::Format;
+use heapless::Vec;
+
+use crate::{
+    keyboard::{ExternalCommunicator, HostLayout, HostLeds, Key, QueueFull},
+    usb::{keyboard_report, media_report},
+};
+
+/// `UsbCommunicator`と同じく6キーロールオーバーにする
+const ROLLOVER: usize = 6;
+
+/// ある時刻から送られ始めたキーボードとメディアキーのレポート
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Report {
+    pub time: u32,
+    pub modifier: u8,
+    pub key_codes: [u8; 6],
+    pub media: u16,
+}
+
+impl Report {
+    /// `keys`が押されているときに`UsbCommunicator`が送るレポート
+    pub fn new(time: u32, keys: &[Key]) -> Report {
+        Report::with_layout(time, keys, HostLayout::Us)
+    }
+
+    /// `layout`のホストに送るレポート
+    pub fn with_layout(time: u32, keys: &[Key], layout: HostLayout) -> Report {
+        let keyboard = keyboard_report(keys, ROLLOVER, layout);
+        let media = media_report(keys.iter().find(|key| key.is_media_key()));
+        Report {
+            time,
+            modifier: keyboard.modifier,
+            key_codes: keyboard.key_codes,
+            media: media.usage_id,
+        }
+    }
+
+    /// レポートに含まれるキー。修飾キー、キー、メディアキーの順
+    pub fn keys(&self) -> Vec<Key, 15> {
+        let modifiers = (0..8)
+            .filter(|bit| self.modifier & (1 << bit) != 0)
+            .filter_map(|bit| Key::try_from(0xe0 + bit as u16).ok());
+        let codes = self
+            .key_codes
+            .iter()
+            .filter(|code| **code != 0)
+            .filter_map(|code| Key::try_from(*code as u16).ok());
+        let media = (self.media != 0)
+            .then(|| Key::try_from(0x1000 | self.media).ok())
+            .flatten();
+        modifiers.chain(codes).chain(media).collect()
+    }
+
+    fn same_keys(&self, other: &Report) -> bool {
+        (self.modifier, self.key_codes, self.media)
+            == (other.modifier, other.key_codes, other.media)
+    }
+}
+
+/// キーを`LeftShift + A`のように書く。何も押されていなければ`(none)`
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys = self.keys();
+        if keys.is_empty() {
+            return write!(f, "(none)");
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if i > 0 {
+                write!(f, " + ")?;
+            }
+            write!(f, "{key:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 送られたレポートのうち、前と変わったものだけを時刻と一緒に記録する`ExternalCommunicator`
+pub struct ReportRecorder<'a, const N: usize> {
+    clock: &'a Cell<u32>,
+    reports: RefCell<Vec<Report, N>>,
+    /// 最後に送られたレポート。始めは何も押されていないレポートが送られていたことにする
+    last: Cell<Report>,
+    host_leds: Cell<HostLeds>,
+}
+
+impl<'a, const N: usize> ReportRecorder<'a, N> {
+    pub fn new(clock: &'a Cell<u32>) -> Self {
+        ReportRecorder {
+            clock,
+            reports: RefCell::new(Vec::new()),
+            last: Cell::new(Report::new(0, &[])),
+            host_leds: Cell::new(HostLeds::empty()),
+        }
+    }
+
+    /// ホストからLEDの状態が届いたことにする
+    pub fn set_host_leds(&self, leds: HostLeds) {
+        self.host_leds.set(leds);
+    }
+
+    pub fn reports(&self) -> Vec<Report, N> {
+        self.reports.borrow().clone()
+    }
+
+    /// 記録したレポートを取り出す。取り出した後も、変わったかどうかは最後のレポートと比べる
+    pub fn take(&self) -> Vec<Report, N> {
+        self.reports.take()
+    }
+}
+
+impl<'a, const N: usize> ExternalCommunicator for ReportRecorder<'a, N> {
+    type Error = QueueFull;
+
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    fn send_keys(&self, keys: &[Key], layout: HostLayout) -> Result<(), QueueFull> {
+        let report = Report::with_layout(self.clock.get(), keys, layout);
+        if report.same_keys(&self.last.get()) {
+            return Ok(());
+        }
+        self.last.set(report);
+        self.reports
+            .borrow_mut()
+            .push(report)
+            .map_err(|_| QueueFull)
+    }
+
+    fn host_leds(&self) -> HostLeds {
+        self.host_leds.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 修飾キーとメディアキーもキーに戻る
+    fn test_report_keys() {
+        let report = Report::new(0, &[Key::LeftShift, Key::A, Key::MediaMute]);
+        assert_eq!(0x02, report.modifier);
+        assert_eq!(
+            &[Key::LeftShift, Key::A, Key::MediaMute],
+            &report.keys()[..]
+        );
+    }
+
+    #[test]
+    // 取り出した後も、変わったレポートだけを記録する
+    fn test_take() {
+        let clock = Cell::new(0);
+        let recorder = ReportRecorder::<4>::new(&clock);
+        recorder.send_keys(&[], HostLayout::Us).unwrap();
+        recorder.send_keys(&[Key::A], HostLayout::Us).unwrap();
+        assert_eq!(1, recorder.take().len());
+        clock.set(10);
+        recorder.send_keys(&[Key::A], HostLayout::Us).unwrap();
+        recorder.send_keys(&[], HostLayout::Us).unwrap();
+        assert_eq!(&[Report::new(10, &[])], &recorder.take()[..]);
+        assert!(recorder.reports().is_empty());
+    }
+}
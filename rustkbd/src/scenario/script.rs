@@ -0,0 +1,200 @@
+use core::cell::Cell;
+
+use defmt::Format;
+use heapless::Vec;
+
+use crate::{
+    keyboard::{Controller, ExternalCommunicator, KeySwitches, Layout},
+    split::Handedness,
+};
+
+use super::{ReportRecorder, ScriptedSwitches};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum Action {
+    Press,
+    Release,
+}
+
+/// `press(3,7) @0ms`のような1つの操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Step {
+    /// 始めてからのミリ秒
+    pub time: u32,
+    pub action: Action,
+    /// 分割キーボードのどちら側か。書かなければ左手側
+    pub side: Option<Handedness>,
+    pub row: u8,
+    pub col: u8,
+}
+
+/// `step`番目（0始まり）の操作が読めないか、時刻が前の操作より前になっている
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct ParseError {
+    pub step: usize,
+}
+
+/// 操作の列と、それを進める時計
+///
+/// 時計は1ミリ秒ずつ進み、`ScriptedSwitches`と`ReportRecorder`はこの時計を読む。
+pub struct Scenario<const N: usize> {
+    steps: Vec<Step, N>,
+    clock: Cell<u32>,
+}
+
+impl<const N: usize> Scenario<N> {
+    /// `;`か改行で区切った操作を読む。操作は時刻の順に並んでいなければならない
+    pub fn parse(script: &str) -> Result<Self, ParseError> {
+        let mut steps = Vec::<Step, N>::new();
+        let texts = script
+            .split([';', '\n'])
+            .map(str::trim)
+            .filter(|text| !text.is_empty());
+        for (i, text) in texts.enumerate() {
+            let error = ParseError { step: i };
+            let step = parse_step(text).ok_or(error)?;
+            if steps.last().is_some_and(|last| last.time > step.time) {
+                return Err(error);
+            }
+            steps.push(step).map_err(|_| error)?;
+        }
+        Ok(Scenario {
+            steps,
+            clock: Cell::new(0),
+        })
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn now(&self) -> u32 {
+        self.clock.get()
+    }
+
+    /// `side`側のスイッチ。`None`ならどちら側の操作も受け取る
+    pub fn switches<SI>(
+        &self,
+        side: Option<Handedness>,
+        switch: fn(u8, u8) -> SI,
+    ) -> ScriptedSwitches<'_, SI> {
+        ScriptedSwitches::new(&self.steps, &self.clock, side, switch)
+    }
+
+    pub fn recorder<const M: usize>(&self) -> ReportRecorder<'_, M> {
+        ReportRecorder::new(&self.clock)
+    }
+
+    /// 最後の操作の`after`ミリ秒後まで、1ミリ秒ごとに`tick`を呼ぶ
+    pub fn run(&self, after: u32, mut tick: impl FnMut()) {
+        let end = self.steps.last().map_or(0, |step| step.time) + after;
+        while self.clock.get() <= end {
+            tick();
+            self.clock.set(self.clock.get() + 1);
+        }
+    }
+
+    /// 実機と同じく`main_loop`と`send_keys`を1ミリ秒ごとに呼ぶ
+    pub fn run_controller<
+        const SZ: usize,
+        const RO: usize,
+        C: ExternalCommunicator,
+        K: KeySwitches<SZ, RO>,
+        L: Layout<SZ, Identifier = K::Identifier>,
+    >(
+        &self,
+        controller: &mut Controller<SZ, RO, C, K, L>,
+        after: u32,
+    ) {
+        self.run(after, || {
+            controller.main_loop();
+            controller.send_keys().ok();
+        });
+    }
+}
+
+/// `press(left 3,7) @10ms`
+fn parse_step(text: &str) -> Option<Step> {
+    let (call, time) = text.split_once('@')?;
+    let time = time.trim().strip_suffix("ms")?.trim().parse().ok()?;
+    let (action, args) = call.trim().strip_suffix(')')?.split_once('(')?;
+    let action = match action.trim() {
+        "press" => Action::Press,
+        "release" => Action::Release,
+        _ => return None,
+    };
+    let args = args.trim();
+    let (side, position) = if let Some(position) = args.strip_prefix("left ") {
+        (Some(Handedness::Left), position)
+    } else if let Some(position) = args.strip_prefix("right ") {
+        (Some(Handedness::Right), position)
+    } else {
+        (None, args)
+    };
+    let (row, col) = position.split_once(',')?;
+    Some(Step {
+        time,
+        action,
+        side,
+        row: row.trim().parse().ok()?,
+        col: col.trim().parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 区切りは`;`でも改行でもよく、どちら側かを書ける
+    fn test_parse() {
+        let scenario = Scenario::<4>::parse(
+            "press(3,7) @0ms; press(right 0, 1) @20ms
+             release(3,7) @50ms;",
+        )
+        .unwrap();
+        assert_eq!(
+            &[
+                Step {
+                    time: 0,
+                    action: Action::Press,
+                    side: None,
+                    row: 3,
+                    col: 7
+                },
+                Step {
+                    time: 20,
+                    action: Action::Press,
+                    side: Some(Handedness::Right),
+                    row: 0,
+                    col: 1
+                },
+                Step {
+                    time: 50,
+                    action: Action::Release,
+                    side: None,
+                    row: 3,
+                    col: 7
+                },
+            ],
+            scenario.steps()
+        );
+    }
+
+    #[test]
+    // 読めない操作と時刻が戻る操作はエラーになる
+    fn test_parse_errors() {
+        assert_eq!(
+            Err(ParseError { step: 1 }),
+            Scenario::<4>::parse("press(0,0) @0ms; tap(0,1) @10ms").map(|_| ())
+        );
+        assert_eq!(
+            Err(ParseError { step: 1 }),
+            Scenario::<4>::parse("press(0,0) @10ms; release(0,0) @5ms").map(|_| ())
+        );
+        assert_eq!(
+            Err(ParseError { step: 0 }),
+            Scenario::<4>::parse("press(0,0)").map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use core::cell::Cell;
+
+use heapless::Vec;
+
+use crate::{
+    keyboard::{KeySwitchIdentifier, KeySwitches},
+    split::Handedness,
+};
+
+use super::{Action, Step};
+
+/// `Scenario`の操作を時計の時刻まで再生した`KeySwitches`
+pub struct ScriptedSwitches<'a, SI> {
+    steps: &'a [Step],
+    clock: &'a Cell<u32>,
+    side: Option<Handedness>,
+    switch: fn(u8, u8) -> SI,
+}
+
+impl<'a, SI> ScriptedSwitches<'a, SI> {
+    pub fn new(
+        steps: &'a [Step],
+        clock: &'a Cell<u32>,
+        side: Option<Handedness>,
+        switch: fn(u8, u8) -> SI,
+    ) -> Self {
+        ScriptedSwitches {
+            steps,
+            clock,
+            side,
+            switch,
+        }
+    }
+}
+
+impl<'a, const SZ: usize, const RO: usize, SI: KeySwitchIdentifier<SZ>> KeySwitches<SZ, RO>
+    for ScriptedSwitches<'a, SI>
+{
+    type Identifier = SI;
+
+    fn scan(&mut self) -> Vec<SI, RO> {
+        let now = self.clock.get();
+        let mut pressed = Vec::<SI, RO>::new();
+        let steps = self.steps.iter().take_while(|step| step.time <= now);
+        for step in steps.filter(|step| match self.side {
+            None => true,
+            Some(side) => step.side.unwrap_or(Handedness::Left) == side,
+        }) {
+            let switch = (self.switch)(step.row, step.col);
+            match step.action {
+                Action::Press if !pressed.contains(&switch) => {
+                    // ロールオーバーを超えた分は読めなかったことにする
+                    pressed.push(switch).ok();
+                }
+                Action::Press => {}
+                Action::Release => pressed.retain(|s| *s != switch),
+            }
+        }
+        pressed
+    }
+}
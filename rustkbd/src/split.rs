@@ -1,13 +1,21 @@
 mod connection;
 mod error;
+mod handedness;
+mod handshake;
+mod link_stats;
 mod message;
+mod split_array_layout;
 mod split_communicator;
 mod split_key_switches;
 mod split_state;
 pub use connection::Connection;
 pub(crate) use connection::ConnectionExt;
 pub use error::Error;
-pub(crate) use message::Message;
+pub use handedness::Handedness;
+pub use handshake::{Features, Hello, Incompatibility};
+pub use link_stats::LinkStats;
+pub(crate) use message::{Message, SharedState, SwitchEvent};
+pub use split_array_layout::SplitArrayLayout;
 pub(crate) use split_communicator::SplitCommunicator;
 pub use split_key_switches::{SplitKeySwitchIdentifier, SplitKeySwitches};
-pub use split_state::SplitState;
+pub use split_state::{Link, SplitState};
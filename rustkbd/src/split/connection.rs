@@ -4,8 +4,9 @@ use embedded_hal_0_2::timer::CountDown;
 use nb;
 
 use crate::{
-    keyboard::KeySwitchIdentifier,
-    split::{Error, Message},
+    crc::Crc16,
+    keyboard::{HostLayout, HostLeds, Key, KeySwitchIdentifier},
+    split::{Error, Features, Handedness, Hello, LinkStats, Message, SharedState, SwitchEvent},
     Vec,
 };
 
@@ -30,64 +31,131 @@ pub trait Connection {
     }
 }
 
-/// 一度に書き込む最大バイト数
-const MAX_BUF_LEN: usize = 40;
+/// フレームの開始バイト
+const START: u8 = 0xa5;
+/// 開始バイト、長さ、連番
+const HEADER_LEN: usize = 3;
+/// CRC-16
+const CRC_LEN: usize = 2;
+/// 長さは1バイトなので、メッセージはこれより長くできない
+const MAX_PAYLOAD_LEN: usize = u8::MAX as usize;
+/// 読み込み用のバッファの大きさ
+const MAX_FRAME_LEN: usize = HEADER_LEN + MAX_PAYLOAD_LEN + CRC_LEN;
+/// 接続時のメッセージの長さ
+const HELLO_LEN: usize = 6;
+/// 状態のメッセージの、キーより前のバイト数
+const STATE_HEADER_LEN: usize = 6;
+/// キー1つのバイト数
+const KEY_LEN: usize = 4;
+/// イベントのメッセージの、イベントより前のバイト数
+const EVENTS_HEADER_LEN: usize = 4;
 
+/// 1つのフレームに入るイベントの数
+pub(crate) const fn max_events(sz: usize) -> usize {
+    (MAX_PAYLOAD_LEN - EVENTS_HEADER_LEN) / (sz + 3)
+}
+
+/// `SZ`バイトの識別子を`RO`個まで送るときの、最も長いメッセージのバイト数
+const fn max_payload_len(sz: usize, ro: usize) -> usize {
+    let switches = 2 + sz * ro;
+    let state = STATE_HEADER_LEN + KEY_LEN * ro;
+    let events = EVENTS_HEADER_LEN
+        + (sz + 3)
+            * if ro < max_events(sz) {
+                ro
+            } else {
+                max_events(sz)
+            };
+    let mut len = HELLO_LEN;
+    if len < switches {
+        len = switches;
+    }
+    if len < state {
+        len = state;
+    }
+    if len < events {
+        len = events;
+    }
+    len
+}
+
+/// `| 0xa5 | 長さ | 連番 | メッセージ | CRC-16 |`のフレームで送受信する
+///
+/// 長さはメッセージのバイト数で、CRCは長さからメッセージまでをリトルエンディアンで書く。
+/// 壊れたフレームは捨て、次の開始バイトから読み直す。
 pub trait ConnectionExt: Connection {
+    /// フレームを1つ読み、連番とメッセージを返す
     fn read_message<C: CountDown, const SZ: usize, const RO: usize, SI: KeySwitchIdentifier<SZ>>(
         &self,
         timer: &mut C,
         timeout: C::Time,
-    ) -> Result<Message<SZ, RO, SI>, Error<Self::Error>> {
-        assert!(
-            MAX_BUF_LEN > SZ * RO,
-            "MAX_BUF_LEN must be large enough to read SI bytes x RO keys"
-        );
-        let mut buf = [0u8; MAX_BUF_LEN];
-        self.read_with_timeout(&mut buf[..1], timer, timeout)?;
-        let head = buf[0];
-        match head {
-            0x00 | 0x01 => {
-                let ctor = if head == 0x00 {
-                    Message::Switches
-                } else {
-                    Message::SwitchesReply
-                };
-                self.read(&mut buf[..1])
-                    .map_err(|source| Error::ReadError { source })?;
-                let len = buf[0] as usize;
-                if len == 0 {
-                    Ok(ctor(Vec::new()))
-                } else if len > RO {
-                    Err(Error::ReadBufferOverflow)
-                } else {
-                    self.read(&mut buf[..(len * SZ)])
-                        .map_err(|source| Error::ReadError { source })?;
-                    let keys = (0..len)
-                        .map(|x| x * SZ)
-                        .map(|x| {
-                            let mut b: [u8; SZ] = [0; SZ];
-                            b.copy_from_slice(&buf[x..(x + SZ)]);
-                            b.into()
-                        })
-                        .collect();
-                    Ok(ctor(keys))
+        stats: &mut LinkStats,
+    ) -> Result<(u8, Message<SZ, RO, SI>), Error<Self::Error>> {
+        const {
+            assert!(
+                max_payload_len(SZ, RO) <= MAX_PAYLOAD_LEN,
+                "a frame must be large enough to read SI bytes x RO keys"
+            )
+        };
+        let max_len = max_payload_len(SZ, RO);
+        let mut buf = Vec::<u8, MAX_FRAME_LEN>::new();
+        timer.start(timeout);
+        loop {
+            // 開始バイトより前は読み捨てる
+            let skip = buf.iter().position(|b| *b == START).unwrap_or(buf.len());
+            discard(&mut buf, skip);
+            stats.skipped_bytes += skip as u32;
+
+            let needed = match buf.get(1) {
+                Some(len) => HEADER_LEN + *len as usize + CRC_LEN,
+                None => 2,
+            };
+            if needed > HEADER_LEN + max_len + CRC_LEN {
+                // 長さが壊れている
+                discard(&mut buf, 1);
+                stats.skipped_bytes += 1;
+                continue;
+            }
+            if buf.len() < needed {
+                if let Err(e) = self.fill(&mut buf, needed, timer) {
+                    if let Error::ReadTimedOut = e {
+                        stats.timeouts += 1;
+                    }
+                    return Err(e);
                 }
+                continue;
+            }
+
+            let (body, crc) = buf[1..needed].split_at(needed - 1 - CRC_LEN);
+            if Crc16::checksum(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+                discard(&mut buf, 1);
+                stats.crc_errors += 1;
+                continue;
             }
-            0xff => Ok(Message::FindReceiver),
-            0xfe => Ok(Message::Acknowledge),
-            _ => Err(Error::UnknownMessage { head }),
+            stats.frames += 1;
+            return Ok((buf[2], decode(&buf[HEADER_LEN..needed - CRC_LEN])?));
         }
     }
 
     fn send_message<const SZ: usize, const RO: usize, SI: KeySwitchIdentifier<SZ>>(
         &self,
+        sequence: u8,
         message: Message<SZ, RO, SI>,
     ) {
-        assert!(
-            MAX_BUF_LEN > SZ * RO,
-            "MAX_BUF_LEN must be large enough to write SI bytes x RO keys"
-        );
+        const {
+            assert!(
+                max_payload_len(SZ, RO) <= MAX_PAYLOAD_LEN,
+                "a frame must be large enough to write SI bytes x RO keys"
+            )
+        };
+        // バッファに組み立てず、CRCを計算しながら少しずつ書く
+        let mut crc = Crc16::new();
+        let mut put = |bytes: &[u8]| {
+            crc.update(bytes);
+            self.write(bytes);
+        };
+        self.write(&[START]);
+        put(&[payload_len(&message) as u8, sequence]);
         match message {
             Message::Switches(ref keys) | Message::SwitchesReply(ref keys) => {
                 let head = if let Message::Switches(_) = message {
@@ -95,40 +163,81 @@ pub trait ConnectionExt: Connection {
                 } else {
                     0x01
                 };
-                let len = keys.len() as u8;
-                let data = core::iter::once(head)
-                    .chain(core::iter::once(len))
-                    .chain(
-                        keys.into_iter()
-                            .flat_map::<[u8; SZ], _>(|key| (*key).into()),
-                    )
-                    .collect::<Vec<u8, MAX_BUF_LEN>>();
-                self.write(&data);
-            }
-            Message::Acknowledge => {
-                self.write(&[0xfe]);
-            }
-            Message::FindReceiver => {
-                self.write(&[0xff]);
+                put(&[head, keys.len() as u8]);
+                for key in keys.iter() {
+                    let bytes: [u8; SZ] = (*key).into();
+                    put(&bytes);
+                }
+            }
+            Message::Events(now, ref events) | Message::EventsReply(now, ref events) => {
+                let head = if let Message::Events(..) = message {
+                    0x03
+                } else {
+                    0x04
+                };
+                put(&[head, events.len() as u8]);
+                put(&now.to_le_bytes());
+                for event in events.iter() {
+                    let bytes: [u8; SZ] = event.switch.into();
+                    put(&bytes);
+                    put(&[event.pressed as u8]);
+                    put(&event.time.to_le_bytes());
+                }
+            }
+            Message::State(ref state) => {
+                put(&[
+                    0x02,
+                    state.layer,
+                    state.host_layout as u8,
+                    state.host_leds.bits(),
+                    state.wpm,
+                    state.keys.len() as u8,
+                ]);
+                for key in state.keys.iter() {
+                    let bytes: [u8; KEY_LEN] = (*key).into();
+                    put(&bytes);
+                }
+            }
+            Message::Acknowledge(hello) | Message::FindReceiver(hello) => {
+                let head = if let Message::Acknowledge(_) = message {
+                    0xfe
+                } else {
+                    0xff
+                };
+                put(&[
+                    head,
+                    hello.version,
+                    hello.switch_size,
+                    hello.rollover,
+                    hello.features.bits(),
+                    hello.handedness as u8,
+                ]);
             }
         }
+        self.write(&crc.finish().to_le_bytes());
     }
-    fn read_with_timeout<C: CountDown>(
+
+    /// `buf`が`len`バイトになるまで読む
+    fn fill<C: CountDown, const N: usize>(
         &self,
-        buffer: &mut [u8],
+        buf: &mut Vec<u8, N>,
+        len: usize,
         timer: &mut C,
-        timeout: C::Time,
     ) -> Result<(), Error<Self::Error>> {
-        timer.start(timeout);
-        let mut offset = 0;
-        while offset != buffer.len() {
+        let mut offset = buf.len();
+        buf.resize(len, 0).ok();
+        while offset != len {
             if timer.wait().is_ok() {
+                buf.truncate(offset);
                 return Err(Error::ReadTimedOut);
             }
-            offset += match self.read_raw(&mut buffer[offset..]) {
+            offset += match self.read_raw(&mut buf[offset..]) {
                 Ok(bytes_read) => bytes_read,
                 Err(e) => match e {
-                    nb::Error::Other(source) => return Err(Error::ReadError { source }),
+                    nb::Error::Other(source) => {
+                        buf.truncate(offset);
+                        return Err(Error::ReadError { source });
+                    }
                     nb::Error::WouldBlock => continue,
                 },
             }
@@ -138,3 +247,285 @@ pub trait ConnectionExt: Connection {
 }
 
 impl<T: Connection> ConnectionExt for T {}
+
+/// 先頭の`n`バイトを捨てる
+fn discard<const N: usize>(buf: &mut Vec<u8, N>, n: usize) {
+    buf.rotate_left(n);
+    buf.truncate(buf.len() - n);
+}
+
+/// メッセージを書いたときのバイト数
+fn payload_len<const SZ: usize, const RO: usize, SI: KeySwitchIdentifier<SZ>>(
+    message: &Message<SZ, RO, SI>,
+) -> usize {
+    match message {
+        Message::Switches(keys) | Message::SwitchesReply(keys) => 2 + SZ * keys.len(),
+        Message::Events(_, events) | Message::EventsReply(_, events) => {
+            EVENTS_HEADER_LEN + (SZ + 3) * events.len()
+        }
+        Message::State(state) => STATE_HEADER_LEN + KEY_LEN * state.keys.len(),
+        Message::Acknowledge(_) | Message::FindReceiver(_) => HELLO_LEN,
+    }
+}
+
+/// フレームの中身をメッセージにする
+fn decode<E: Debug, const SZ: usize, const RO: usize, SI: KeySwitchIdentifier<SZ>>(
+    payload: &[u8],
+) -> Result<Message<SZ, RO, SI>, Error<E>> {
+    match payload {
+        [head @ (0x00 | 0x01), len, keys @ ..] => {
+            let len = *len as usize;
+            if len > RO {
+                return Err(Error::ReadBufferOverflow);
+            }
+            if keys.len() != len * SZ {
+                return Err(Error::MalformedMessage);
+            }
+            let keys = keys
+                .as_chunks::<SZ>()
+                .0
+                .iter()
+                .map(|b| SI::try_from(*b).map_err(|_| Error::InvalidSwitch))
+                .collect::<Result<_, _>>()?;
+            if *head == 0x00 {
+                Ok(Message::Switches(keys))
+            } else {
+                Ok(Message::SwitchesReply(keys))
+            }
+        }
+        [head @ (0x03 | 0x04), len, now_low, now_high, events @ ..] => {
+            let len = *len as usize;
+            if len > RO {
+                return Err(Error::ReadBufferOverflow);
+            }
+            if events.len() != len * (SZ + 3) {
+                return Err(Error::MalformedMessage);
+            }
+            let events = events
+                .chunks(SZ + 3)
+                .map(|chunk| {
+                    let (switch, rest) = chunk.split_at(SZ);
+                    let mut b: [u8; SZ] = [0; SZ];
+                    b.copy_from_slice(switch);
+                    Ok(SwitchEvent {
+                        switch: SI::try_from(b).map_err(|_| Error::InvalidSwitch)?,
+                        pressed: match rest[0] {
+                            0 => false,
+                            1 => true,
+                            _ => return Err(Error::MalformedMessage),
+                        },
+                        time: u16::from_le_bytes([rest[1], rest[2]]),
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            let now = u16::from_le_bytes([*now_low, *now_high]);
+            if *head == 0x03 {
+                Ok(Message::Events(now, events))
+            } else {
+                Ok(Message::EventsReply(now, events))
+            }
+        }
+        [0x02, layer, host_layout, host_leds, wpm, len, keys @ ..] => {
+            let len = *len as usize;
+            if len > RO {
+                return Err(Error::ReadBufferOverflow);
+            }
+            if keys.len() != len * KEY_LEN {
+                return Err(Error::MalformedMessage);
+            }
+            let keys = keys
+                .as_chunks::<KEY_LEN>()
+                .0
+                .iter()
+                .map(|b| Key::try_from(*b).map_err(|_| Error::MalformedMessage))
+                .collect::<Result<_, _>>()?;
+            Ok(Message::State(SharedState {
+                layer: *layer,
+                keys,
+                host_layout: match host_layout {
+                    0 => HostLayout::Us,
+                    1 => HostLayout::Jis,
+                    _ => return Err(Error::MalformedMessage),
+                },
+                host_leds: HostLeds::from_bits(*host_leds),
+                wpm: *wpm,
+            }))
+        }
+        [head @ (0xfe | 0xff), version, rest @ ..] => {
+            // 版が違えば続きの形も違うかもしれないので、版だけは必ず読めるようにする
+            let field = |i: usize| rest.get(i).copied().unwrap_or(0);
+            let hello = Hello {
+                version: *version,
+                switch_size: field(0),
+                rollover: field(1),
+                features: Features::from_bits(field(2)),
+                handedness: if field(3) == 1 {
+                    Handedness::Right
+                } else {
+                    Handedness::Left
+                },
+            };
+            if *head == 0xfe {
+                Ok(Message::Acknowledge(hello))
+            } else {
+                Ok(Message::FindReceiver(hello))
+            }
+        }
+        [head, ..] => Err(Error::UnknownMessage { head: *head }),
+        [] => Err(Error::MalformedMessage),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keyboard::array_layout_tests::Switch,
+        scenario::{LoopbackConnection, MockTimer, Pipe},
+        split::SplitKeySwitchIdentifier,
+    };
+
+    type TestMessage = Message<2, 6, Switch>;
+
+    fn read(
+        connection: &LoopbackConnection,
+        stats: &mut LinkStats,
+    ) -> Result<(u8, TestMessage), Error<core::convert::Infallible>> {
+        connection.read_message(&mut MockTimer::new(), 100, stats)
+    }
+
+    fn switches(message: TestMessage) -> Vec<Switch, 6> {
+        match message {
+            Message::Switches(switches) => switches,
+            _ => panic!("unexpected message"),
+        }
+    }
+
+    #[test]
+    // 書いたメッセージが連番とともにそのまま読める。状態のキーはデータを持つキーも戻る
+    fn test_round_trip() {
+        let pipe = Pipe::new();
+        let connection = LoopbackConnection::new(&pipe, &pipe);
+        let mut stats = LinkStats::default();
+        connection.send_message::<2, 6, Switch>(
+            7,
+            Message::Switches(Vec::from_slice(&[Switch(1, 2), Switch(3, 4)]).unwrap()),
+        );
+        connection.send_message::<2, 6, Switch>(
+            8,
+            Message::Acknowledge(Hello::new::<2, 6>(Handedness::Right)),
+        );
+        let state = SharedState {
+            layer: 1,
+            keys: Vec::from_slice(&[
+                Key::A,
+                Key::MediaMute,
+                Key::Unicode('あ'),
+                Key::WithModifiers(0x02, 0x04),
+                Key::LayerTap(2, 0x2c),
+            ])
+            .unwrap(),
+            host_layout: HostLayout::Jis,
+            host_leds: HostLeds::CAPS_LOCK,
+            wpm: 120,
+        };
+        connection.send_message::<2, 6, Switch>(9, Message::State(state.clone()));
+        let event = SwitchEvent {
+            switch: Switch(1, 2),
+            pressed: true,
+            time: 0x1234,
+        };
+        connection.send_message::<2, 6, Switch>(
+            10,
+            Message::EventsReply(0x1240, Vec::from_slice(&[event]).unwrap()),
+        );
+
+        let (sequence, message) = read(&connection, &mut stats).unwrap();
+        assert_eq!(7, sequence);
+        assert_eq!([Switch(1, 2), Switch(3, 4)], switches(message).as_slice());
+        assert!(matches!(
+            read(&connection, &mut stats),
+            Ok((8, Message::Acknowledge(hello))) if hello == Hello::new::<2, 6>(Handedness::Right)
+        ));
+        assert!(matches!(
+            read(&connection, &mut stats),
+            Ok((9, Message::State(s))) if s == state
+        ));
+        assert!(matches!(
+            read(&connection, &mut stats),
+            Ok((10, Message::EventsReply(0x1240, events))) if events == [event]
+        ));
+        assert!(matches!(
+            read(&connection, &mut stats),
+            Err(Error::ReadTimedOut)
+        ));
+        assert_eq!(
+            LinkStats {
+                frames: 4,
+                timeouts: 1,
+                ..Default::default()
+            },
+            stats
+        );
+    }
+
+    #[test]
+    // 壊れたフレームや前後のごみは捨て、次のフレームから読み直す
+    fn test_resync() {
+        let pipe = Pipe::new();
+        let connection = LoopbackConnection::new(&pipe, &pipe);
+        let mut stats = LinkStats::default();
+        // 開始バイトに見えるごみと、長さの壊れたフレーム
+        connection.write(&[0x12, START, 0xff, 0x34]);
+        // CRCの合わないフレーム
+        connection.send_message::<2, 6, Switch>(
+            0,
+            Message::Switches(Vec::from_slice(&[Switch(1, 2)]).unwrap()),
+        );
+        let mut bytes = [0u8; 9];
+        connection.read(&mut bytes).unwrap();
+        bytes[5] ^= 0x01;
+        connection.write(&[0x56]);
+        connection.write(&bytes);
+        connection.send_message::<2, 6, Switch>(
+            1,
+            Message::Switches(Vec::from_slice(&[Switch(5, 6)]).unwrap()),
+        );
+
+        let (sequence, message) = read(&connection, &mut stats).unwrap();
+        assert_eq!(1, sequence);
+        assert_eq!([Switch(5, 6)], switches(message).as_slice());
+        assert_eq!(1, stats.frames);
+        assert_eq!(1, stats.crc_errors);
+        assert_eq!(4 + 1 + 8, stats.skipped_bytes);
+    }
+
+    #[test]
+    // 片側6×7で20キーロールオーバーでも、1つのフレームで送受信できる
+    fn test_large_matrix() {
+        type Id = SplitKeySwitchIdentifier<2, Switch>;
+        let pipe = Pipe::new();
+        let connection = LoopbackConnection::new(&pipe, &pipe);
+        let mut stats = LinkStats::default();
+        let switches: Vec<Id, 20> = (0..20).map(|i| Id::Right(Switch(i / 7, i % 7))).collect();
+        let events: Vec<SwitchEvent<3, Id>, 20> = switches
+            .iter()
+            .map(|switch| SwitchEvent {
+                switch: *switch,
+                pressed: true,
+                time: 0,
+            })
+            .collect();
+        connection.send_message::<3, 20, Id>(0, Message::Switches(switches.clone()));
+        connection.send_message::<3, 20, Id>(1, Message::EventsReply(0, events.clone()));
+
+        assert!(matches!(
+            connection.read_message(&mut MockTimer::new(), 100, &mut stats),
+            Ok((0, Message::<3, 20, Id>::Switches(s))) if s == switches
+        ));
+        assert!(matches!(
+            connection.read_message(&mut MockTimer::new(), 100, &mut stats),
+            Ok((1, Message::<3, 20, Id>::EventsReply(0, e))) if e == events
+        ));
+    }
+}
@@ -13,4 +13,8 @@ pub enum Error<E: 'static + Debug> {
     UnknownMessage {
         head: u8,
     },
+    /// スイッチの識別子として読めないデータを受け取った
+    InvalidSwitch,
+    /// CRCは合っているが、長さがメッセージの中身と合わない
+    MalformedMessage,
 }
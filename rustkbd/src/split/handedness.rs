@@ -0,0 +1,6 @@
+/// 分割キーボードのどちら側か
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Handedness {
+    Left = 0,
+    Right = 1,
+}
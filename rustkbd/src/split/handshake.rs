@@ -0,0 +1,157 @@
+use crate::split::Handedness;
+
+/// 左右の通信の版。メッセージの形を変えたら上げる
+const PROTOCOL_VERSION: u8 = 3;
+
+/// 左右で使える機能
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub struct Features(u8);
+
+impl Features {
+    /// ControllerからReceiverにレイヤと押されているキーを送る
+    pub const STATE_SYNC: Features = Features(0b0000_0001);
+
+    /// スイッチの一覧の代わりに、押された・離されたイベントを送る
+    pub const EVENT_DELTA: Features = Features(0b0000_0010);
+
+    /// このファームウェアが使える機能
+    pub const SUPPORTED: Features = Features::STATE_SYNC.union(Features::EVENT_DELTA);
+
+    pub const fn empty() -> Self {
+        Features(0)
+    }
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Features(bits)
+    }
+
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(&self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+
+    /// 両方が使える機能
+    pub const fn intersection(&self, other: Features) -> Features {
+        Features(self.0 & other.0)
+    }
+}
+
+/// 接続するときに交換する、自分の側の通信の仕様
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Hello {
+    pub version: u8,
+    /// スイッチの識別子のバイト数
+    pub switch_size: u8,
+    /// 一度に送れるスイッチの数
+    pub rollover: u8,
+    pub features: Features,
+    pub handedness: Handedness,
+}
+
+/// 左右で話が通じない理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Incompatibility {
+    /// 通信の版が違う
+    Version { local: u8, remote: u8 },
+    /// スイッチの識別子の大きさが違う
+    SwitchSize { local: u8, remote: u8 },
+    /// 両方が同じ側として設定されている
+    SameHandedness(Handedness),
+}
+
+impl Hello {
+    pub(crate) fn new<const SZ: usize, const RO: usize>(handedness: Handedness) -> Self {
+        Hello {
+            version: PROTOCOL_VERSION,
+            switch_size: SZ as u8,
+            rollover: RO.min(u8::MAX as usize) as u8,
+            features: Features::SUPPORTED,
+            handedness,
+        }
+    }
+
+    /// 相手と話せるなら、両方が使えるスイッチの数と機能を返す
+    pub(crate) fn negotiate(&self, remote: &Hello) -> Result<(usize, Features), Incompatibility> {
+        if self.version != remote.version {
+            return Err(Incompatibility::Version {
+                local: self.version,
+                remote: remote.version,
+            });
+        }
+        if self.switch_size != remote.switch_size {
+            return Err(Incompatibility::SwitchSize {
+                local: self.switch_size,
+                remote: remote.switch_size,
+            });
+        }
+        if self.handedness == remote.handedness {
+            return Err(Incompatibility::SameHandedness(self.handedness));
+        }
+        Ok((
+            self.rollover.min(remote.rollover) as usize,
+            self.features.intersection(remote.features),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 版と識別子の大きさは一致し、左右は違わなければならない。スイッチの数と機能は小さい方に合わせる
+    fn test_negotiate() {
+        let local = Hello {
+            version: 1,
+            switch_size: 2,
+            rollover: 6,
+            features: Features::from_bits(0b011),
+            handedness: Handedness::Left,
+        };
+        let remote = Hello {
+            rollover: 4,
+            features: Features::from_bits(0b110),
+            handedness: Handedness::Right,
+            ..local
+        };
+        assert_eq!(
+            Ok((4, Features::from_bits(0b010))),
+            local.negotiate(&remote)
+        );
+        assert_eq!(
+            Err(Incompatibility::Version {
+                local: 1,
+                remote: 2
+            }),
+            local.negotiate(&Hello {
+                version: 2,
+                switch_size: 3,
+                ..remote
+            })
+        );
+        assert_eq!(
+            Err(Incompatibility::SwitchSize {
+                local: 2,
+                remote: 3
+            }),
+            local.negotiate(&Hello {
+                switch_size: 3,
+                ..remote
+            })
+        );
+        assert_eq!(
+            Err(Incompatibility::SameHandedness(Handedness::Left)),
+            local.negotiate(&Hello {
+                handedness: Handedness::Left,
+                ..remote
+            })
+        );
+    }
+}
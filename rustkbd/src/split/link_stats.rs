@@ -0,0 +1,20 @@
+use defmt::Format;
+
+/// 左右の通信で起きたことの回数。診断用
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Format)]
+pub struct LinkStats {
+    /// 正しく受け取ったフレーム
+    pub frames: u32,
+    /// CRCが合わずに捨てたフレーム
+    pub crc_errors: u32,
+    /// フレームの区切りを探すために読み捨てたバイト
+    pub skipped_bytes: u32,
+    /// 連番が飛んでいて届かなかったとわかるフレーム
+    pub lost_frames: u32,
+    /// 連番が戻っていて、重複か順番の入れ替わりとして捨てたフレーム
+    pub stale_frames: u32,
+    /// 時間内にフレームを受け取れなかった回数
+    pub timeouts: u32,
+    /// 切れたとみなした回数
+    pub disconnections: u32,
+}
@@ -1,9 +1,38 @@
-use crate::{keyboard::KeySwitchIdentifier, Vec};
+use crate::{
+    keyboard::{HostLayout, HostLeds, Key, KeySwitchIdentifier},
+    split::Hello,
+    Vec,
+};
 
 #[derive(Debug, Clone)]
 pub enum Message<const SZ: usize, const RO: usize, SI: KeySwitchIdentifier<SZ>> {
     Switches(Vec<SI, RO>),      // 0x00
     SwitchesReply(Vec<SI, RO>), // 0x01
-    Acknowledge,                // 0xfe
-    FindReceiver,               // 0xff
+    /// Controllerのキーボードの状態
+    State(SharedState<RO>), // 0x02
+    /// 送った側のいまのスキャンの回数と、前に送ってから押された・離されたスイッチを起きた順に
+    Events(u16, Vec<SwitchEvent<SZ, SI>, RO>), // 0x03
+    EventsReply(u16, Vec<SwitchEvent<SZ, SI>, RO>), // 0x04
+    Acknowledge(Hello),         // 0xfe
+    FindReceiver(Hello),        // 0xff
+}
+
+/// 押された・離されたスイッチ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwitchEvent<const SZ: usize, SI: KeySwitchIdentifier<SZ>> {
+    pub switch: SI,
+    pub pressed: bool,
+    /// 押された・離されたときの、送った側のスキャンの回数
+    pub time: u16,
+}
+
+/// ControllerからReceiverに送るキーボードの状態
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedState<const RO: usize> {
+    /// レイヤの番号
+    pub layer: u8,
+    pub keys: Vec<Key, RO>,
+    pub host_layout: HostLayout,
+    pub host_leds: HostLeds,
+    pub wpm: u8,
 }
@@ -0,0 +1,160 @@
+use core::marker::PhantomData;
+
+use crate::keyboard::{
+    active_layer, DynamicKeymap, Key, KeySwitchIdentifier, Layer, Layout, MatrixPosition,
+};
+
+use super::SplitKeySwitchIdentifier;
+
+/// 左手側と右手側のキーマップで決まる`ArrayLayout`
+///
+/// `DynamicKeymap`としては、左手側の下に右手側を並べた`ROWS * 2`行の行列になる。
+/// `layer_switches`の位置も同じ行列で、右手側のスイッチは`ROWS`行だけ下になる。
+pub struct SplitArrayLayout<
+    const SZ: usize,
+    I: KeySwitchIdentifier<SZ>,
+    Y: 'static,
+    const LAYERS: usize,
+    const ROWS: usize,
+    const COLS: usize,
+> {
+    default_keymap: [[[[Key; COLS]; ROWS]; LAYERS]; 2],
+    keymap: [[[[Key; COLS]; ROWS]; LAYERS]; 2],
+    layer_switches: &'static [((usize, usize), Y)],
+    _identifier: PhantomData<I>,
+}
+
+impl<
+        const SZ: usize,
+        I: KeySwitchIdentifier<SZ>,
+        Y,
+        const LAYERS: usize,
+        const ROWS: usize,
+        const COLS: usize,
+    > SplitArrayLayout<SZ, I, Y, LAYERS, ROWS, COLS>
+{
+    pub const fn new(
+        left: [[[Key; COLS]; ROWS]; LAYERS],
+        right: [[[Key; COLS]; ROWS]; LAYERS],
+        layer_switches: &'static [((usize, usize), Y)],
+    ) -> Self {
+        SplitArrayLayout {
+            default_keymap: [left, right],
+            keymap: [left, right],
+            layer_switches,
+            _identifier: PhantomData,
+        }
+    }
+}
+
+impl<
+        const N: usize,
+        const SZ: usize,
+        I: KeySwitchIdentifier<SZ> + MatrixPosition,
+        Y: Layer,
+        const LAYERS: usize,
+        const ROWS: usize,
+        const COLS: usize,
+    > Layout<N> for SplitArrayLayout<SZ, I, Y, LAYERS, ROWS, COLS>
+where
+    SplitKeySwitchIdentifier<SZ, I>: KeySwitchIdentifier<N>,
+{
+    type Identifier = SplitKeySwitchIdentifier<SZ, I>;
+    type Layer = Y;
+
+    fn layer(&self, switches: &[Self::Identifier]) -> Y {
+        let positions = switches
+            .iter()
+            .map(|switch| switch.position(ROWS * 2, COLS));
+        active_layer(self.layer_switches, positions)
+    }
+
+    fn key(&self, layer: Y, switch: &Self::Identifier) -> Key {
+        const {
+            assert!(
+                Y::ALL.len() == LAYERS,
+                "the keymap needs one table per layer"
+            )
+        };
+        let (row, col) = switch.position(ROWS * 2, COLS);
+        self.get_key(layer.index(), row, col).unwrap_or(Key::None)
+    }
+}
+
+impl<
+        const SZ: usize,
+        I: KeySwitchIdentifier<SZ>,
+        Y,
+        const LAYERS: usize,
+        const ROWS: usize,
+        const COLS: usize,
+    > DynamicKeymap for SplitArrayLayout<SZ, I, Y, LAYERS, ROWS, COLS>
+{
+    fn layer_count(&self) -> usize {
+        LAYERS
+    }
+
+    fn rows(&self) -> usize {
+        ROWS * 2
+    }
+
+    fn cols(&self) -> usize {
+        COLS
+    }
+
+    fn get_key(&self, layer: usize, row: usize, col: usize) -> Option<Key> {
+        self.keymap
+            .get(row / ROWS)?
+            .get(layer)?
+            .get(row % ROWS)?
+            .get(col)
+            .copied()
+    }
+
+    fn set_key(&mut self, layer: usize, row: usize, col: usize, key: Key) -> bool {
+        if let Some(k) = self
+            .keymap
+            .get_mut(row / ROWS)
+            .and_then(|s| s.get_mut(layer))
+            .and_then(|l| l.get_mut(row % ROWS))
+            .and_then(|r| r.get_mut(col))
+        {
+            *k = key;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn reset(&mut self) {
+        self.keymap = self.default_keymap;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::array_layout_tests::{Switch, TestLayer};
+
+    static LAYER_SWITCHES: [((usize, usize), TestLayer); 1] = [((1, 0), TestLayer::Raise)];
+
+    #[test]
+    // 右手側は左手側の下の行になる
+    fn test_split_keys() {
+        let left = [[[Key::A]], [[Key::B]], [[Key::C]]];
+        let right = [[[Key::None]], [[Key::D]], [[Key::E]]];
+        let mut layout =
+            SplitArrayLayout::<2, Switch, _, 3, 1, 1>::new(left, right, &LAYER_SWITCHES);
+        let switches = [
+            SplitKeySwitchIdentifier::Left(Switch(0, 0)),
+            SplitKeySwitchIdentifier::Right(Switch(0, 0)),
+        ];
+        let layer = Layout::<3>::layer(&layout, &switches);
+        assert_eq!(TestLayer::Raise, layer);
+        assert_eq!(Key::C, layout.key(layer, &switches[0]));
+        assert_eq!(2, layout.rows());
+        assert!(layout.set_key(1, 1, 0, Key::F));
+        assert_eq!(Key::F, layout.key(TestLayer::Lower, &switches[1]));
+        assert_eq!(None, layout.get_key(0, 2, 0));
+    }
+}
@@ -3,7 +3,21 @@ use heapless::Vec;
 
 use crate::keyboard::KeySwitches;
 
-use super::{Connection, ConnectionExt, Error, Message, SplitState};
+use super::{
+    connection::max_events, Connection, ConnectionExt, Error, Features, Handedness, Hello,
+    Incompatibility, Link, LinkStats, Message, SharedState, SplitState, SwitchEvent,
+};
+
+/// Controllerで、続けてこの回数だけ応答がなければ切れたとみなす
+const LINK_LOSS_MISSES: u8 = 3;
+/// Receiverで、この回数スキャンする間に何も届かなければ切れたとみなす
+const LINK_LOSS_SCANS: u16 = 100;
+/// 相手が見つからないか話が通じなかったとき、次に探すまでのスキャンの回数
+pub(super) const RETRY_SCANS: u16 = 100;
+/// イベントを送るとき、取りこぼしから立ち直るためにスイッチの一覧を送り直す間隔（やりとりの回数）
+const SNAPSHOT_INTERVAL: u16 = 100;
+/// 期待した連番からこれ以上先の連番は、進んだのではなく戻ったものとみなす
+const STALE_SEQUENCE: u8 = 128;
 
 pub struct SplitCommunicator<
     const SZ: usize,
@@ -17,8 +31,42 @@ pub struct SplitCommunicator<
     connection: S,
     state: SplitState,
     timer: C,
-    buffer: Vec<K::Identifier, RO>,
+    /// 相手の押されているスイッチと、押されたときのこちらのスキャンの回数。押された順
+    buffer: Vec<(K::Identifier, u16), RO>,
+    /// 自分の押されているスイッチと、押されたときのスキャンの回数。押された順
+    local: Vec<(K::Identifier, u16), RO>,
+    /// 前に相手に知らせてから離された自分のスイッチと、離されたときのスキャンの回数
+    released: Vec<(K::Identifier, u16), RO>,
     timeout: C::Time,
+    /// 次に送るフレームの連番
+    sequence: u8,
+    /// 次に届くはずのフレームの連番
+    expected_sequence: Option<u8>,
+    stats: LinkStats,
+    /// 相手と決めた、一度に送るスイッチの数
+    rollover: usize,
+    /// 相手と決めた、使う機能
+    features: Features,
+    handedness: Handedness,
+    /// 自分の側がホストにつながっているか
+    host_connected: bool,
+    /// Controllerで続けて応答がなかった回数
+    misses: u8,
+    /// Receiverで最後に受け取ってからのスキャンの回数、`NotAvailable`や`Incompatible`で次に探すまでのスキャンの回数
+    scans: u16,
+    /// いまの時刻(µs)。往復にかかった時間を測るのに使う
+    clock: Option<fn() -> u32>,
+    link: Link,
+    /// Controllerでは最後に送った、Receiverでは最後に受け取ったキーボードの状態
+    shared: Option<SharedState<RO>>,
+    /// 相手に知らせた自分のスイッチ。イベントを送るときの差分の元
+    sent: Vec<K::Identifier, RO>,
+    /// 最後に送ったのがスイッチの一覧か
+    sent_snapshot: bool,
+    /// 次にスイッチの一覧を送るまでのやりとりの回数。0なら次は一覧を送る
+    snapshot_in: u16,
+    /// イベントにつける時刻。スキャンの回数
+    ticks: u16,
 }
 
 impl<const SZ: usize, const RO: usize, K: KeySwitches<SZ, RO>, S: Connection, C: CountDown>
@@ -26,55 +74,181 @@ impl<const SZ: usize, const RO: usize, K: KeySwitches<SZ, RO>, S: Connection, C:
 where
     C::Time: Copy,
 {
-    pub fn new(connection: S, timer: C, timeout: C::Time) -> SplitCommunicator<SZ, RO, K, S, C> {
+    pub fn new(
+        connection: S,
+        timer: C,
+        timeout: C::Time,
+        handedness: Handedness,
+    ) -> SplitCommunicator<SZ, RO, K, S, C> {
         SplitCommunicator {
             connection,
-            state: SplitState::Undetermined, // TODO: これだとNotAvailableになれない
+            state: SplitState::Undetermined,
             timer,
             buffer: Vec::new(),
+            local: Vec::new(),
+            released: Vec::new(),
             timeout,
+            sequence: 0,
+            expected_sequence: None,
+            stats: LinkStats::default(),
+            rollover: RO,
+            features: Features::empty(),
+            handedness,
+            host_connected: false,
+            misses: 0,
+            scans: 0,
+            clock: None,
+            link: Link::default(),
+            shared: None,
+            sent: Vec::new(),
+            sent_snapshot: false,
+            snapshot_in: 0,
+            ticks: 0,
         }
     }
 
+    /// 往復にかかった時間を測るための時計(µs)を設定する
+    pub fn set_clock(&mut self, clock: fn() -> u32) {
+        self.clock = Some(clock);
+    }
+
+    /// ホストにつながっている側から、相手をReceiverにしてControllerになる
+    ///
+    /// 相手からも`FindReceiver`が届いたら`find_receiver`に任せる。相手が見つからなければ`NotAvailable`になる。
     pub fn establish(&mut self) -> Result<(), Error<S::Error>> {
         self.state = SplitState::Undetermined;
-        self.connection
-            .send_message(Message::<SZ, RO, K::Identifier>::FindReceiver);
-        self.state = match self.read()? {
-            Message::Acknowledge => {
-                defmt::info!("Split connection established");
-                SplitState::Controller
+        self.send(Message::FindReceiver(self.hello()));
+        loop {
+            match self.read() {
+                Ok(Message::Acknowledge(remote)) => {
+                    self.state = match self.negotiate(&remote) {
+                        Ok(()) => {
+                            defmt::info!("Split connection established");
+                            self.connect();
+                            SplitState::Controller(self.link)
+                        }
+                        Err(reason) => {
+                            self.scans = RETRY_SCANS;
+                            SplitState::Incompatible(reason)
+                        }
+                    };
+                    return Ok(());
+                }
+                Ok(Message::FindReceiver(remote)) => {
+                    self.find_receiver(&remote);
+                    if self.state != SplitState::Undetermined {
+                        return Ok(());
+                    }
+                }
+                Ok(_) => {
+                    defmt::warn!("Unexpected response");
+                }
+                Err(Error::ReadTimedOut) => {
+                    self.state = SplitState::NotAvailable;
+                    self.scans = RETRY_SCANS;
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
             }
-            _ => {
-                defmt::warn!("Unexpected response");
-                SplitState::Undetermined
+        }
+    }
+
+    /// ホストにつながっているかを知らせる。つながっていない側はControllerをやめる
+    pub fn set_host_connected(&mut self, connected: bool) {
+        self.host_connected = connected;
+        if !connected && matches!(self.state, SplitState::Controller(_)) {
+            defmt::info!("Host disconnected");
+            self.state = SplitState::Undetermined;
+        }
+    }
+
+    /// Controllerになろうとしているか。`NotAvailable`と`Incompatible`のときはしばらく待ってから探し直す
+    ///
+    /// 相手を書き換えれば話が通じるようになるので、`Incompatible`のままにはしない。
+    pub fn wants_controller(&mut self) -> bool {
+        match self.state {
+            SplitState::Undetermined => self.host_connected,
+            SplitState::NotAvailable | SplitState::Incompatible(_) if self.host_connected => {
+                self.scans = self.scans.saturating_sub(1);
+                self.scans == 0
             }
-        };
-        Ok(())
+            _ => false,
+        }
     }
 
     pub fn state(&self) -> SplitState {
         self.state
     }
 
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    /// 相手と決めた、使う機能
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    /// いまのスキャンの回数
+    pub fn ticks(&self) -> u16 {
+        self.ticks
+    }
+
+    /// 自分の押されているスイッチと、押されたときのスキャンの回数。押された順
+    pub fn local(&self) -> &[(K::Identifier, u16)] {
+        &self.local
+    }
+
+    /// Controllerのキーボードの状態を、変わったときだけReceiverに送る
+    pub fn share_state(&mut self, state: SharedState<RO>) {
+        if !matches!(self.state, SplitState::Controller(_))
+            || !self.features.contains(Features::STATE_SYNC)
+            || self.shared.as_ref() == Some(&state)
+        {
+            return;
+        }
+        self.send(Message::State(state.clone()));
+        self.shared = Some(state);
+    }
+
+    /// Receiverで、Controllerから受け取ったキーボードの状態
+    pub fn shared_state(&self) -> Option<&SharedState<RO>> {
+        match self.state {
+            SplitState::Receiver(_) => self.shared.as_ref(),
+            _ => None,
+        }
+    }
+
     pub fn respond(&mut self, keys: &Vec<K::Identifier, RO>) {
         match self.read() {
             Ok(Message::Switches(switches)) => {
                 // Controllerからrequestが届いたとき：バッファに保存しつつkeysをReplyする
-                self.buffer = switches;
-                self.connection
-                    .send_message(Message::SwitchesReply(keys.clone()));
+                // Receiverでなければ応答しない。Controllerは切れたとみなして探し直す
+                if let SplitState::Receiver(_) = self.state {
+                    self.receive_snapshot(switches);
+                    self.scans = 0;
+                    let reply = self.outgoing(keys, true);
+                    self.send(reply);
+                }
+            }
+            Ok(Message::Events(now, events)) => {
+                if let SplitState::Receiver(_) = self.state {
+                    self.apply(now, &events);
+                    self.scans = 0;
+                    let reply = self.outgoing(keys, true);
+                    self.send(reply);
+                }
             }
             Ok(Message::SwitchesReply(switches)) => {
                 // 通常ここには来ないがタイミングの問題で来る場合があるので適切にハンドリングする
-                self.buffer = switches;
+                self.set_buffer(&switches);
             }
-            Ok(Message::FindReceiver) => {
-                // Controllerからestablishが届いたとき：Acknowledgeを応答して自分をReceiverにする
-                self.connection
-                    .send_message(Message::<SZ, RO, K::Identifier>::Acknowledge);
-                self.state = SplitState::Receiver;
+            Ok(Message::State(state)) => {
+                if let SplitState::Receiver(_) = self.state {
+                    self.shared = Some(state);
+                }
             }
+            Ok(Message::FindReceiver(remote)) => self.find_receiver(&remote),
             Ok(_) => {
                 defmt::warn!("Received unexpected message");
             }
@@ -84,19 +258,36 @@ where
         }
     }
 
-    pub fn request(&mut self, keys: &Vec<K::Identifier, RO>) -> Vec<K::Identifier, RO> {
-        // Controllerの場合：自分のキーを送信、Receiverから応答を受信して返す
-        // Receiverの場合：respondで受信していたバッファを返す
-        // それ以外：空
+    /// Controllerの場合：自分のキーを送信、Receiverから応答を受信して返す
+    /// Receiverの場合：respondで受信していたバッファを返す
+    /// それ以外：空
+    ///
+    /// 相手のスイッチは、押されたときのこちらのスキャンの回数とともに押された順に返す。
+    /// 毎回のやりとりがハートビートを兼ねていて、しばらく途絶えたら切れたとみなして`Undetermined`に戻る。
+    pub fn request(&mut self, keys: &Vec<K::Identifier, RO>) -> Vec<(K::Identifier, u16), RO> {
+        self.ticks = self.ticks.wrapping_add(1);
+        self.track(keys);
         match self.state {
-            SplitState::Controller => {
-                self.connection
-                    .send_message(Message::Switches(keys.clone()));
+            SplitState::Controller(_) => {
+                let sent_at = self.clock.map(|clock| clock());
+                let message = self.outgoing(keys, false);
+                self.send(message);
                 match self.read() {
                     Ok(Message::SwitchesReply(switches)) => {
                         // replied
-                        self.buffer = switches.clone();
-                        switches
+                        self.replied(sent_at);
+                        self.receive_snapshot(switches);
+                        self.buffer.clone()
+                    }
+                    Ok(Message::EventsReply(now, events)) => {
+                        self.replied(sent_at);
+                        self.apply(now, &events);
+                        self.buffer.clone()
+                    }
+                    Ok(Message::FindReceiver(remote)) => {
+                        // 相手が起動し直した
+                        self.find_receiver(&remote);
+                        self.buffer.clone()
                     }
                     Ok(_) => {
                         defmt::warn!("Received unexpected reply");
@@ -104,16 +295,270 @@ where
                     }
                     Err(e) => {
                         defmt::warn!("Failed to receive request: {}", e);
+                        self.misses += 1;
+                        if self.misses >= LINK_LOSS_MISSES {
+                            self.disconnect();
+                        }
                         self.buffer.clone()
                     }
                 }
             }
-            SplitState::Receiver => self.buffer.clone(),
+            SplitState::Receiver(_) => {
+                self.scans += 1;
+                if self.scans >= LINK_LOSS_SCANS {
+                    self.disconnect();
+                }
+                self.buffer.clone()
+            }
             _ => Vec::new(),
         }
     }
 
+    /// 応答があったとき：往復にかかった時間を測る
+    fn replied(&mut self, sent_at: Option<u32>) {
+        self.misses = 0;
+        self.link.latency_us = self
+            .clock
+            .zip(sent_at)
+            .map(|(clock, sent_at)| clock().wrapping_sub(sent_at));
+        self.state = SplitState::Controller(self.link);
+    }
+
+    /// 相手に送るメッセージ。使えるならイベントを、そうでなければスイッチの一覧を送る
+    ///
+    /// 一定の間隔で、または取りこぼしがあったときは、一覧を送って相手と揃え直す。
+    fn outgoing(
+        &mut self,
+        keys: &Vec<K::Identifier, RO>,
+        reply: bool,
+    ) -> Message<SZ, RO, K::Identifier> {
+        let keys = self.limit(keys);
+        if self.features.contains(Features::EVENT_DELTA) && self.snapshot_in > 0 {
+            if let Some(events) = self.events(&keys) {
+                self.snapshot_in -= 1;
+                self.sent = keys;
+                self.sent_snapshot = false;
+                self.released.clear();
+                return if reply {
+                    Message::EventsReply(self.ticks, events)
+                } else {
+                    Message::Events(self.ticks, events)
+                };
+            }
+        }
+        self.snapshot_in = SNAPSHOT_INTERVAL;
+        self.sent = keys.clone();
+        self.sent_snapshot = true;
+        self.released.clear();
+        if reply {
+            Message::SwitchesReply(keys)
+        } else {
+            Message::Switches(keys)
+        }
+    }
+
+    /// 自分のスイッチが押された・離されたときのスキャンの回数を覚える
+    fn track(&mut self, keys: &Vec<K::Identifier, RO>) {
+        for (switch, _) in self.local.iter().filter(|(s, _)| !keys.contains(s)) {
+            self.released.retain(|(s, _)| s != switch);
+            self.released.push((*switch, self.ticks)).ok();
+        }
+        self.local.retain(|(switch, _)| keys.contains(switch));
+        for switch in keys.iter() {
+            if !self.local.iter().any(|(s, _)| s == switch) {
+                self.released.retain(|(s, _)| s != switch);
+                self.local.push((*switch, self.ticks)).ok();
+            }
+        }
+    }
+
+    /// 前に知らせたスイッチからの差分を起きた順に。1つのフレームに入らなければ`None`
+    fn events(
+        &self,
+        keys: &Vec<K::Identifier, RO>,
+    ) -> Option<Vec<SwitchEvent<SZ, K::Identifier>, RO>> {
+        let time = |list: &[(K::Identifier, u16)], switch: &K::Identifier| {
+            list.iter()
+                .find(|(s, _)| s == switch)
+                .map_or(self.ticks, |(_, time)| *time)
+        };
+        let released = self
+            .sent
+            .iter()
+            .filter(|switch| !keys.contains(switch))
+            .map(|switch| SwitchEvent {
+                switch: *switch,
+                pressed: false,
+                time: time(&self.released, switch),
+            });
+        let pressed = keys
+            .iter()
+            .filter(|switch| !self.sent.contains(switch))
+            .map(|switch| SwitchEvent {
+                switch: *switch,
+                pressed: true,
+                time: time(&self.local, switch),
+            });
+        let mut events = Vec::new();
+        for event in released.chain(pressed) {
+            insert_by_time(&mut events, event, self.ticks, |event| event.time).ok()?;
+        }
+        (events.len() <= max_events(SZ)).then_some(events)
+    }
+
+    /// 相手のイベントを起きた順にバッファに反映する
+    ///
+    /// 時刻は相手の`now`からどれだけ前かを見て、こちらのスキャンの回数に直す。
+    fn apply(&mut self, now: u16, events: &[SwitchEvent<SZ, K::Identifier>]) {
+        for event in events {
+            if event.pressed {
+                if !self.buffer.iter().any(|(s, _)| *s == event.switch) {
+                    let time = self.ticks.wrapping_sub(now.wrapping_sub(event.time));
+                    insert_by_time(
+                        &mut self.buffer,
+                        (event.switch, time),
+                        self.ticks,
+                        |(_, t)| *t,
+                    )
+                    .ok();
+                }
+            } else {
+                self.buffer.retain(|(s, _)| *s != event.switch);
+            }
+        }
+    }
+
+    /// 相手のスイッチの一覧をバッファにする。前からあったスイッチは押された時刻をそのままにする
+    fn set_buffer(&mut self, switches: &[K::Identifier]) {
+        let mut buffer = Vec::new();
+        for switch in switches {
+            let time = self
+                .buffer
+                .iter()
+                .find(|(s, _)| s == switch)
+                .map_or(self.ticks, |(_, time)| *time);
+            insert_by_time(&mut buffer, (*switch, time), self.ticks, |(_, t)| *t).ok();
+        }
+        self.buffer = buffer;
+    }
+
+    /// 相手からスイッチの一覧が届いたとき
+    ///
+    /// こちらがイベントを送っていたなら、相手が取りこぼしたのかもしれないので次はこちらも一覧を送る。
+    fn receive_snapshot(&mut self, switches: Vec<K::Identifier, RO>) {
+        self.set_buffer(&switches);
+        if !self.sent_snapshot {
+            self.snapshot_in = 0;
+        }
+    }
+
+    /// つながったときに、前の接続の記録を消す
+    fn connect(&mut self) {
+        self.misses = 0;
+        self.scans = 0;
+        self.link = Link::default();
+        self.shared = None;
+        self.sent.clear();
+        self.snapshot_in = 0;
+    }
+
+    /// 切れたとき：押されたままにならないよう相手のスイッチを離して、役割を決め直す
+    fn disconnect(&mut self) {
+        defmt::warn!("Split connection lost");
+        self.stats.disconnections += 1;
+        self.buffer.clear();
+        self.shared = None;
+        self.state = SplitState::Undetermined;
+    }
+
+    /// 相手から`FindReceiver`が届いたとき：Acknowledgeを応答して自分をReceiverにする
+    ///
+    /// 両方がホストにつながっていれば左手側がControllerになるので、左手側は応答しない。
+    /// 話が通じなくても、理由がわかるように自分の仕様は返す。
+    fn find_receiver(&mut self, remote: &Hello) {
+        match self.negotiate(remote) {
+            Ok(()) if self.host_connected && self.handedness == Handedness::Left => {}
+            Ok(()) => {
+                self.send(Message::Acknowledge(self.hello()));
+                self.connect();
+                self.state = SplitState::Receiver(self.link);
+            }
+            Err(reason) => {
+                self.send(Message::Acknowledge(self.hello()));
+                self.scans = RETRY_SCANS;
+                self.state = SplitState::Incompatible(reason);
+            }
+        }
+    }
+
+    fn hello(&self) -> Hello {
+        Hello::new::<SZ, RO>(self.handedness)
+    }
+
+    /// 相手と仕様を突き合わせて、スイッチの数と機能を決める
+    fn negotiate(&mut self, remote: &Hello) -> Result<(), Incompatibility> {
+        match self.hello().negotiate(remote) {
+            Ok((rollover, features)) => {
+                self.rollover = rollover;
+                self.features = features;
+                Ok(())
+            }
+            Err(reason) => {
+                defmt::warn!("Split halves are incompatible: {}", reason);
+                Err(reason)
+            }
+        }
+    }
+
+    /// 相手が受け取れる数までに切り詰める
+    fn limit(&self, keys: &Vec<K::Identifier, RO>) -> Vec<K::Identifier, RO> {
+        keys.iter().take(self.rollover).cloned().collect()
+    }
+
+    fn send(&mut self, message: Message<SZ, RO, K::Identifier>) {
+        self.connection.send_message(self.sequence, message);
+        self.sequence = self.sequence.wrapping_add(1);
+    }
+
+    /// 連番が戻っているフレームは、重複したか順番が入れ替わった古いものなので捨てて次を読む
     fn read(&mut self) -> Result<Message<SZ, RO, K::Identifier>, Error<S::Error>> {
-        self.connection.read_message(&mut self.timer, self.timeout) // timeout in 10ms
+        loop {
+            let (sequence, message) =
+                self.connection
+                    .read_message(&mut self.timer, self.timeout, &mut self.stats)?; // timeout in 10ms
+            if let Message::FindReceiver(_) = message {
+                // 相手が起動し直したので連番も最初から
+                self.expected_sequence = None;
+            }
+            if let Some(expected) = self.expected_sequence {
+                let lost = sequence.wrapping_sub(expected);
+                if lost >= STALE_SEQUENCE {
+                    self.stats.stale_frames += 1;
+                    continue;
+                }
+                if lost > 0 {
+                    // 取りこぼしたイベントがあるかもしれないので、一覧を送って揃え直す
+                    self.stats.lost_frames += lost as u32;
+                    self.snapshot_in = 0;
+                }
+            }
+            self.expected_sequence = Some(sequence.wrapping_add(1));
+            return Ok(message);
+        }
     }
 }
+
+/// 古い順に並んだ`list`に、同じ時刻のものより後になるように入れる。時刻は`now`からどれだけ前かで比べる
+fn insert_by_time<T, const N: usize>(
+    list: &mut Vec<T, N>,
+    item: T,
+    now: u16,
+    time: impl Fn(&T) -> u16,
+) -> Result<(), T> {
+    let age = now.wrapping_sub(time(&item));
+    let index = list
+        .iter()
+        .position(|other| now.wrapping_sub(time(other)) < age)
+        .unwrap_or(list.len());
+    list.insert(index, item)
+}
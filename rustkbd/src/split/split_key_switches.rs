@@ -1,9 +1,17 @@
+use core::fmt::Write;
+
 use embedded_hal_0_2::timer::CountDown;
 use heapless::Vec;
 
 use crate::{
-    keyboard::{KeySwitchIdentifier, KeySwitches},
-    split::{Connection, SplitCommunicator, SplitState},
+    console::{Command, ConsoleHandler},
+    keyboard::{
+        InvalidSwitchIdentifier, KeySwitchIdentifier, KeySwitches, KeyboardState, Layer,
+        MatrixPosition,
+    },
+    split::{
+        Connection, Features, Handedness, LinkStats, SharedState, SplitCommunicator, SplitState,
+    },
 };
 
 pub struct SplitKeySwitches<
@@ -18,7 +26,7 @@ pub struct SplitKeySwitches<
     communicator: SplitCommunicator<SZ, RO, K, C, T>,
     switches: Vec<K::Identifier, RO>,
     underlying_switches: K,
-    is_left: bool,
+    handedness: Handedness,
 }
 
 impl<const SZ: usize, const RO: usize, C: Connection, K: KeySwitches<SZ, RO>, T: CountDown>
@@ -26,15 +34,32 @@ impl<const SZ: usize, const RO: usize, C: Connection, K: KeySwitches<SZ, RO>, T:
 where
     T::Time: Copy,
 {
-    pub fn new(key_switches: K, connection: C, timer: T, timeout: T::Time, is_left: bool) -> Self {
+    /// どちらがControllerになるかは、ホストにつながっている側に決まる。
+    /// 両方がつながっていれば左手側になる。
+    pub fn new(
+        key_switches: K,
+        connection: C,
+        timer: T,
+        timeout: T::Time,
+        handedness: Handedness,
+    ) -> Self {
         SplitKeySwitches {
-            communicator: SplitCommunicator::new(connection, timer, timeout),
+            communicator: SplitCommunicator::new(connection, timer, timeout, handedness),
             switches: Vec::new(),
             underlying_switches: key_switches,
-            is_left,
+            handedness,
         }
     }
 
+    /// 往復にかかった時間を`SplitState`に出すための時計(µs)を設定する
+    pub fn set_clock(&mut self, clock: fn() -> u32) {
+        self.communicator.set_clock(clock);
+    }
+
+    pub fn handedness(&self) -> Handedness {
+        self.handedness
+    }
+
     pub fn poll(&mut self) {
         self.communicator.respond(&self.switches);
     }
@@ -43,6 +68,16 @@ where
         self.communicator.state()
     }
 
+    /// 左右の通信で起きたことの回数
+    pub fn stats(&self) -> LinkStats {
+        self.communicator.stats()
+    }
+
+    /// 相手と決めた、使う機能
+    pub fn features(&self) -> Features {
+        self.communicator.features()
+    }
+
     fn establish(&mut self) {
         if let Err(e) = self.communicator.establish() {
             defmt::warn!("Failed to establish split connection: {}", e);
@@ -50,31 +85,95 @@ where
     }
 
     fn _scan(&mut self) -> Vec<SplitKeySwitchIdentifier<SZ, K::Identifier>, RO> {
-        if self.is_left && self.communicator.state() == SplitState::Undetermined {
+        if self.communicator.wants_controller() {
             self.establish();
         }
         self.switches = self.underlying_switches.scan();
-        let near_side = self.switches.clone();
-        let far_side = self.communicator.request(&near_side);
+        let far_side = self.communicator.request(&self.switches);
 
         let left_side_transform: fn(K::Identifier) -> SplitKeySwitchIdentifier<SZ, K::Identifier> =
             SplitKeySwitchIdentifier::<SZ, K::Identifier>::Left;
         let right_side_transform: fn(K::Identifier) -> SplitKeySwitchIdentifier<SZ, K::Identifier> =
             SplitKeySwitchIdentifier::<SZ, K::Identifier>::Right;
 
-        let (near_side_transform, far_side_transform) = if self.is_left {
-            (left_side_transform, right_side_transform)
-        } else {
-            (right_side_transform, left_side_transform)
+        let (near_side_transform, far_side_transform) = match self.handedness {
+            Handedness::Left => (left_side_transform, right_side_transform),
+            Handedness::Right => (right_side_transform, left_side_transform),
         };
 
-        near_side
-            .iter()
-            .cloned()
-            .map(near_side_transform)
-            .chain(far_side.iter().cloned().map(far_side_transform))
-            .take(RO)
-            .collect()
+        // 両側のスイッチを押された順に並べる。同じときに押されたなら近い側が先
+        let now = self.communicator.ticks();
+        let mut near_side = self.communicator.local().iter().peekable();
+        let mut far_side = far_side.iter().peekable();
+        let mut switches = Vec::new();
+        loop {
+            let next = match (near_side.peek(), far_side.peek()) {
+                (Some((_, near)), Some((_, far)))
+                    if now.wrapping_sub(*far) > now.wrapping_sub(*near) =>
+                {
+                    far_side
+                        .next()
+                        .map(|(switch, _)| far_side_transform(*switch))
+                }
+                (Some(_), _) => near_side
+                    .next()
+                    .map(|(switch, _)| near_side_transform(*switch)),
+                (None, Some(_)) => far_side
+                    .next()
+                    .map(|(switch, _)| far_side_transform(*switch)),
+                (None, None) => None,
+            };
+            match next {
+                Some(switch) if switches.push(switch).is_ok() => {}
+                _ => break,
+            }
+        }
+        switches
+    }
+}
+
+impl<
+        const SZ: usize,
+        const RO: usize,
+        C: Connection,
+        K: KeySwitches<SZ, RO> + ConsoleHandler,
+        T: CountDown,
+    > ConsoleHandler for SplitKeySwitches<SZ, RO, C, K, T>
+where
+    T::Time: Copy,
+{
+    fn handle(&mut self, command: &Command<'_>, out: &mut dyn Write) -> bool {
+        if command.matches("split").is_some() {
+            writeln!(out, "side: {:?}", self.handedness).ok();
+            writeln!(out, "state: {:?}", self.communicator.state()).ok();
+            writeln!(
+                out,
+                "features: {:#04x}",
+                self.communicator.features().bits()
+            )
+            .ok();
+            let stats = self.communicator.stats();
+            writeln!(
+                out,
+                "frames: {}, crc errors: {}, skipped bytes: {}, lost: {}, stale: {}, timeouts: {}, disconnections: {}",
+                stats.frames,
+                stats.crc_errors,
+                stats.skipped_bytes,
+                stats.lost_frames,
+                stats.stale_frames,
+                stats.timeouts,
+                stats.disconnections
+            )
+            .ok();
+            true
+        } else {
+            self.underlying_switches.handle(command, out)
+        }
+    }
+
+    fn help(&self, out: &mut dyn Write) {
+        writeln!(out, "split   split connection state").ok();
+        self.underlying_switches.help(out);
     }
 }
 
@@ -84,18 +183,36 @@ pub enum SplitKeySwitchIdentifier<const SZ: usize, I: KeySwitchIdentifier<SZ>> {
     Right(I),
 }
 
+/// 左右を縦に並べた行列とみなす（右手側は`rows / 2`行目から）
+impl<const SZ: usize, I: KeySwitchIdentifier<SZ> + MatrixPosition> MatrixPosition
+    for SplitKeySwitchIdentifier<SZ, I>
+{
+    fn position(&self, rows: usize, cols: usize) -> (usize, usize) {
+        match self {
+            SplitKeySwitchIdentifier::Left(i) => i.position(rows / 2, cols),
+            SplitKeySwitchIdentifier::Right(i) => {
+                let (row, col) = i.position(rows / 2, cols);
+                (rows / 2 + row, col)
+            }
+        }
+    }
+}
+
 macro_rules! impl_split_key_switches {
     ( $x:expr ) => {
-        impl<I: KeySwitchIdentifier<$x>> From<[u8; $x + 1]> for SplitKeySwitchIdentifier<$x, I> {
-            fn from(value: [u8; $x + 1]) -> Self {
-                match value.split_first().unwrap() {
-                    (&0, v) => {
-                        SplitKeySwitchIdentifier::Left(I::from(<[u8; $x]>::try_from(v).unwrap()))
-                    }
-                    (&1, v) => {
-                        SplitKeySwitchIdentifier::Right(I::from(<[u8; $x]>::try_from(v).unwrap()))
-                    }
-                    _ => panic!("unexpected switch data"), // TODO: TryFromにすべきか
+        impl<I: KeySwitchIdentifier<$x>> TryFrom<[u8; $x + 1]> for SplitKeySwitchIdentifier<$x, I> {
+            type Error = InvalidSwitchIdentifier;
+
+            fn try_from(value: [u8; $x + 1]) -> Result<Self, Self::Error> {
+                let (head, tail) = value.split_first().unwrap();
+                let switch = <[u8; $x]>::try_from(tail)
+                    .ok()
+                    .and_then(|v| I::try_from(v).ok())
+                    .ok_or(InvalidSwitchIdentifier)?;
+                match head {
+                    0 => Ok(SplitKeySwitchIdentifier::Left(switch)),
+                    1 => Ok(SplitKeySwitchIdentifier::Right(switch)),
+                    _ => Err(InvalidSwitchIdentifier),
                 }
             }
         }
@@ -129,6 +246,31 @@ macro_rules! impl_split_key_switches {
             fn scan(&mut self) -> Vec<Self::Identifier, RO> {
                 self._scan()
             }
+
+            fn set_host_connected(&mut self, connected: bool) {
+                self.communicator.set_host_connected(connected);
+            }
+
+            fn share_state<L: Layer>(&mut self, state: &KeyboardState<L, RO>) {
+                self.communicator.share_state(SharedState {
+                    layer: state.layer.index() as u8,
+                    keys: state.keys.clone(),
+                    host_layout: state.host_layout,
+                    host_leds: state.host_leds,
+                    wpm: state.wpm,
+                });
+            }
+
+            fn shared_state<L: Layer>(&self) -> Option<KeyboardState<L, RO>> {
+                let state = self.communicator.shared_state()?;
+                Some(KeyboardState {
+                    layer: L::from_index(state.layer as usize)?,
+                    keys: state.keys.clone(),
+                    host_layout: state.host_layout,
+                    host_leds: state.host_leds,
+                    wpm: state.wpm,
+                })
+            }
         }
     };
 }
@@ -140,3 +282,325 @@ impl_split_key_switches!(4);
 impl_split_key_switches!(5);
 impl_split_key_switches!(6);
 impl_split_key_switches!(7);
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::RefCell, ops::Range};
+
+    use super::*;
+    use crate::{
+        keyboard::{
+            array_layout_tests::{Switch, TestLayer},
+            Controller, HostLayout, HostLeds, Key,
+        },
+        scenario::{LoopbackConnection, MockTimer, Pipe, Report, Scenario},
+        split::{
+            split_communicator::RETRY_SCANS, ConnectionExt, Hello, Incompatibility, Message,
+            SplitArrayLayout, SwitchEvent,
+        },
+    };
+
+    /// 右手側の(1, 0)。右手側の行は左手側の下に続く
+    static LAYER_SWITCHES: [((usize, usize), TestLayer); 1] = [((3, 0), TestLayer::Lower)];
+
+    /// `host`の側だけをホストにつないで動かし、レポートと(`host`の側, 反対側)の状態、
+    /// 反対側に送られてきたキーボードの状態を返す
+    ///
+    /// `unplugged`の間は左右をつなぐ線が抜けていて、`dropped`の間は反対側の応答だけが届かない。
+    fn run_split(
+        script: &str,
+        host: Handedness,
+        unplugged: Range<u32>,
+        dropped: Range<u32>,
+    ) -> (
+        Vec<Report, 8>,
+        SplitState,
+        SplitState,
+        Option<KeyboardState<TestLayer, 6>>,
+    ) {
+        let scenario = Scenario::<8>::parse(script).unwrap();
+        let other = match host {
+            Handedness::Left => Handedness::Right,
+            Handedness::Right => Handedness::Left,
+        };
+        let (to_host, to_other) = (Pipe::new(), Pipe::new());
+        let far = RefCell::new(SplitKeySwitches::<2, 6, _, _, _>::new(
+            scenario.switches(Some(other), Switch),
+            LoopbackConnection::new(&to_other, &to_host),
+            MockTimer::new(),
+            3,
+            other,
+        ));
+        // 反対側も実機と同じく、スキャンしてから応答する
+        let poll = || {
+            if unplugged.contains(&scenario.now()) {
+                to_other.clear();
+                return;
+            }
+            let mut far = far.borrow_mut();
+            KeySwitches::<3, 6>::scan(&mut *far);
+            far.poll();
+            if dropped.contains(&scenario.now()) {
+                to_host.clear();
+            }
+        };
+        let near = SplitKeySwitches::<2, 6, _, _, _>::new(
+            scenario.switches(Some(host), Switch),
+            LoopbackConnection::with_idle(&to_host, &to_other, &poll),
+            MockTimer::new(),
+            3,
+            host,
+        );
+        let layout = SplitArrayLayout::new(
+            [
+                [[Key::A, Key::B], [Key::None; 2]],
+                [[Key::Digit1_Exclamation, Key::Digit2_At], [Key::None; 2]],
+                [[Key::None; 2]; 2],
+            ],
+            [
+                [[Key::C, Key::D], [Key::None; 2]],
+                [[Key::Digit3_Number, Key::Digit4_Dollar], [Key::None; 2]],
+                [[Key::None; 2]; 2],
+            ],
+            &LAYER_SWITCHES,
+        );
+        let recorder = scenario.recorder::<8>();
+        recorder.set_host_leds(HostLeds::CAPS_LOCK);
+        let mut controller = Controller::<3, 6, _, _, _>::new(recorder, near, layout);
+        controller.set_host_layout(HostLayout::Jis);
+        scenario.run_controller(&mut controller, 10);
+        let far = far.borrow();
+        (
+            controller.communicator.reports().clone(),
+            controller.key_switches.state(),
+            far.state(),
+            KeySwitches::<3, 6>::shared_state(&*far),
+        )
+    }
+
+    const SCRIPT: &str = "press(right 0,0) @0ms; press(left 0,1) @10ms; press(right 1,0) @20ms; \
+         press(left 0,0) @30ms; release(right 1,0) @40ms; release(left 0,0) @40ms; \
+         release(left 0,1) @40ms; release(right 0,0) @50ms";
+
+    #[test]
+    // 右手側のスイッチは通信で左手側に届き、左手側のスイッチと合わせてキーになる
+    fn test_scenario_split() {
+        let (reports, host, other, _) = run_split(SCRIPT, Handedness::Left, 0..0, 0..0);
+        assert!(matches!(host, SplitState::Controller(_)));
+        assert!(matches!(other, SplitState::Receiver(_)));
+        assert_eq!(
+            [
+                Report::new(0, &[Key::C]),
+                Report::new(10, &[Key::C, Key::B]),
+                Report::new(30, &[Key::C, Key::B, Key::Digit1_Exclamation]),
+                Report::new(40, &[Key::C]),
+                Report::new(50, &[]),
+            ],
+            reports.as_slice()
+        );
+    }
+
+    #[test]
+    // 右手側をホストにつなげば右手側がControllerになり、左右の割り当ては変わらない
+    fn test_scenario_split_right_controller() {
+        let (reports, host, other, _) = run_split(SCRIPT, Handedness::Right, 0..0, 0..0);
+        assert!(matches!(host, SplitState::Controller(_)));
+        assert!(matches!(other, SplitState::Receiver(_)));
+        assert_eq!(
+            [
+                Report::new(0, &[Key::C]),
+                Report::new(10, &[Key::C, Key::B]),
+                Report::new(30, &[Key::C, Key::B, Key::Digit1_Exclamation]),
+                Report::new(40, &[Key::C]),
+                Report::new(50, &[]),
+            ],
+            reports.as_slice()
+        );
+    }
+
+    #[test]
+    // 線が抜けたら相手のスイッチを離し、つなぎ直せば元に戻る
+    fn test_scenario_split_reconnect() {
+        let (reports, host, other, _) = run_split(
+            "press(right 0,0) @0ms; press(left 0,1) @10ms; release(left 0,1) @250ms; \
+             release(right 0,0) @260ms",
+            Handedness::Left,
+            20..50,
+            0..0,
+        );
+        assert!(matches!(host, SplitState::Controller(_)));
+        assert!(matches!(other, SplitState::Receiver(_)));
+        assert_eq!(
+            [
+                Report::new(0, &[Key::C]),
+                Report::new(10, &[Key::C, Key::B]),
+                Report::new(22, &[Key::B]),
+                Report::new(123, &[Key::B, Key::C]),
+                Report::new(250, &[Key::C]),
+                Report::new(260, &[]),
+            ],
+            reports.as_slice()
+        );
+    }
+
+    #[test]
+    // 離したイベントが届かなくても、取りこぼしに気づいて一覧を送り直し、押されたままにならない
+    fn test_scenario_split_lost_event() {
+        let (reports, _, _, _) = run_split(
+            "press(right 0,0) @0ms; release(right 0,0) @20ms",
+            Handedness::Left,
+            0..0,
+            20..21,
+        );
+        assert_eq!(
+            [Report::new(0, &[Key::C]), Report::new(22, &[])],
+            reports.as_slice()
+        );
+    }
+
+    #[test]
+    // Controllerのレイヤ、押されているキー、ホストの設定とLEDがReceiverに届く
+    fn test_scenario_split_state_sync() {
+        let (_, _, _, state) = run_split(
+            "press(right 1,0) @0ms; press(left 0,0) @10ms",
+            Handedness::Left,
+            0..0,
+            0..0,
+        );
+        let state = state.unwrap();
+        assert_eq!(TestLayer::Lower, state.layer);
+        assert_eq!([Key::Digit1_Exclamation], state.keys.as_slice());
+        assert_eq!(HostLayout::Jis, state.host_layout);
+        assert_eq!(HostLeds::CAPS_LOCK, state.host_leds);
+    }
+
+    #[test]
+    // 通信の版が違う相手とは通信せず、理由を状態に残す。しばらくしたら探し直す
+    fn test_incompatible() {
+        let scenario = Scenario::<1>::parse("press(left 0,0) @0ms").unwrap();
+        let (to_left, to_right) = (Pipe::new(), Pipe::new());
+        let connection = LoopbackConnection::new(&to_left, &to_right);
+        let remote = Hello {
+            version: 0,
+            ..Hello::new::<2, 6>(Handedness::Right)
+        };
+        LoopbackConnection::new(&to_right, &to_left)
+            .send_message::<2, 6, Switch>(0, Message::Acknowledge(remote));
+        let mut left = SplitKeySwitches::<2, 6, _, _, _>::new(
+            scenario.switches(Some(Handedness::Left), Switch),
+            connection,
+            MockTimer::new(),
+            3,
+            Handedness::Left,
+        );
+        KeySwitches::<3, 6>::set_host_connected(&mut left, true);
+        assert_eq!(
+            [SplitKeySwitchIdentifier::Left(Switch(0, 0))],
+            KeySwitches::<3, 6>::scan(&mut left).as_slice()
+        );
+        assert_eq!(
+            SplitState::Incompatible(Incompatibility::Version {
+                local: 3,
+                remote: 0
+            }),
+            left.state()
+        );
+
+        // 相手を書き換えたら、次に探したときにつながる
+        to_right.clear();
+        LoopbackConnection::new(&to_right, &to_left).send_message::<2, 6, Switch>(
+            1,
+            Message::Acknowledge(Hello::new::<2, 6>(Handedness::Right)),
+        );
+        for _ in 1..RETRY_SCANS {
+            KeySwitches::<3, 6>::scan(&mut left);
+        }
+        assert!(matches!(left.state(), SplitState::Incompatible(_)));
+        KeySwitches::<3, 6>::scan(&mut left);
+        assert!(matches!(left.state(), SplitState::Controller(_)));
+    }
+
+    #[test]
+    // 反対側で先に押されたスイッチは、届くのが遅れても先に並ぶ
+    fn test_far_side_pressed_first() {
+        let scenario = Scenario::<1>::parse("press(left 0,1) @0ms").unwrap();
+        let (to_left, to_right) = (Pipe::new(), Pipe::new());
+        let connection = LoopbackConnection::new(&to_left, &to_right);
+        let right = LoopbackConnection::new(&to_right, &to_left);
+        right.send_message::<2, 6, Switch>(
+            0,
+            Message::Acknowledge(Hello::new::<2, 6>(Handedness::Right)),
+        );
+        let mut left = SplitKeySwitches::<2, 6, _, _, _>::new(
+            scenario.switches(Some(Handedness::Left), Switch),
+            connection,
+            MockTimer::new(),
+            3,
+            Handedness::Left,
+        );
+        KeySwitches::<3, 6>::set_host_connected(&mut left, true);
+        assert_eq!(
+            [SplitKeySwitchIdentifier::Left(Switch(0, 1))],
+            KeySwitches::<3, 6>::scan(&mut left).as_slice()
+        );
+        assert!(matches!(left.state(), SplitState::Controller(_)));
+
+        // 右手側では、いまより10回前のスキャンで押されていた
+        let event = SwitchEvent {
+            switch: Switch(0, 0),
+            pressed: true,
+            time: 40,
+        };
+        right.send_message::<2, 6, Switch>(
+            1,
+            Message::EventsReply(50, [event].into_iter().collect()),
+        );
+        assert_eq!(
+            [
+                SplitKeySwitchIdentifier::Right(Switch(0, 0)),
+                SplitKeySwitchIdentifier::Left(Switch(0, 1)),
+            ],
+            KeySwitches::<3, 6>::scan(&mut left).as_slice()
+        );
+    }
+
+    #[test]
+    // 連番が戻ったフレームは重複として捨て、取りこぼしには数えない
+    fn test_stale_frame() {
+        let scenario = Scenario::<1>::parse("press(left 0,1) @0ms").unwrap();
+        let (to_left, to_right) = (Pipe::new(), Pipe::new());
+        let connection = LoopbackConnection::new(&to_left, &to_right);
+        let right = LoopbackConnection::new(&to_right, &to_left);
+        right.send_message::<2, 6, Switch>(
+            0,
+            Message::Acknowledge(Hello::new::<2, 6>(Handedness::Right)),
+        );
+        let mut left = SplitKeySwitches::<2, 6, _, _, _>::new(
+            scenario.switches(Some(Handedness::Left), Switch),
+            connection,
+            MockTimer::new(),
+            3,
+            Handedness::Left,
+        );
+        KeySwitches::<3, 6>::set_host_connected(&mut left, true);
+        KeySwitches::<3, 6>::scan(&mut left);
+        assert!(matches!(left.state(), SplitState::Controller(_)));
+
+        let event = SwitchEvent {
+            switch: Switch(0, 0),
+            pressed: true,
+            time: 0,
+        };
+        right.send_message::<2, 6, Switch>(
+            0,
+            Message::EventsReply(0, [event].into_iter().collect()),
+        );
+        right.send_message::<2, 6, Switch>(1, Message::EventsReply(0, Vec::new()));
+        assert_eq!(
+            [SplitKeySwitchIdentifier::Left(Switch(0, 1))],
+            KeySwitches::<3, 6>::scan(&mut left).as_slice()
+        );
+        assert_eq!(0, left.stats().lost_frames);
+        assert_eq!(1, left.stats().stale_frames);
+    }
+}
@@ -1,6 +1,20 @@
+use crate::split::Incompatibility;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SplitState {
+    /// まだ役割が決まっていない。ホストにつながっていない側は、相手から呼ばれるまでこのまま
     Undetermined,
-    Controller,
-    Receiver,
+    /// ホストにつながっているが、相手が見つからない
+    NotAvailable,
+    Controller(Link),
+    Receiver(Link),
+    /// 相手と話が通じないので通信しない。ホストにつながっている側はしばらくしたら探し直す
+    Incompatible(Incompatibility),
+}
+
+/// つながっている間の様子
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Link {
+    /// 最後にやりとりしたときの往復の時間(µs)。Controllerで時計があるときだけ測る
+    pub latency_us: Option<u32>,
 }
@@ -0,0 +1,8 @@
+mod error;
+mod flash_storage;
+mod mock_flash;
+mod settings;
+pub use error::Error;
+pub use flash_storage::{Record, Storage};
+pub use mock_flash::{MockFlash, MockFlashError};
+pub use settings::Setting;
@@ -0,0 +1,24 @@
+use core::fmt::Debug;
+
+use defmt::Format;
+
+#[derive(Debug, Format)]
+pub enum Error<E: 'static + Debug> {
+    /// フラッシュの範囲やサイズが使えない組み合わせ
+    InvalidConfiguration,
+    /// 0xffffは空き領域を表すので使えない
+    InvalidKey,
+    /// 最新のレコードだけを残しても書き込めない
+    Full,
+    /// 保持できるキーの種類数を超えた
+    TooManyKeys,
+    /// 設定やキーマップがレコードの形式に収まらない
+    TooLarge,
+    BufferTooSmall {
+        len: usize,
+    },
+    FlashError {
+        #[defmt(Debug2Format)]
+        source: E,
+    },
+}
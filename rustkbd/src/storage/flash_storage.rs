@@ -0,0 +1,673 @@
+use core::cmp::min;
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::LinearMap;
+
+use crate::crc::Crc16;
+
+use super::Error;
+
+/// セクタヘッダの先頭に書く値（"RKBD"）
+const SECTOR_MAGIC: u32 = 0x4442_4b52;
+/// magic(4) + generation(4) + crc(2)
+const SECTOR_HEADER_LEN: usize = 10;
+/// key(2) + version(1) + kind(1) + len(2)
+const RECORD_HEADER_LEN: usize = 6;
+const RECORD_CRC_LEN: usize = 2;
+/// 読み書きの単位。READ_SIZEとWRITE_SIZEはこれを割り切れないといけない
+const CHUNK_LEN: usize = 32;
+/// コンパクション時に保持できるキーの種類数
+const MAX_KEYS: usize = 32;
+
+const KIND_VALUE: u8 = 0x00;
+const KIND_REMOVED: u8 = 0x01;
+const EMPTY_KEY: u16 = 0xffff;
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct RecordHeader {
+    pub key: u16,
+    pub version: u8,
+    pub kind: u8,
+    pub len: usize,
+}
+
+/// 読み出したレコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub version: u8,
+    pub data: &'a [u8],
+}
+
+enum Scan {
+    Record(RecordHeader),
+    End,
+    Corrupted,
+}
+
+/// NORフラッシュ上のキー・バリューストア
+///
+/// 連続した`sectors`個のセクタをリングとして使う。レコードはアクティブなセクタの末尾に追記し、
+/// いっぱいになったら各キーの最新のレコードだけを次のセクタにコピーしてそちらをアクティブにする。
+/// セクタヘッダはコピーが終わってから書くので、途中で電源が落ちても元のセクタが使われる。
+/// 書き込み途中で電源が落ちて壊れたレコードは、起動時にそれより前のレコードだけを残して捨てる。
+pub struct Storage<F: NorFlash> {
+    flash: F,
+    offset: u32,
+    sectors: usize,
+    active: usize,
+    generation: u32,
+    /// アクティブなセクタ内で次に書き込む位置
+    cursor: usize,
+}
+
+impl<F: NorFlash> Storage<F> {
+    const ALIGN: usize = if F::READ_SIZE > F::WRITE_SIZE {
+        F::READ_SIZE
+    } else {
+        F::WRITE_SIZE
+    };
+
+    /// `offset`から`sectors`個のセクタを使う。有効なデータがなければ初期化する
+    pub fn new(flash: F, offset: u32, sectors: usize) -> Result<Self, Error<F::Error>> {
+        let end = offset as usize + sectors * F::ERASE_SIZE;
+        if sectors < 2
+            || !(offset as usize).is_multiple_of(F::ERASE_SIZE)
+            || end > flash.capacity()
+            || !CHUNK_LEN.is_multiple_of(Self::ALIGN)
+            || !F::ERASE_SIZE.is_multiple_of(Self::ALIGN)
+            || F::ERASE_SIZE < Self::records_start() + Self::record_len(0)
+        {
+            return Err(Error::InvalidConfiguration);
+        }
+
+        let mut storage = Storage {
+            flash,
+            offset,
+            sectors,
+            active: 0,
+            generation: 0,
+            cursor: 0,
+        };
+
+        let mut latest: Option<(usize, u32)> = None;
+        for sector in 0..sectors {
+            if let Some(generation) = storage.read_sector_header(sector)? {
+                if latest.is_none_or(|(_, g)| generation > g) {
+                    latest = Some((sector, generation));
+                }
+            }
+        }
+
+        let Some((sector, generation)) = latest else {
+            storage.format()?;
+            return Ok(storage);
+        };
+        storage.active = sector;
+        storage.generation = generation;
+        let (end, corrupted) = storage.find_end()?;
+        storage.cursor = end;
+        if corrupted {
+            defmt::warn!("Corrupted record found in sector {}", sector);
+            storage.compact()?;
+        }
+        Ok(storage)
+    }
+
+    /// すべてのレコードを消す
+    pub fn format(&mut self) -> Result<(), Error<F::Error>> {
+        for sector in 0..self.sectors {
+            self.erase_sector(sector)?;
+        }
+        self.active = 0;
+        self.generation = 0;
+        self.cursor = Self::records_start();
+        self.write_sector_header(0, 0)
+    }
+
+    /// 最新のレコードを読み出す
+    pub fn read<'b>(
+        &mut self,
+        key: u16,
+        buffer: &'b mut [u8],
+    ) -> Result<Option<Record<'b>>, Error<F::Error>> {
+        let Some((at, header)) = self.find(key)? else {
+            return Ok(None);
+        };
+        if buffer.len() < header.len {
+            return Err(Error::BufferTooSmall { len: header.len });
+        }
+        let address = self.sector_address(self.active) + (at + RECORD_HEADER_LEN) as u32;
+        self.read_bytes(address, &mut buffer[..header.len])?;
+        Ok(Some(Record {
+            version: header.version,
+            data: &buffer[..header.len],
+        }))
+    }
+
+    pub fn write(&mut self, key: u16, version: u8, data: &[u8]) -> Result<(), Error<F::Error>> {
+        self.append(key, version, KIND_VALUE, data.len(), data.iter().copied())
+    }
+
+    pub fn remove(&mut self, key: u16) -> Result<(), Error<F::Error>> {
+        if self.find(key)?.is_none() {
+            return Ok(());
+        }
+        self.append(key, 0, KIND_REMOVED, 0, core::iter::empty())
+    }
+
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// 最新のレコードの位置とヘッダを返す
+    pub(super) fn find(
+        &mut self,
+        key: u16,
+    ) -> Result<Option<(usize, RecordHeader)>, Error<F::Error>> {
+        let mut found = None;
+        let mut at = Self::records_start();
+        while let Scan::Record(header) = self.scan(self.active, at)? {
+            if header.key == key {
+                found = (header.kind == KIND_VALUE).then_some((at, header));
+            }
+            at += Self::record_len(header.len);
+        }
+        Ok(found)
+    }
+
+    /// 最新のレコードのデータを`position`から読む
+    pub(super) fn read_data(
+        &mut self,
+        at: usize,
+        position: usize,
+        buffer: &mut [u8],
+    ) -> Result<(), Error<F::Error>> {
+        let address = self.sector_address(self.active) + (at + RECORD_HEADER_LEN + position) as u32;
+        self.read_bytes(address, buffer)
+    }
+
+    /// `data`はちょうど`len`バイトを返さないといけない
+    pub(super) fn append(
+        &mut self,
+        key: u16,
+        version: u8,
+        kind: u8,
+        len: usize,
+        data: impl Iterator<Item = u8>,
+    ) -> Result<(), Error<F::Error>> {
+        if key == EMPTY_KEY {
+            return Err(Error::InvalidKey);
+        }
+        let record_len = Self::record_len(len);
+        if len > u16::MAX as usize || Self::records_start() + record_len > F::ERASE_SIZE {
+            return Err(Error::Full);
+        }
+        if self.cursor + record_len > F::ERASE_SIZE {
+            self.compact()?;
+            if self.cursor + record_len > F::ERASE_SIZE {
+                return Err(Error::Full);
+            }
+        }
+
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        header[0..2].copy_from_slice(&key.to_le_bytes());
+        header[2] = version;
+        header[3] = kind;
+        header[4..6].copy_from_slice(&(len as u16).to_le_bytes());
+
+        let address = self.sector_address(self.active) + self.cursor as u32;
+        match write_record(
+            &mut self.flash,
+            address,
+            &header,
+            data.take(len),
+            Self::ALIGN,
+        ) {
+            Ok(()) => {
+                self.cursor += record_len;
+                Ok(())
+            }
+            Err(source) => {
+                // どこまで書けたかわからないので、次の書き込みはコンパクションしてからにする
+                self.cursor = F::ERASE_SIZE;
+                Err(Error::FlashError { source })
+            }
+        }
+    }
+
+    /// 各キーの最新のレコードを次のセクタにコピーしてアクティブにする
+    fn compact(&mut self) -> Result<(), Error<F::Error>> {
+        let mut live = LinearMap::<u16, usize, MAX_KEYS>::new();
+        let mut at = Self::records_start();
+        while let Scan::Record(header) = self.scan(self.active, at)? {
+            if header.kind == KIND_VALUE {
+                live.insert(header.key, at)
+                    .map_err(|_| Error::TooManyKeys)?;
+            } else {
+                live.remove(&header.key);
+            }
+            at += Self::record_len(header.len);
+        }
+
+        let target = (self.active + 1) % self.sectors;
+        self.erase_sector(target)?;
+        let mut cursor = Self::records_start();
+        for (_, &at) in live.iter() {
+            let mut header = [0u8; RECORD_HEADER_LEN];
+            let source = self.sector_address(self.active) + at as u32;
+            self.read_bytes(source, &mut header)?;
+            let data_len = parse_header(&header).len;
+            if cursor + Self::record_len(data_len) > F::ERASE_SIZE {
+                return Err(Error::Full);
+            }
+
+            // ヘッダからCRCまでそのままコピーする
+            let len = RECORD_HEADER_LEN + data_len + RECORD_CRC_LEN;
+            let mut writer = Writer::new(self.sector_address(target) + cursor as u32);
+            let mut chunk = [0u8; CHUNK_LEN];
+            let mut copied = 0;
+            while copied < len {
+                let n = min(CHUNK_LEN, len - copied);
+                self.read_bytes(source + copied as u32, &mut chunk[..n])?;
+                writer
+                    .push(&mut self.flash, &chunk[..n])
+                    .map_err(|source| Error::FlashError { source })?;
+                copied += n;
+            }
+            writer
+                .finish(&mut self.flash, Self::ALIGN)
+                .map_err(|source| Error::FlashError { source })?;
+            cursor += Self::round_up(len);
+        }
+
+        self.write_sector_header(target, self.generation.wrapping_add(1))?;
+        self.active = target;
+        self.generation = self.generation.wrapping_add(1);
+        self.cursor = cursor;
+        Ok(())
+    }
+
+    /// 追記する位置と、壊れたレコードで止まったかどうかを返す
+    fn find_end(&mut self) -> Result<(usize, bool), Error<F::Error>> {
+        let mut at = Self::records_start();
+        loop {
+            match self.scan(self.active, at)? {
+                Scan::Record(header) => at += Self::record_len(header.len),
+                Scan::End => return Ok((at, false)),
+                Scan::Corrupted => return Ok((at, true)),
+            }
+        }
+    }
+
+    fn scan(&mut self, sector: usize, at: usize) -> Result<Scan, Error<F::Error>> {
+        if at + Self::record_len(0) > F::ERASE_SIZE {
+            return Ok(Scan::End);
+        }
+        let address = self.sector_address(sector) + at as u32;
+        let mut bytes = [0u8; RECORD_HEADER_LEN];
+        self.read_bytes(address, &mut bytes)?;
+        if bytes.iter().all(|b| *b == 0xff) {
+            return Ok(Scan::End);
+        }
+        let header = parse_header(&bytes);
+        if at + Self::record_len(header.len) > F::ERASE_SIZE {
+            return Ok(Scan::Corrupted);
+        }
+
+        let mut crc = Crc16::new();
+        crc.update(&bytes);
+        let mut chunk = [0u8; CHUNK_LEN];
+        let mut position = 0;
+        while position < header.len {
+            let n = min(CHUNK_LEN, header.len - position);
+            self.read_bytes(
+                address + (RECORD_HEADER_LEN + position) as u32,
+                &mut chunk[..n],
+            )?;
+            crc.update(&chunk[..n]);
+            position += n;
+        }
+        let mut stored = [0u8; RECORD_CRC_LEN];
+        self.read_bytes(
+            address + (RECORD_HEADER_LEN + header.len) as u32,
+            &mut stored,
+        )?;
+        if u16::from_le_bytes(stored) != crc.finish() {
+            return Ok(Scan::Corrupted);
+        }
+        Ok(Scan::Record(header))
+    }
+
+    fn read_sector_header(&mut self, sector: usize) -> Result<Option<u32>, Error<F::Error>> {
+        let mut bytes = [0u8; SECTOR_HEADER_LEN];
+        self.read_bytes(self.sector_address(sector), &mut bytes)?;
+        let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let generation = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let crc = u16::from_le_bytes([bytes[8], bytes[9]]);
+        if magic != SECTOR_MAGIC || crc != Crc16::checksum(&bytes[..8]) {
+            return Ok(None);
+        }
+        Ok(Some(generation))
+    }
+
+    fn write_sector_header(
+        &mut self,
+        sector: usize,
+        generation: u32,
+    ) -> Result<(), Error<F::Error>> {
+        let mut bytes = [0u8; SECTOR_HEADER_LEN];
+        bytes[0..4].copy_from_slice(&SECTOR_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&generation.to_le_bytes());
+        let crc = Crc16::checksum(&bytes[..8]);
+        bytes[8..10].copy_from_slice(&crc.to_le_bytes());
+        let mut writer = Writer::new(self.sector_address(sector));
+        writer
+            .push(&mut self.flash, &bytes)
+            .and_then(|_| writer.finish(&mut self.flash, Self::ALIGN))
+            .map_err(|source| Error::FlashError { source })
+    }
+
+    fn erase_sector(&mut self, sector: usize) -> Result<(), Error<F::Error>> {
+        let from = self.sector_address(sector);
+        self.flash
+            .erase(from, from + F::ERASE_SIZE as u32)
+            .map_err(|source| Error::FlashError { source })
+    }
+
+    /// 任意の位置から読む。READ_SIZEに揃えたチャンクごとに読んで切り出す
+    fn read_bytes(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<F::Error>> {
+        let mut chunk = [0u8; CHUNK_LEN];
+        let mut done = 0;
+        while done < buffer.len() {
+            let address = address as usize + done;
+            let skip = address % Self::ALIGN;
+            let n = min(CHUNK_LEN - skip, buffer.len() - done);
+            let len = Self::round_up(skip + n);
+            self.flash
+                .read((address - skip) as u32, &mut chunk[..len])
+                .map_err(|source| Error::FlashError { source })?;
+            buffer[done..(done + n)].copy_from_slice(&chunk[skip..(skip + n)]);
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn sector_address(&self, sector: usize) -> u32 {
+        self.offset + (sector * F::ERASE_SIZE) as u32
+    }
+
+    const fn records_start() -> usize {
+        Self::round_up(SECTOR_HEADER_LEN)
+    }
+
+    const fn record_len(len: usize) -> usize {
+        Self::round_up(RECORD_HEADER_LEN + len + RECORD_CRC_LEN)
+    }
+
+    const fn round_up(len: usize) -> usize {
+        len.div_ceil(Self::ALIGN) * Self::ALIGN
+    }
+}
+
+fn parse_header(bytes: &[u8; RECORD_HEADER_LEN]) -> RecordHeader {
+    RecordHeader {
+        key: u16::from_le_bytes([bytes[0], bytes[1]]),
+        version: bytes[2],
+        kind: bytes[3],
+        len: u16::from_le_bytes([bytes[4], bytes[5]]) as usize,
+    }
+}
+
+fn write_record<F: NorFlash>(
+    flash: &mut F,
+    address: u32,
+    header: &[u8; RECORD_HEADER_LEN],
+    data: impl Iterator<Item = u8>,
+    align: usize,
+) -> Result<(), F::Error> {
+    let mut crc = Crc16::new();
+    crc.update(header);
+    let mut writer = Writer::new(address);
+    writer.push(flash, header)?;
+    for byte in data {
+        crc.update(&[byte]);
+        writer.push(flash, &[byte])?;
+    }
+    writer.push(flash, &crc.finish().to_le_bytes())?;
+    writer.finish(flash, align)
+}
+
+/// 先頭から順にCHUNK_LENずつ書き込む
+struct Writer {
+    address: u32,
+    buffer: [u8; CHUNK_LEN],
+    len: usize,
+}
+
+impl Writer {
+    fn new(address: u32) -> Self {
+        Writer {
+            address,
+            buffer: [0xff; CHUNK_LEN],
+            len: 0,
+        }
+    }
+
+    fn push<F: NorFlash>(&mut self, flash: &mut F, bytes: &[u8]) -> Result<(), F::Error> {
+        for byte in bytes {
+            self.buffer[self.len] = *byte;
+            self.len += 1;
+            if self.len == CHUNK_LEN {
+                flash.write(self.address, &self.buffer)?;
+                self.address += CHUNK_LEN as u32;
+                self.len = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// 残りを0xffで埋めて書き込む
+    fn finish<F: NorFlash>(&mut self, flash: &mut F, align: usize) -> Result<(), F::Error> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        let len = self.len.div_ceil(align) * align;
+        self.buffer[self.len..len].fill(0xff);
+        flash.write(self.address, &self.buffer[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MockFlash;
+
+    type Flash = MockFlash<1024, 256, 4>;
+
+    fn storage() -> Storage<Flash> {
+        Storage::new(Flash::new(), 0, 4).unwrap()
+    }
+
+    #[test]
+    // 書いたものが読める
+    fn test_write_and_read() {
+        let mut storage = storage();
+        storage.write(1, 1, &[1, 2, 3]).unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            Some(Record {
+                version: 1,
+                data: &[1u8, 2, 3][..]
+            }),
+            storage.read(1, &mut buffer).unwrap()
+        );
+        assert_eq!(None, storage.read(2, &mut buffer).unwrap());
+    }
+
+    #[test]
+    // 同じキーは最後に書いたものが読める。削除したら読めない
+    fn test_overwrite_and_remove() {
+        let mut storage = storage();
+        storage.write(1, 1, &[1]).unwrap();
+        storage.write(1, 2, &[2, 2]).unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            Some(Record {
+                version: 2,
+                data: &[2u8, 2][..]
+            }),
+            storage.read(1, &mut buffer).unwrap()
+        );
+        storage.remove(1).unwrap();
+        assert_eq!(None, storage.read(1, &mut buffer).unwrap());
+    }
+
+    #[test]
+    // 読み直しても内容が残っている
+    fn test_remount() {
+        let mut storage = storage();
+        storage.write(1, 1, &[1, 2, 3]).unwrap();
+        storage.write(2, 1, &[4]).unwrap();
+        let mut storage = Storage::new(storage.release(), 0, 4).unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            Some(Record {
+                version: 1,
+                data: &[1u8, 2, 3][..]
+            }),
+            storage.read(1, &mut buffer).unwrap()
+        );
+        assert_eq!(
+            Some(Record {
+                version: 1,
+                data: &[4u8][..]
+            }),
+            storage.read(2, &mut buffer).unwrap()
+        );
+    }
+
+    #[test]
+    // セクタがいっぱいになると次のセクタに移り、すべてのセクタを順番に使う
+    fn test_wear_leveling() {
+        let mut storage = storage();
+        let mut visited = [false; 4];
+        for i in 0..100u8 {
+            storage.write(1, 1, &[i; 16]).unwrap();
+            storage.write(2, 1, &[i]).unwrap();
+            visited[storage.active] = true;
+        }
+        assert_eq!([true; 4], visited);
+        let mut storage = Storage::new(storage.release(), 0, 4).unwrap();
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            Some(Record {
+                version: 1,
+                data: &[99u8; 16][..]
+            }),
+            storage.read(1, &mut buffer).unwrap()
+        );
+        assert_eq!(
+            Some(Record {
+                version: 1,
+                data: &[99u8][..]
+            }),
+            storage.read(2, &mut buffer).unwrap()
+        );
+    }
+
+    #[test]
+    // 最新のレコードだけでもセクタに収まらなければFull
+    fn test_full() {
+        let mut storage = storage();
+        for key in 0..6 {
+            storage.write(key, 1, &[0; 32]).unwrap();
+        }
+        assert!(matches!(storage.write(6, 1, &[0; 32]), Err(Error::Full)));
+        assert!(matches!(storage.write(7, 1, &[0; 512]), Err(Error::Full)));
+    }
+
+    #[test]
+    // 書き込み途中で電源が落ちたら、壊れたレコードは捨ててその前の値に戻る
+    fn test_recover_from_torn_write() {
+        let mut storage = storage();
+        storage.write(1, 1, &[1; 8]).unwrap();
+        let mut flash = storage.release();
+        flash.cut_power_after(8);
+        let mut storage = Storage::new(flash, 0, 4).unwrap();
+        assert!(storage.write(1, 1, &[2; 8]).is_err());
+
+        let mut flash = storage.release();
+        flash.restore_power();
+        let mut storage = Storage::new(flash, 0, 4).unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            Some(Record {
+                version: 1,
+                data: &[1u8; 8][..]
+            }),
+            storage.read(1, &mut buffer).unwrap()
+        );
+        storage.write(1, 1, &[3; 8]).unwrap();
+        assert_eq!(
+            Some(Record {
+                version: 1,
+                data: &[3u8; 8][..]
+            }),
+            storage.read(1, &mut buffer).unwrap()
+        );
+    }
+
+    #[test]
+    // コンパクションの途中で電源が落ちたら元のセクタを使い続ける
+    fn test_recover_from_torn_compaction() {
+        let mut storage = storage();
+        // 1セクタに10個まで入る
+        for i in 0..10u8 {
+            storage.write(1, 1, &[i; 16]).unwrap();
+        }
+        let active = storage.active;
+        let mut flash = storage.release();
+        flash.cut_power_after(16);
+        let mut storage = Storage::new(flash, 0, 4).unwrap();
+        assert!(storage.write(1, 1, &[10; 16]).is_err());
+
+        let mut flash = storage.release();
+        flash.restore_power();
+        let mut storage = Storage::new(flash, 0, 4).unwrap();
+        assert_eq!(active, storage.active);
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            Some(Record {
+                version: 1,
+                data: &[9u8; 16][..]
+            }),
+            storage.read(1, &mut buffer).unwrap()
+        );
+        storage.write(1, 1, &[11; 16]).unwrap();
+        assert_ne!(active, storage.active);
+        assert_eq!(
+            Some(Record {
+                version: 1,
+                data: &[11u8; 16][..]
+            }),
+            storage.read(1, &mut buffer).unwrap()
+        );
+    }
+
+    #[test]
+    // セクタ数が足りない・範囲がはみ出す構成は使えない
+    fn test_invalid_configuration() {
+        assert!(matches!(
+            Storage::new(Flash::new(), 0, 1),
+            Err(Error::InvalidConfiguration)
+        ));
+        assert!(matches!(
+            Storage::new(Flash::new(), 256, 4),
+            Err(Error::InvalidConfiguration)
+        ));
+        assert!(matches!(
+            Storage::new(Flash::new(), 128, 2),
+            Err(Error::InvalidConfiguration)
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+use defmt::Format;
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum MockFlashError {
+    NotAligned,
+    OutOfBounds,
+    /// `cut_power_after`で指定した量を書き込んだ
+    PowerLoss,
+}
+
+impl NorFlashError for MockFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            MockFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            MockFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            MockFlashError::PowerLoss => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// RAM上のNORフラッシュ
+///
+/// 消去すると0xffになり、書き込みではビットを0にすることしかできない。
+/// `cut_power_after`で書き込みの途中で電源が落ちた状態を再現できる。
+pub struct MockFlash<const SIZE: usize, const ERASE_SIZE: usize = 4096, const WRITE_SIZE: usize = 4>
+{
+    data: [u8; SIZE],
+    /// 電源が落ちるまでに書き込めるバイト数
+    write_budget: Option<usize>,
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize>
+    MockFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+{
+    pub const fn new() -> Self {
+        MockFlash {
+            data: [0xff; SIZE],
+            write_budget: None,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// `bytes`バイト書き込んだところで電源が落ちたことにする。以降の書き込みと消去は失敗する
+    pub fn cut_power_after(&mut self, bytes: usize) {
+        self.write_budget = Some(bytes);
+    }
+
+    pub fn restore_power(&mut self) {
+        self.write_budget = None;
+    }
+
+    fn check(&self, offset: u32, len: usize, align: usize) -> Result<(), MockFlashError> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(align) || !len.is_multiple_of(align) {
+            Err(MockFlashError::NotAligned)
+        } else if offset + len > SIZE {
+            Err(MockFlashError::OutOfBounds)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> Default
+    for MockFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> ErrorType
+    for MockFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+{
+    type Error = MockFlashError;
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> ReadNorFlash
+    for MockFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), Self::READ_SIZE)?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..(offset + bytes.len())]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> NorFlash
+    for MockFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+{
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to {
+            return Err(MockFlashError::OutOfBounds);
+        }
+        self.check(from, (to - from) as usize, ERASE_SIZE)?;
+        if self.write_budget == Some(0) {
+            return Err(MockFlashError::PowerLoss);
+        }
+        self.data[(from as usize)..(to as usize)].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), WRITE_SIZE)?;
+        let (len, result) = match self.write_budget {
+            Some(budget) if budget < bytes.len() => {
+                self.write_budget = Some(0);
+                (budget, Err(MockFlashError::PowerLoss))
+            }
+            Some(budget) => {
+                self.write_budget = Some(budget - bytes.len());
+                (bytes.len(), Ok(()))
+            }
+            None => (bytes.len(), Ok(())),
+        };
+        let offset = offset as usize;
+        for (cell, byte) in self.data[offset..(offset + len)].iter_mut().zip(bytes) {
+            *cell &= *byte;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // 書き込みはビットを落とすだけで、消去すると0xffに戻る
+    fn test_nor_semantics() {
+        let mut flash = MockFlash::<64, 32, 4>::new();
+        flash.write(0, &[0x0f, 0xf0, 0xff, 0x00]).unwrap();
+        flash.write(0, &[0xf3, 0xf3, 0xf3, 0xf3]).unwrap();
+        assert_eq!([0x03, 0xf0, 0xf3, 0x00], flash.data()[..4]);
+        flash.erase(0, 32).unwrap();
+        assert_eq!([0xff; 4], flash.data()[..4]);
+        assert_eq!(Err(MockFlashError::NotAligned), flash.write(2, &[0; 4]));
+        assert_eq!(Err(MockFlashError::OutOfBounds), flash.erase(32, 96));
+    }
+
+    #[test]
+    // 電源が落ちると書き込みは途中までになる
+    fn test_power_loss() {
+        let mut flash = MockFlash::<64, 32, 4>::new();
+        flash.cut_power_after(6);
+        flash.write(0, &[0; 4]).unwrap();
+        assert_eq!(Err(MockFlashError::PowerLoss), flash.write(4, &[0; 4]));
+        assert_eq!([0, 0, 0, 0, 0, 0, 0xff, 0xff], flash.data()[..8]);
+        assert_eq!(Err(MockFlashError::PowerLoss), flash.erase(0, 32));
+    }
+}
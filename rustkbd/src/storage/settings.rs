@@ -0,0 +1,343 @@
+use core::cmp::min;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::{
+    keyboard::{DynamicKeymap, HostLayout, Key, UnicodeMode},
+    split::Handedness,
+};
+
+use super::{Error, Storage};
+
+// 0x8000以降のキーはボード側で自由に使える
+const KEY_KEYMAP: u16 = 0x0001;
+const KEY_HANDEDNESS: u16 = 0x0002;
+const KEY_UNICODE_MODE: u16 = 0x0003;
+const KEY_HOST_LAYOUT: u16 = 0x0004;
+
+/// 1はキーを判別値の2バイトで、2はデータを持つキーも戻せる4バイトで保存する
+const KEYMAP_VERSION: u8 = 2;
+/// レイヤ数・行数・列数
+const KEYMAP_HEADER_LEN: usize = 3;
+/// キー1つのバイト数
+const KEY_LEN: usize = 4;
+
+/// `Setting`をエンコードしたときの最大のバイト数
+const MAX_SETTING_LEN: usize = 256;
+
+/// Storageに保存できる設定
+///
+/// 保存したときの`VERSION`が`decode`に渡されるので、古い形式から読み替えられる。
+/// 読めない形式のときは`None`を返せば保存されていないものとして扱われる。
+pub trait Setting: Sized {
+    const KEY: u16;
+    const VERSION: u8;
+
+    /// `buffer`に書き込んで長さを返す。入りきらないときはNone
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize>;
+
+    fn decode(version: u8, data: &[u8]) -> Option<Self>;
+}
+
+impl<F: NorFlash> Storage<F> {
+    pub fn load<S: Setting>(&mut self) -> Result<Option<S>, Error<F::Error>> {
+        let mut buffer = [0u8; MAX_SETTING_LEN];
+        Ok(self
+            .read(S::KEY, &mut buffer)?
+            .and_then(|record| S::decode(record.version, record.data)))
+    }
+
+    pub fn save<S: Setting>(&mut self, setting: &S) -> Result<(), Error<F::Error>> {
+        let mut buffer = [0u8; MAX_SETTING_LEN];
+        let len = setting.encode(&mut buffer).ok_or(Error::TooLarge)?;
+        self.write(S::KEY, S::VERSION, &buffer[..len])
+    }
+
+    /// キーマップ全体をレイヤ・行・列の順に保存する
+    pub fn save_keymap<K: DynamicKeymap>(&mut self, keymap: &K) -> Result<(), Error<F::Error>> {
+        let (layers, rows, cols) = (keymap.layer_count(), keymap.rows(), keymap.cols());
+        let header = keymap_header(keymap).ok_or(Error::TooLarge)?;
+        let keys = (0..layers).flat_map(move |layer| {
+            (0..rows).flat_map(move |row| {
+                (0..cols).flat_map(move |col| {
+                    let key = keymap.get_key(layer, row, col).unwrap_or(Key::None);
+                    <[u8; KEY_LEN]>::from(key)
+                })
+            })
+        });
+        let len = KEYMAP_HEADER_LEN + layers * rows * cols * KEY_LEN;
+        self.append(
+            KEY_KEYMAP,
+            KEYMAP_VERSION,
+            0x00,
+            len,
+            header.into_iter().chain(keys),
+        )
+    }
+
+    /// 保存されたキーマップを`keymap`に書き込む。
+    /// 保存されていないか、大きさが違うときはfalseを返して何もしない
+    pub fn load_keymap<K: DynamicKeymap>(
+        &mut self,
+        keymap: &mut K,
+    ) -> Result<bool, Error<F::Error>> {
+        let Some((at, header)) = self.find(KEY_KEYMAP)? else {
+            return Ok(false);
+        };
+        let mut size = [0u8; KEYMAP_HEADER_LEN];
+        self.read_data(at, 0, &mut size)?;
+        let (layers, rows, cols) = (keymap.layer_count(), keymap.rows(), keymap.cols());
+        let key_len = match header.version {
+            1 => 2,
+            KEYMAP_VERSION => KEY_LEN,
+            _ => return Ok(false),
+        };
+        if Some(size) != keymap_header(keymap)
+            || header.len != KEYMAP_HEADER_LEN + layers * rows * cols * key_len
+        {
+            return Ok(false);
+        }
+
+        let mut chunk = [0u8; 32];
+        let count = layers * rows * cols;
+        let mut index = 0;
+        while index < count {
+            let n = min(chunk.len() / key_len, count - index);
+            let bytes = &mut chunk[..(n * key_len)];
+            self.read_data(at, KEYMAP_HEADER_LEN + index * key_len, bytes)?;
+            for (i, bytes) in bytes.chunks_exact(key_len).enumerate() {
+                let i = index + i;
+                let (layer, row, col) = (i / (rows * cols), i / cols % rows, i % cols);
+                let key = match *bytes {
+                    [a, b] => Key::try_from(u16::from_le_bytes([a, b])).ok(),
+                    [a, b, c, d] => Key::try_from([a, b, c, d]).ok(),
+                    _ => None,
+                };
+                if let Some(key) = key {
+                    keymap.set_key(layer, row, col, key);
+                }
+            }
+            index += n;
+        }
+        Ok(true)
+    }
+}
+
+/// レイヤ数・行数・列数。1バイトに収まらないときはNone
+fn keymap_header<K: DynamicKeymap>(keymap: &K) -> Option<[u8; KEYMAP_HEADER_LEN]> {
+    Some([
+        u8::try_from(keymap.layer_count()).ok()?,
+        u8::try_from(keymap.rows()).ok()?,
+        u8::try_from(keymap.cols()).ok()?,
+    ])
+}
+
+impl Setting for Handedness {
+    const KEY: u16 = KEY_HANDEDNESS;
+    const VERSION: u8 = 1;
+
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        *buffer.first_mut()? = match self {
+            Handedness::Left => 0,
+            Handedness::Right => 1,
+        };
+        Some(1)
+    }
+
+    fn decode(version: u8, data: &[u8]) -> Option<Self> {
+        match (version, data) {
+            (1, [0]) => Some(Handedness::Left),
+            (1, [1]) => Some(Handedness::Right),
+            _ => None,
+        }
+    }
+}
+
+impl Setting for UnicodeMode {
+    const KEY: u16 = KEY_UNICODE_MODE;
+    const VERSION: u8 = 1;
+
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        *buffer.first_mut()? = match self {
+            UnicodeMode::Linux => 0,
+            UnicodeMode::MacOs => 1,
+            UnicodeMode::WinCompose => 2,
+            UnicodeMode::WindowsAlt => 3,
+        };
+        Some(1)
+    }
+
+    fn decode(version: u8, data: &[u8]) -> Option<Self> {
+        match (version, data) {
+            (1, [0]) => Some(UnicodeMode::Linux),
+            (1, [1]) => Some(UnicodeMode::MacOs),
+            (1, [2]) => Some(UnicodeMode::WinCompose),
+            (1, [3]) => Some(UnicodeMode::WindowsAlt),
+            _ => None,
+        }
+    }
+}
+
+impl Setting for HostLayout {
+    const KEY: u16 = KEY_HOST_LAYOUT;
+    const VERSION: u8 = 1;
+
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        *buffer.first_mut()? = *self as u8;
+        Some(1)
+    }
+
+    fn decode(version: u8, data: &[u8]) -> Option<Self> {
+        match (version, data) {
+            (1, [0]) => Some(HostLayout::Us),
+            (1, [1]) => Some(HostLayout::Jis),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MockFlash;
+
+    type Flash = MockFlash<4096, 1024, 4>;
+
+    struct Keymap {
+        keys: [[[Key; 3]; 2]; 2],
+    }
+
+    impl DynamicKeymap for Keymap {
+        fn layer_count(&self) -> usize {
+            2
+        }
+
+        fn rows(&self) -> usize {
+            2
+        }
+
+        fn cols(&self) -> usize {
+            3
+        }
+
+        fn get_key(&self, layer: usize, row: usize, col: usize) -> Option<Key> {
+            self.keys.get(layer)?.get(row)?.get(col).copied()
+        }
+
+        fn set_key(&mut self, layer: usize, row: usize, col: usize, key: Key) -> bool {
+            self.keys[layer][row][col] = key;
+            true
+        }
+
+        fn reset(&mut self) {
+            self.keys = [[[Key::None; 3]; 2]; 2];
+        }
+    }
+
+    #[test]
+    // 設定を保存して読み出せる
+    fn test_save_and_load() {
+        let mut storage = Storage::new(Flash::new(), 0, 4).unwrap();
+        assert_eq!(None, storage.load::<Handedness>().unwrap());
+        storage.save(&Handedness::Right).unwrap();
+        storage.save(&UnicodeMode::MacOs).unwrap();
+        storage.save(&HostLayout::Jis).unwrap();
+
+        let mut storage = Storage::new(storage.release(), 0, 4).unwrap();
+        assert_eq!(Some(Handedness::Right), storage.load().unwrap());
+        assert_eq!(Some(UnicodeMode::MacOs), storage.load().unwrap());
+        assert_eq!(Some(HostLayout::Jis), storage.load().unwrap());
+    }
+
+    #[test]
+    // データを持つキーも含めてキーマップを保存して読み出せる。大きさが違うときは読まない
+    fn test_save_and_load_keymap() {
+        let mut storage = Storage::new(Flash::new(), 0, 4).unwrap();
+        let mut keymap = Keymap {
+            keys: [
+                [[Key::A, Key::B, Key::C], [Key::D, Key::E, Key::F]],
+                [
+                    [Key::Transparent, Key::Asterisk, Key::MediaMute],
+                    [
+                        Key::Unicode('あ'),
+                        Key::WithModifiers(0x01, 0x2b),
+                        Key::LayerTap(1, 0x2c),
+                    ],
+                ],
+            ],
+        };
+        storage.save_keymap(&keymap).unwrap();
+        let saved = keymap.keys;
+        keymap.reset();
+        assert!(storage.load_keymap(&mut keymap).unwrap());
+        assert_eq!(saved, keymap.keys);
+
+        storage
+            .write(KEY_KEYMAP, KEYMAP_VERSION, &[1, 1, 1, 0, 0x04, 0x00, 0])
+            .unwrap();
+        assert!(!storage.load_keymap(&mut keymap).unwrap());
+
+        // 判別値だけで保存していた形式も読める
+        let mut data = [0u8; KEYMAP_HEADER_LEN + 12 * 2];
+        data[..KEYMAP_HEADER_LEN].copy_from_slice(&[2, 2, 3]);
+        data[KEYMAP_HEADER_LEN..KEYMAP_HEADER_LEN + 2].copy_from_slice(&[0x05, 0x00]);
+        storage.write(KEY_KEYMAP, 1, &data).unwrap();
+        assert!(storage.load_keymap(&mut keymap).unwrap());
+        assert_eq!(Key::B, keymap.keys[0][0][0]);
+    }
+
+    #[test]
+    // レコードの形式に収まらない設定やキーマップは、書き込まずにエラーにする
+    fn test_too_large() {
+        struct Large;
+
+        impl Setting for Large {
+            const KEY: u16 = 0x8000;
+            const VERSION: u8 = 1;
+
+            fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+                buffer.get_mut(..=MAX_SETTING_LEN)?.fill(0);
+                Some(MAX_SETTING_LEN + 1)
+            }
+
+            fn decode(_version: u8, _data: &[u8]) -> Option<Self> {
+                Some(Large)
+            }
+        }
+
+        struct ManyLayers;
+
+        impl DynamicKeymap for ManyLayers {
+            fn layer_count(&self) -> usize {
+                256
+            }
+
+            fn rows(&self) -> usize {
+                1
+            }
+
+            fn cols(&self) -> usize {
+                1
+            }
+
+            fn get_key(&self, _layer: usize, _row: usize, _col: usize) -> Option<Key> {
+                Some(Key::A)
+            }
+
+            fn set_key(&mut self, _layer: usize, _row: usize, _col: usize, _key: Key) -> bool {
+                true
+            }
+
+            fn reset(&mut self) {}
+        }
+
+        let mut storage = Storage::new(Flash::new(), 0, 4).unwrap();
+        assert!(matches!(storage.save(&Large), Err(Error::TooLarge)));
+        assert!(matches!(
+            storage.save_keymap(&ManyLayers),
+            Err(Error::TooLarge)
+        ));
+        assert!(!storage.load_keymap(&mut ManyLayers).unwrap());
+        assert!(storage.load::<Large>().unwrap().is_none());
+    }
+}
@@ -1,6 +1,11 @@
 mod device_info;
 mod hid_report;
+mod raw_hid;
 mod usb_communicator;
+mod via;
 
 pub use device_info::DeviceInfo;
-pub use usb_communicator::UsbCommunicator;
+pub use hid_report::HidKeyboardReport;
+pub use raw_hid::{RawHidDispatcher, RawHidHandler, RAW_HID_REPORT_LEN, RAW_HID_UNHANDLED};
+pub use usb_communicator::{keyboard_report, media_report, UsbCommunicator};
+pub use via::Via;
@@ -12,6 +12,9 @@ use usbd_hid_macros::gen_hid_descriptor;
         (usage_min = 0x00, usage_max = 0xff) = {
             #[item_settings constant,array,absolute] reserved=input;
         };
+        (usage_page = LEDS, usage_min = 0x01, usage_max = 0x05) = {
+            #[packed_bits 5] #[item_settings data,variable,absolute] leds=output;
+        };
         (usage_page = KEYBOARD, usage_min = 0x00, usage_max = 0xff) = {
             #[item_settings data,array,absolute] key_codes=input;
         };
@@ -21,6 +24,8 @@ use usbd_hid_macros::gen_hid_descriptor;
 pub struct HidKeyboardReport {
     pub modifier: u8,
     pub reserved: u8,
+    /// ホストから届くLEDの出力レポート。入力レポートには含まれない
+    pub leds: u8,
     pub key_codes: [u8; 6],
 }
 
@@ -29,6 +34,7 @@ impl HidKeyboardReport {
         HidKeyboardReport {
             modifier: 0,
             reserved: 0,
+            leds: 0,
             key_codes: [0; 6],
         }
     }
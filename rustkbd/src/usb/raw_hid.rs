@@ -0,0 +1,152 @@
+use crate::Vec;
+
+/// Raw HIDのレポート長（QMKのRaw HIDと同じ）
+pub const RAW_HID_REPORT_LEN: usize = 32;
+
+/// どのハンドラも処理しなかったコマンドへの応答に入るコマンドID
+pub const RAW_HID_UNHANDLED: u8 = 0xff;
+
+/**
+ * Vendor-defined (usage page 0xFF60, usage 0x61) の入出力32バイトずつのレポート
+ * cf. https://github.com/qmk/qmk_firmware/blob/master/tmk_core/protocol/usb_descriptor.c
+ */
+#[rustfmt::skip]
+pub(crate) const RAW_HID_REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0x60, 0xff, // Usage Page (Vendor Defined 0xFF60)
+    0x09, 0x61, // Usage (0x61)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x62, //   Usage (0x62)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x95, RAW_HID_REPORT_LEN as u8, //   Report Count
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x09, 0x63, //   Usage (0x63)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x95, RAW_HID_REPORT_LEN as u8, //   Report Count
+    0x75, 0x08, //   Report Size (8)
+    0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0xc0, // End Collection
+];
+
+/// Raw HIDのコマンドを処理するハンドラ
+///
+/// レポートの先頭1バイトがコマンドID、残りがペイロード。
+/// 応答は受け取ったペイロードを上書きして作る。
+pub trait RawHidHandler {
+    /// コマンドを処理したらtrueを返す。falseなら次のハンドラに回される
+    fn handle(&mut self, command_id: u8, payload: &mut [u8]) -> bool;
+}
+
+/// 登録されたハンドラに順番にコマンドを渡す
+pub struct RawHidDispatcher<'a, const N: usize> {
+    handlers: Vec<&'a mut dyn RawHidHandler, N>,
+}
+
+impl<'a, const N: usize> RawHidDispatcher<'a, N> {
+    pub fn new() -> Self {
+        RawHidDispatcher {
+            handlers: Vec::new(),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        handler: &'a mut dyn RawHidHandler,
+    ) -> Result<(), &'a mut dyn RawHidHandler> {
+        self.handlers.push(handler)
+    }
+
+    /// レポートをその場で応答に書き換える。どのハンドラも処理しなかった場合はfalse
+    pub fn dispatch(&mut self, report: &mut [u8; RAW_HID_REPORT_LEN]) -> bool {
+        let (command_id, payload) = report.split_first_mut().unwrap();
+        let handled = self
+            .handlers
+            .iter_mut()
+            .any(|handler| handler.handle(*command_id, payload));
+        if !handled {
+            *command_id = RAW_HID_UNHANDLED;
+        }
+        handled
+    }
+}
+
+impl<'a, const N: usize> Default for RawHidDispatcher<'a, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo {
+        command_id: u8,
+        count: usize,
+    }
+
+    impl RawHidHandler for Echo {
+        fn handle(&mut self, command_id: u8, payload: &mut [u8]) -> bool {
+            if command_id != self.command_id {
+                return false;
+            }
+            self.count += 1;
+            payload[0] = payload[0].wrapping_add(1);
+            true
+        }
+    }
+
+    #[test]
+    // コマンドIDが一致するハンドラだけが呼ばれる
+    fn test_dispatch_to_matching_handler() {
+        let mut first = Echo {
+            command_id: 0x01,
+            count: 0,
+        };
+        let mut second = Echo {
+            command_id: 0x02,
+            count: 0,
+        };
+        let mut report = [0u8; RAW_HID_REPORT_LEN];
+        report[0] = 0x02;
+        report[1] = 0x10;
+        {
+            let mut dispatcher = RawHidDispatcher::<2>::new();
+            dispatcher.register(&mut first).ok().unwrap();
+            dispatcher.register(&mut second).ok().unwrap();
+            assert!(dispatcher.dispatch(&mut report));
+        }
+        assert_eq!(0, first.count);
+        assert_eq!(1, second.count);
+        assert_eq!([0x02, 0x11], report[..2]);
+    }
+
+    #[test]
+    // どのハンドラも処理しなければコマンドIDが0xffになり、ペイロードはそのまま返る
+    fn test_dispatch_unhandled() {
+        let mut report = [0u8; RAW_HID_REPORT_LEN];
+        report[0] = 0x42;
+        report[1] = 0x10;
+        let mut dispatcher = RawHidDispatcher::<0>::new();
+        assert!(!dispatcher.dispatch(&mut report));
+        assert_eq!([RAW_HID_UNHANDLED, 0x10], report[..2]);
+    }
+
+    #[test]
+    // 登録数を超えたハンドラは返される
+    fn test_register_overflow() {
+        let mut first = Echo {
+            command_id: 0x01,
+            count: 0,
+        };
+        let mut second = Echo {
+            command_id: 0x02,
+            count: 0,
+        };
+        let mut dispatcher = RawHidDispatcher::<1>::new();
+        assert!(dispatcher.register(&mut first).is_ok());
+        assert!(dispatcher.register(&mut second).is_err());
+    }
+}
@@ -1,5 +1,6 @@
+use heapless::{String, Vec};
 use usb_device::{
-    class_prelude::{UsbBus, UsbBusAllocator},
+    class_prelude::{UsbBus, UsbBusAllocator, UsbClass},
     device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
     LangID, UsbError,
 };
@@ -7,15 +8,28 @@ use usbd_hid::{
     descriptor::{MediaKeyboardReport, SerializedDescriptor},
     hid_class::HIDClass,
 };
+use usbd_serial::SerialPort;
 
-use crate::keyboard::{ExternalCommunicator, Key};
+use crate::{
+    console::{Edit, LineBuffer, CONSOLE_LINE_LEN},
+    keyboard::{ExternalCommunicator, HostLayout, HostLeds, Key},
+};
 
-use super::{hid_report::HidKeyboardReport, DeviceInfo};
+use super::{
+    hid_report::HidKeyboardReport,
+    raw_hid::{RAW_HID_REPORT_DESCRIPTOR, RAW_HID_REPORT_LEN},
+    DeviceInfo, RawHidDispatcher,
+};
 
 pub struct UsbCommunicator<'a, B: UsbBus> {
     usb_device: UsbDevice<'a, B>,
     keyboard_usb_hid: HIDClass<'a, B>,
     media_usb_hid: HIDClass<'a, B>,
+    raw_usb_hid: HIDClass<'a, B>,
+    console: Option<SerialPort<'a, B>>,
+    console_line: LineBuffer,
+    /// ホストから最後に届いたLEDの出力レポート
+    host_leds: HostLeds,
 }
 
 impl<'a, B: UsbBus> UsbCommunicator<'a, B> {
@@ -24,32 +38,126 @@ impl<'a, B: UsbBus> UsbCommunicator<'a, B> {
     pub fn new(
         device_info: DeviceInfo,
         usb_bus_alloc: &'a UsbBusAllocator<B>,
+    ) -> UsbCommunicator<'a, B> {
+        Self::build(device_info, usb_bus_alloc, false)
+    }
+
+    /// CDC-ACMのシリアルコンソールも持たせる
+    pub fn with_console(
+        device_info: DeviceInfo,
+        usb_bus_alloc: &'a UsbBusAllocator<B>,
+    ) -> UsbCommunicator<'a, B> {
+        Self::build(device_info, usb_bus_alloc, true)
+    }
+
+    fn build(
+        device_info: DeviceInfo,
+        usb_bus_alloc: &'a UsbBusAllocator<B>,
+        console: bool,
     ) -> UsbCommunicator<'a, B> {
         let keyboard_usb_hid = HIDClass::new(usb_bus_alloc, HidKeyboardReport::desc(), 10);
         let media_usb_hid = HIDClass::new(usb_bus_alloc, MediaKeyboardReport::desc(), 10);
+        let raw_usb_hid = HIDClass::new(usb_bus_alloc, RAW_HID_REPORT_DESCRIPTOR, 1);
+        let console = console.then(|| SerialPort::new(usb_bus_alloc));
         let descriptors = StringDescriptors::new(LangID::EN_US)
             .manufacturer(device_info.manufacturer)
             .serial_number(device_info.serial_number)
             .product(device_info.product_name);
-        let usb_device = UsbDeviceBuilder::new(
+        let builder = UsbDeviceBuilder::new(
             usb_bus_alloc,
             UsbVidPid(device_info.vendor_id, device_info.product_id),
         )
         .strings(&[descriptors])
-        .expect("Failed to create string descriptors")
-        .device_class(0)
-        .build();
+        .expect("Failed to create string descriptors");
+        // CDC-ACMは2つのインターフェースを使うのでIADが要る
+        let usb_device = if console.is_some() {
+            builder.composite_with_iads().build()
+        } else {
+            builder.device_class(0).build()
+        };
 
         UsbCommunicator {
             usb_device,
             keyboard_usb_hid,
             media_usb_hid,
+            raw_usb_hid,
+            console,
+            console_line: LineBuffer::new(),
+            host_leds: HostLeds::empty(),
         }
     }
 
     pub fn poll(&mut self) {
-        self.usb_device
-            .poll(&mut [&mut self.keyboard_usb_hid, &mut self.media_usb_hid]);
+        let mut classes: Vec<&mut dyn UsbClass<B>, 4> = Vec::new();
+        classes.push(&mut self.keyboard_usb_hid).ok();
+        classes.push(&mut self.media_usb_hid).ok();
+        classes.push(&mut self.raw_usb_hid).ok();
+        if let Some(console) = self.console.as_mut() {
+            classes.push(console).ok();
+        }
+        self.usb_device.poll(&mut classes);
+        drop(classes);
+
+        let mut leds = [0u8; 1];
+        if let Ok(1) = self.keyboard_usb_hid.pull_raw_output(&mut leds) {
+            self.host_leds = HostLeds::from_bits(leds[0]);
+        }
+    }
+
+    /// コンソールに届いた文字をエコーバックしながら溜め、1行揃ったら返す
+    pub fn read_console_line(&mut self) -> Option<String<CONSOLE_LINE_LEN>> {
+        let console = self.console.as_mut()?;
+        let mut byte = [0u8; 1];
+        while let Ok(1) = console.read(&mut byte) {
+            let echo: &[u8] = match self.console_line.push(byte[0]) {
+                Edit::Line(line) => {
+                    console.write(b"\r\n").ok();
+                    return Some(line);
+                }
+                Edit::Erase => b"\x08 \x08",
+                Edit::Echo(_) => &byte,
+                Edit::None => continue,
+            };
+            console.write(echo).ok();
+        }
+        None
+    }
+
+    /// コンソールに書き出す。送信バッファに入りきらない分は捨てる
+    pub fn write_console(&mut self, text: &str) -> Result<(), UsbError> {
+        let Some(console) = self.console.as_mut() else {
+            return Ok(());
+        };
+        for (i, line) in text.split('\n').enumerate() {
+            let newline: &[u8] = if i == 0 { b"" } else { b"\r\n" };
+            for mut bytes in [newline, line.as_bytes()] {
+                while !bytes.is_empty() {
+                    match console.write(bytes) {
+                        Ok(n) => bytes = &bytes[n..],
+                        Err(UsbError::WouldBlock) => return Ok(()),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// ホストからRaw HIDのレポートが届いていれば、dispatcherで処理して応答を返す
+    pub fn handle_raw_hid<const N: usize>(
+        &self,
+        dispatcher: &mut RawHidDispatcher<'_, N>,
+    ) -> Result<(), UsbError> {
+        let mut report = [0u8; RAW_HID_REPORT_LEN];
+        match self.raw_usb_hid.pull_raw_output(&mut report) {
+            Ok(_) => {
+                dispatcher.dispatch(&mut report);
+                self.raw_usb_hid.push_raw_input(&report)?;
+                Ok(())
+            }
+            Err(UsbError::WouldBlock) => Ok(()),
+            Err(e) => Err(e),
+        }
     }
 
     pub fn state(&self) -> UsbDeviceState {
@@ -64,8 +172,8 @@ impl<'a, B: UsbBus> ExternalCommunicator for UsbCommunicator<'a, B> {
         self.usb_device.state() == UsbDeviceState::Configured
     }
 
-    fn send_keys(&self, keys: &[Key]) -> Result<(), UsbError> {
-        let keyboard_report = keyboard_report(keys, Self::NUM_ROLLOVER);
+    fn send_keys(&self, keys: &[Key], layout: HostLayout) -> Result<(), UsbError> {
+        let keyboard_report = keyboard_report(keys, Self::NUM_ROLLOVER, layout);
         let media_key = keys.iter().find(|key| key.is_media_key());
         let media_keyboard_report = media_report(media_key);
 
@@ -73,23 +181,29 @@ impl<'a, B: UsbBus> ExternalCommunicator for UsbCommunicator<'a, B> {
         self.media_usb_hid.push_input(&media_keyboard_report)?;
         Ok(())
     }
+
+    fn host_leds(&self) -> HostLeds {
+        self.host_leds
+    }
 }
 
-fn keyboard_report(keys: &[Key], rollover: usize) -> HidKeyboardReport {
+/// 押されているキーからキーボードのレポートを作る
+pub fn keyboard_report(keys: &[Key], rollover: usize, layout: HostLayout) -> HidKeyboardReport {
     let mut report = HidKeyboardReport::empty();
     report.modifier = keys
         .iter()
-        .map(|key| key.modifier_key_flag())
+        .map(|key| key.resolve(layout).0)
         .fold(0x00_u8, |acc, flg| acc | flg);
     keys.iter()
-        .filter_map(|key| key.key_code())
+        .filter_map(|key| key.resolve(layout).1)
         .take(rollover)
         .enumerate()
         .for_each(|(i, c)| report.key_codes[i] = c);
     report
 }
 
-fn media_report(key: Option<&Key>) -> MediaKeyboardReport {
+/// 押されているメディアキーからメディアキーのレポートを作る
+pub fn media_report(key: Option<&Key>) -> MediaKeyboardReport {
     MediaKeyboardReport {
         usage_id: key.map(|key| key.media_usage_id()).unwrap_or(0),
     }
@@ -0,0 +1,271 @@
+use crate::keyboard::{DynamicKeymap, Key};
+
+use super::RawHidHandler;
+
+/**
+ * VIAのRaw HIDプロトコル
+ * cf. https://github.com/qmk/qmk_firmware/blob/master/quantum/via.h
+ */
+const VIA_PROTOCOL_VERSION: u16 = 0x000c;
+
+const ID_GET_PROTOCOL_VERSION: u8 = 0x01;
+const ID_GET_KEYBOARD_VALUE: u8 = 0x02;
+const ID_SET_KEYBOARD_VALUE: u8 = 0x03;
+const ID_DYNAMIC_KEYMAP_GET_KEYCODE: u8 = 0x04;
+const ID_DYNAMIC_KEYMAP_SET_KEYCODE: u8 = 0x05;
+const ID_DYNAMIC_KEYMAP_RESET: u8 = 0x06;
+const ID_EEPROM_RESET: u8 = 0x0a;
+const ID_DYNAMIC_KEYMAP_MACRO_GET_COUNT: u8 = 0x0c;
+const ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE: u8 = 0x0d;
+const ID_DYNAMIC_KEYMAP_MACRO_RESET: u8 = 0x10;
+const ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT: u8 = 0x11;
+const ID_DYNAMIC_KEYMAP_GET_BUFFER: u8 = 0x12;
+const ID_DYNAMIC_KEYMAP_SET_BUFFER: u8 = 0x13;
+
+const ID_UPTIME: u8 = 0x01;
+const ID_LAYOUT_OPTIONS: u8 = 0x02;
+const ID_FIRMWARE_VERSION: u8 = 0x04;
+
+/// 一度に読み書きできるキーマップバッファのバイト数
+const BUFFER_CHUNK_LEN: usize = 28;
+
+/// VIAからキーマップを読み書きするRaw HIDハンドラ
+///
+/// キーコードはQMKのものに変換してやり取りする。Keyで表せないキーコードは書き込まれず、
+/// QMKのキーコードで表せないキーは`KC_NO`として読み出される。
+pub struct Via<'a, K: DynamicKeymap> {
+    keymap: &'a mut K,
+    edited: bool,
+}
+
+impl<'a, K: DynamicKeymap> Via<'a, K> {
+    pub fn new(keymap: &'a mut K) -> Self {
+        Via {
+            keymap,
+            edited: false,
+        }
+    }
+
+    /// キーマップを書き換えたか。trueならキーマップを保存する
+    pub fn edited(&self) -> bool {
+        self.edited
+    }
+
+    fn keycode(&self, layer: usize, row: usize, col: usize) -> u16 {
+        self.keymap
+            .get_key(layer, row, col)
+            .and_then(|key| key.to_qmk_keycode())
+            .unwrap_or(0x0000)
+    }
+
+    fn set_keycode(&mut self, layer: usize, row: usize, col: usize, keycode: u16) {
+        if let Some(key) = Key::from_qmk_keycode(keycode) {
+            self.edited |= self.keymap.set_key(layer, row, col, key);
+        } else {
+            defmt::warn!("Unsupported keycode: {=u16:#06x}", keycode);
+        }
+    }
+
+    /// キーマップバッファ（レイヤ・行・列の順に2バイトずつ）の位置からキーの位置に変換する
+    fn buffer_position(&self, offset: usize) -> (usize, usize, usize) {
+        let index = offset / 2;
+        let cols = self.keymap.cols();
+        let rows = self.keymap.rows();
+        (index / (rows * cols), index / cols % rows, index % cols)
+    }
+
+    fn buffer_len(&self) -> usize {
+        self.keymap.layer_count() * self.keymap.rows() * self.keymap.cols() * 2
+    }
+
+    fn get_buffer(&self, offset: usize, data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let offset = offset + i;
+            if offset >= self.buffer_len() {
+                break;
+            }
+            let (layer, row, col) = self.buffer_position(offset);
+            let keycode = self.keycode(layer, row, col).to_be_bytes();
+            *byte = keycode[offset % 2];
+        }
+    }
+
+    /// 上位バイトだけで終わる書き込みは反映しない
+    fn set_buffer(&mut self, offset: usize, data: &[u8]) {
+        let mut high = None;
+        for (i, byte) in data.iter().enumerate() {
+            let offset = offset + i;
+            if offset >= self.buffer_len() {
+                break;
+            }
+            if offset.is_multiple_of(2) {
+                high = Some(*byte);
+                continue;
+            }
+            let (layer, row, col) = self.buffer_position(offset);
+            let high = high
+                .take()
+                .unwrap_or_else(|| self.keycode(layer, row, col).to_be_bytes()[0]);
+            self.set_keycode(layer, row, col, u16::from_be_bytes([high, *byte]));
+        }
+    }
+}
+
+impl<'a, K: DynamicKeymap> RawHidHandler for Via<'a, K> {
+    fn handle(&mut self, command_id: u8, payload: &mut [u8]) -> bool {
+        match command_id {
+            ID_GET_PROTOCOL_VERSION => {
+                payload[..2].copy_from_slice(&VIA_PROTOCOL_VERSION.to_be_bytes());
+            }
+            ID_GET_KEYBOARD_VALUE => match payload[0] {
+                ID_UPTIME | ID_LAYOUT_OPTIONS | ID_FIRMWARE_VERSION => {
+                    payload[1..5].fill(0);
+                }
+                _ => return false,
+            },
+            ID_SET_KEYBOARD_VALUE => match payload[0] {
+                ID_LAYOUT_OPTIONS => {}
+                _ => return false,
+            },
+            ID_DYNAMIC_KEYMAP_GET_KEYCODE => {
+                let (layer, row, col) = (payload[0], payload[1], payload[2]);
+                let keycode = self.keycode(layer as usize, row as usize, col as usize);
+                payload[3..5].copy_from_slice(&keycode.to_be_bytes());
+            }
+            ID_DYNAMIC_KEYMAP_SET_KEYCODE => {
+                let (layer, row, col) = (payload[0], payload[1], payload[2]);
+                let keycode = u16::from_be_bytes([payload[3], payload[4]]);
+                self.set_keycode(layer as usize, row as usize, col as usize, keycode);
+            }
+            ID_DYNAMIC_KEYMAP_RESET | ID_EEPROM_RESET => {
+                self.keymap.reset();
+                self.edited = true;
+            }
+            ID_DYNAMIC_KEYMAP_MACRO_GET_COUNT => {
+                payload[0] = 0;
+            }
+            ID_DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE => {
+                payload[..2].fill(0);
+            }
+            ID_DYNAMIC_KEYMAP_MACRO_RESET => {}
+            ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT => {
+                payload[0] = self.keymap.layer_count() as u8;
+            }
+            ID_DYNAMIC_KEYMAP_GET_BUFFER => {
+                let offset = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+                let size = (payload[2] as usize).min(BUFFER_CHUNK_LEN);
+                self.get_buffer(offset, &mut payload[3..(3 + size)]);
+            }
+            ID_DYNAMIC_KEYMAP_SET_BUFFER => {
+                let offset = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+                let size = (payload[2] as usize).min(BUFFER_CHUNK_LEN);
+                self.set_buffer(offset, &payload[3..(3 + size)]);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Keymap {
+        keys: [[[Key; 3]; 2]; 2],
+    }
+
+    impl DynamicKeymap for Keymap {
+        fn layer_count(&self) -> usize {
+            2
+        }
+
+        fn rows(&self) -> usize {
+            2
+        }
+
+        fn cols(&self) -> usize {
+            3
+        }
+
+        fn get_key(&self, layer: usize, row: usize, col: usize) -> Option<Key> {
+            self.keys.get(layer)?.get(row)?.get(col).copied()
+        }
+
+        fn set_key(&mut self, layer: usize, row: usize, col: usize, key: Key) -> bool {
+            self.keys[layer][row][col] = key;
+            true
+        }
+
+        fn reset(&mut self) {
+            self.keys = [[[Key::None; 3]; 2]; 2];
+        }
+    }
+
+    fn keymap() -> Keymap {
+        Keymap {
+            keys: [
+                [[Key::A, Key::B, Key::C], [Key::D, Key::E, Key::F]],
+                [
+                    [Key::Transparent, Key::Asterisk, Key::MediaMute],
+                    [Key::None; 3],
+                ],
+            ],
+        }
+    }
+
+    #[test]
+    // レイヤ・行・列を指定してキーコードを読み書きする
+    fn test_get_and_set_keycode() {
+        let mut keymap = keymap();
+        let mut via = Via::new(&mut keymap);
+        let mut payload = [0u8; 31];
+        payload[..3].copy_from_slice(&[1, 0, 1]);
+        assert!(via.handle(ID_DYNAMIC_KEYMAP_GET_KEYCODE, &mut payload));
+        assert_eq!([0x02, 0x25], payload[3..5]);
+        assert!(!via.edited());
+
+        payload[..5].copy_from_slice(&[0, 1, 2, 0x00, 0x29]);
+        assert!(via.handle(ID_DYNAMIC_KEYMAP_SET_KEYCODE, &mut payload));
+        assert!(via.edited());
+        assert_eq!(Key::Escape, keymap.keys[0][1][2]);
+    }
+
+    #[test]
+    // リセットもキーマップの書き換えになる
+    fn test_reset() {
+        let mut keymap = keymap();
+        let mut via = Via::new(&mut keymap);
+        let mut payload = [0u8; 31];
+        assert!(via.handle(ID_DYNAMIC_KEYMAP_RESET, &mut payload));
+        assert!(via.edited());
+        assert_eq!(Key::None, keymap.keys[0][0][0]);
+    }
+
+    #[test]
+    // キーマップバッファはレイヤ・行・列の順にビッグエンディアンで並ぶ
+    fn test_get_and_set_buffer() {
+        let mut keymap = keymap();
+        let mut via = Via::new(&mut keymap);
+        let mut payload = [0u8; 31];
+        payload[..3].copy_from_slice(&[0x00, 0x0c, 6]);
+        assert!(via.handle(ID_DYNAMIC_KEYMAP_GET_BUFFER, &mut payload));
+        assert_eq!([0x00, 0x01, 0x02, 0x25, 0x00, 0xa8], payload[3..9]);
+
+        payload[..7].copy_from_slice(&[0x00, 0x02, 4, 0x00, 0x1e, 0x02, 0x1e]);
+        assert!(via.handle(ID_DYNAMIC_KEYMAP_SET_BUFFER, &mut payload));
+        assert_eq!(Key::Digit1_Exclamation, keymap.keys[0][0][1]);
+        assert_eq!(Key::Exclamation, keymap.keys[0][0][2]);
+    }
+
+    #[test]
+    // 知らないコマンドは処理しない
+    fn test_unknown_command() {
+        let mut keymap = keymap();
+        let mut via = Via::new(&mut keymap);
+        let mut payload = [0u8; 31];
+        assert!(!via.handle(0x42, &mut payload));
+        assert!(via.handle(ID_DYNAMIC_KEYMAP_GET_LAYER_COUNT, &mut payload));
+        assert_eq!(2, payload[0]);
+    }
+}
@@ -0,0 +1,58 @@
+use rustkbd::keyboard::{InvalidSwitchIdentifier, KeySwitchIdentifier};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, KeySwitchIdentifier)]
+struct Switch {
+    row: u8,
+    col: i16,
+    pressed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, KeySwitchIdentifier)]
+enum SideSwitch {
+    Left(u8, u8),
+    Right { index: u16 },
+    Encoder,
+}
+
+#[test]
+// 構造体はフィールドを宣言順にリトルエンディアンで並べ、元に戻せる
+fn test_struct_round_trip() {
+    let switch = Switch {
+        row: 3,
+        col: -2,
+        pressed: true,
+    };
+    let bytes: [u8; 4] = switch.into();
+    assert_eq!([3, 0xfe, 0xff, 1], bytes);
+    assert_eq!(Ok(switch), Switch::try_from(bytes));
+
+    // boolに0と1以外は読めない
+    assert_eq!(Err(InvalidSwitchIdentifier), Switch::try_from([3, 0, 0, 2]));
+}
+
+#[test]
+// 列挙型は先頭にヴァリアントの番号を書き、元に戻せる
+fn test_enum_round_trip() {
+    for switch in [
+        SideSwitch::Left(1, 2),
+        SideSwitch::Right { index: 0x1234 },
+        SideSwitch::Encoder,
+    ] {
+        let bytes: [u8; 3] = switch.into();
+        assert_eq!(Ok(switch), SideSwitch::try_from(bytes));
+    }
+    assert_eq!(
+        [1, 0x34, 0x12],
+        <[u8; 3]>::from(SideSwitch::Right { index: 0x1234 })
+    );
+
+    // 範囲外のヴァリアントの番号や、使わないバイトが0でないものは読めない
+    assert_eq!(
+        Err(InvalidSwitchIdentifier),
+        SideSwitch::try_from([3, 0, 0])
+    );
+    assert_eq!(
+        Err(InvalidSwitchIdentifier),
+        SideSwitch::try_from([2, 1, 0])
+    );
+}
@@ -0,0 +1,127 @@
+use rustkbd::{
+    keyboard::{DynamicKeymap, Key, KeySwitchIdentifier, Layer as _, Layout as _, MatrixPosition},
+    split::SplitKeySwitchIdentifier,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, KeySwitchIdentifier)]
+pub struct Switch(u8, u8);
+
+impl MatrixPosition for Switch {
+    fn position(&self, _rows: usize, _cols: usize) -> (usize, usize) {
+        (self.0 as usize, self.1 as usize)
+    }
+}
+
+mod single {
+    use super::Switch;
+
+    rustkbd::keyboard::keymap! {
+        pub type Layout: 2, Switch;
+        pub enum Layer;
+        hold (1, 0) => Lower;
+        hold (1, 1) => Raise;
+        layer Default r"
+            | A | B |
+            |   |   |
+        ";
+        layer Lower r"
+            | 1 |Trn|
+            |Trn|Trn|
+        ";
+        layer Raise below Default r"
+            |Trn| 2 |
+            |Trn|Trn|
+        ";
+    }
+}
+
+mod split {
+    use super::Switch;
+
+    rustkbd::keyboard::keymap! {
+        pub type Layout: 2, Switch;
+        pub enum Layer;
+        hold Right(1, 0) => Lower;
+        layer Default r"
+            | A | B |
+            | C | D |
+        " r"
+            | E | F |
+            |   | G |
+        ";
+        layer Lower r"
+            | 1 | 2 |
+            | 3 | 4 |
+        " r"
+            | 5 | 6 |
+            |Trn| 7 |
+        ";
+    }
+}
+
+#[test]
+// `hold`のスイッチを押している間はそのレイヤになり、後に書いたレイヤが優先される
+fn test_holds() {
+    use single::{Layer, LAYOUT};
+
+    let layout = LAYOUT;
+    assert_eq!(Layer::Default, layout.layer(&[Switch(0, 0)]));
+    assert_eq!(Layer::Lower, layout.layer(&[Switch(1, 0)]));
+    assert_eq!(Layer::Raise, layout.layer(&[Switch(1, 1)]));
+    assert_eq!(Layer::Raise, layout.layer(&[Switch(1, 0), Switch(1, 1)]));
+
+    assert_eq!(Key::A, layout.key(Layer::Default, &Switch(0, 0)));
+    assert_eq!(
+        Key::Digit1_Exclamation,
+        layout.key(Layer::Lower, &Switch(0, 0))
+    );
+    assert_eq!(Key::Transparent, layout.key(Layer::Lower, &Switch(0, 1)));
+    assert_eq!(Key::Digit2_At, layout.key(Layer::Raise, &Switch(0, 1)));
+}
+
+#[test]
+// `below`を書かないレイヤは1つ前のレイヤに落ちる
+fn test_below() {
+    use single::Layer;
+
+    assert_eq!(None, Layer::Default.below());
+    assert_eq!(Some(Layer::Default), Layer::Lower.below());
+    assert_eq!(Some(Layer::Default), Layer::Raise.below());
+}
+
+#[test]
+// 分割キーボードでは`SplitArrayLayout`になり、`hold Right`は右手側のスイッチだけに効く
+fn test_split_halves() {
+    use split::{Layer, LAYOUT};
+    use SplitKeySwitchIdentifier::{Left, Right};
+
+    let layout = LAYOUT;
+    assert_eq!(Layer::Default, layout.layer(&[Left(Switch(1, 0))]));
+    assert_eq!(Layer::Lower, layout.layer(&[Right(Switch(1, 0))]));
+
+    assert_eq!(Key::B, layout.key(Layer::Default, &Left(Switch(0, 1))));
+    assert_eq!(Key::E, layout.key(Layer::Default, &Right(Switch(0, 0))));
+    assert_eq!(Key::G, layout.key(Layer::Default, &Right(Switch(1, 1))));
+    assert_eq!(
+        Key::Digit3_Number,
+        layout.key(Layer::Lower, &Left(Switch(1, 0)))
+    );
+    assert_eq!(
+        Key::Digit7_Ampersand,
+        layout.key(Layer::Lower, &Right(Switch(1, 1)))
+    );
+
+    // VIAからは左手側の下に右手側を並べた行列に見える
+    assert_eq!(
+        (2, 4, 2),
+        (layout.layer_count(), layout.rows(), layout.cols())
+    );
+    assert_eq!(Some(Key::G), layout.get_key(0, 3, 1));
+}
+
+#[test]
+// 存在しないレイヤや左右の揃わない表はエラー
+fn test_keymap_errors() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/keymap_*.rs");
+}
@@ -0,0 +1,40 @@
+use rustkbd::keyboard::{layout, Key, Layer as _};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, rustkbd::keyboard::Layer)]
+enum Layer {
+    #[default]
+    Default,
+    #[layer(index = 2)]
+    Lower,
+    #[layer(index = 1, below = Default)]
+    Raise,
+}
+
+const KEYMAP: [[Key; 2]; 1] = layout! {r"
+    | LT(Lower, Space) | LT(Layer::Raise, Enter) |
+"};
+
+#[test]
+// `LT`のレイヤは判別値ではなく`index()`になる
+fn test_layer_tap_index() {
+    assert_eq!(Key::LayerTap(2, 0x2c), KEYMAP[0][0]);
+    assert_eq!(Key::LayerTap(1, 0x28), KEYMAP[0][1]);
+    assert_eq!(Some(Layer::Lower), Layer::from_index(2));
+}
+
+#[test]
+// `Key`のデータを持たないヴァリアントは、すべて名前で`layout!`に書ける
+fn test_key_names() {
+    let names = Key::FIELDLESS
+        .iter()
+        .map(|key| format!("{key:?}"))
+        .collect::<Vec<_>>();
+    assert_eq!(rustkbd_keymap::KEY_NAMES, names);
+}
+
+#[test]
+// `layout!`の表の誤りは、行と列とセルの文字列がわかるエラーになる
+fn test_layout_errors() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/layout_*.rs");
+}
@@ -0,0 +1,13 @@
+use rustkbd::keyboard::{keymap, KeySwitchIdentifier};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, KeySwitchIdentifier)]
+struct Switch(u8, u8);
+
+keymap! {
+    pub type Layout: 2, Switch;
+    pub enum Layer;
+    layer Default "|A|B|";
+    layer Lower "|1|2|" "|3|4|";
+}
+
+fn main() {}
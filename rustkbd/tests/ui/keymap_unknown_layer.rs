@@ -0,0 +1,14 @@
+use rustkbd::keyboard::{keymap, KeySwitchIdentifier};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, KeySwitchIdentifier)]
+struct Switch(u8, u8);
+
+keymap! {
+    pub type Layout: 2, Switch;
+    pub enum Layer;
+    hold (0, 0) => Upper;
+    layer Default "|A|B|";
+    layer Adjust below Upper "|Trn|Trn|";
+}
+
+fn main() {}
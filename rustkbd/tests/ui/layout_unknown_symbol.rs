@@ -0,0 +1,13 @@
+use rustkbd::keyboard::{layout, Key};
+
+const LAYOUT: [[Key; 3]; 2] = layout! {
+    rows = 2, cols = 3,
+    r"
+    | Esc | Q   | W       |
+    | Tab | Spce| C(Tba)  |
+    "
+};
+
+fn main() {
+    let _ = LAYOUT;
+}